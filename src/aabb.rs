@@ -0,0 +1,103 @@
+use crate::math::Ray;
+use crate::math::Vec3;
+
+// Axis-aligned bounding box.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(a: Vec3, b: Vec3) -> Aabb {
+        Aabb {
+            min: Vec3::min(&a, &b),
+            max: Vec3::max(&a, &b),
+        }
+    }
+
+    // The empty box; the union with any other box yields that box.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3 {
+                x: f32::INFINITY,
+                y: f32::INFINITY,
+                z: f32::INFINITY,
+            },
+            max: Vec3 {
+                x: f32::NEG_INFINITY,
+                y: f32::NEG_INFINITY,
+                z: f32::NEG_INFINITY,
+            },
+        }
+    }
+
+    pub fn union(a: &Aabb, b: &Aabb) -> Aabb {
+        Aabb {
+            min: Vec3::min(&a.min, &b.min),
+            max: Vec3::max(&a.max, &b.max),
+        }
+    }
+
+    pub fn grow(&mut self, p: &Vec3) {
+        self.min = Vec3::min(&self.min, p);
+        self.max = Vec3::max(&self.max, p);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let e = self.extent();
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
+    // Slab test. Takes the reciprocal ray direction, which callers compute
+    // once per ray instead of once per box.
+    pub fn hit(&self, ray: &Ray, inv_direction: &Vec3, t_min: f32, t_max: f32) -> bool {
+        self.overlap(ray, inv_direction, t_min, t_max).is_some()
+    }
+
+    // The part of [t_min, t_max] during which the ray is inside the box.
+    pub fn overlap(
+        &self,
+        ray: &Ray,
+        inv_direction: &Vec3,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<(f32, f32)> {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            let mut near = (self.min[axis] - ray.origin[axis]) * inv_direction[axis];
+            let mut far = (self.max[axis] - ray.origin[axis]) * inv_direction[axis];
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // NaNs from 0 * inf fall through the comparisons and keep the
+            // previous interval.
+            if near > t0 {
+                t0 = near;
+            }
+            if far < t1 {
+                t1 = far;
+            }
+            if t1 < t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+}
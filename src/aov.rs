@@ -0,0 +1,66 @@
+// Arbitrary output variables: extra per pixel data from the first hit of the
+// camera rays, written as additional EXR channels for compositing.
+
+use crate::exr;
+use crate::image::Image;
+use crate::intersection::HitRecord;
+use crate::intersection::Intersectable;
+use crate::math::Color;
+use crate::math::Ray;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aov {
+    // Distance from the camera, infinite for rays that miss everything.
+    Depth,
+    // World space shading normal, zero for rays that miss everything.
+    Normal,
+}
+
+impl Aov {
+    pub fn from_name(name: &str) -> Option<Aov> {
+        match name {
+            "depth" => Some(Aov::Depth),
+            "normal" => Some(Aov::Normal),
+            _ => None,
+        }
+    }
+
+    // The value for a single camera ray, stored in a color.
+    pub fn sample<I: Intersectable>(self, ray: &Ray, world: &I) -> Color {
+        let mut hit_record = HitRecord::new();
+        let hit = world.intersect_surface(ray, 0.0001, 10000.0, &mut hit_record);
+        match self {
+            Aov::Depth => {
+                let depth = if hit {
+                    hit_record.t * ray.direction.mag()
+                } else {
+                    f32::INFINITY
+                };
+                Color {
+                    r: depth,
+                    g: depth,
+                    b: depth,
+                }
+            }
+            Aov::Normal => {
+                if !hit {
+                    return Color::black();
+                }
+                Color {
+                    r: hit_record.normal.x,
+                    g: hit_record.normal.y,
+                    b: hit_record.normal.z,
+                }
+            }
+        }
+    }
+
+    // Channels named following the OpenEXR conventions.
+    pub fn channels(self, image: &Image) -> Vec<exr::Channel> {
+        match self {
+            // All components hold the depth.
+            Aov::Depth => vec![image.channel("Z", |c| c.r)],
+            Aov::Normal => image.channels(&["N.X", "N.Y", "N.Z"]),
+        }
+    }
+}
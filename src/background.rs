@@ -0,0 +1,80 @@
+use std::f32::consts::PI;
+
+use crate::image::Image;
+use crate::image::Wrap;
+use crate::math::Color;
+use crate::math::Vec3;
+
+// Radiance arriving from infinitely far away, seen by rays that miss
+// everything in the scene.
+pub trait Background: Send + Sync {
+    fn color(&self, direction: &Vec3) -> Color;
+}
+
+pub struct Solid {
+    pub color: Color,
+}
+
+// Blends from `bottom` (looking straight down) to `top` (straight up).
+pub struct Gradient {
+    pub bottom: Color,
+    pub top: Color,
+}
+
+// Equirectangular (latitude/longitude) environment map.
+pub struct EnvironmentMap {
+    image: Image,
+    // Rotation around the up axis, in radians.
+    rotation: f32,
+    intensity: f32,
+}
+
+impl Default for Gradient {
+    // The white to light blue sky from the book.
+    fn default() -> Gradient {
+        Gradient {
+            bottom: Color::white(),
+            top: Color {
+                r: 0.5,
+                g: 0.7,
+                b: 1.0,
+            },
+        }
+    }
+}
+
+impl Background for Solid {
+    fn color(&self, _direction: &Vec3) -> Color {
+        self.color
+    }
+}
+
+impl Background for Gradient {
+    fn color(&self, direction: &Vec3) -> Color {
+        let unit_direction = direction.normalized();
+        let t = 0.5 * (unit_direction.y + 1.0);
+        Color::lerp(&self.bottom, &self.top, t)
+    }
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Image, rotation: f32, intensity: f32) -> EnvironmentMap {
+        EnvironmentMap {
+            image,
+            rotation,
+            intensity,
+        }
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn color(&self, direction: &Vec3) -> Color {
+        let d = direction.normalized();
+        let phi = d.z.atan2(d.x) + self.rotation;
+        let theta = d.y.clamp(-1.0, 1.0).acos();
+
+        let u = (phi / (2.0 * PI)).rem_euclid(1.0);
+        let v = theta / PI;
+        self.image.sample_bilinear(u, v, Wrap::Repeat, Wrap::Clamp) * self.intensity
+    }
+}
@@ -0,0 +1,225 @@
+// Renders the "random spheres" scene from the cover of Ray Tracing in One
+// Weekend with a plain IntersectableList, a Bvh and a Bvh of trait objects,
+// and reports how long each took.
+
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use crate::background::Gradient;
+use crate::bvh::Bvh;
+use crate::camera::Camera;
+use crate::camera::PerspectiveCamera;
+use crate::cli::Options;
+use crate::image::Encoding;
+use crate::image::Image;
+use crate::intersection::Intersectable;
+use crate::intersection::IntersectableList;
+use crate::intersection::Sphere;
+use crate::light::LightList;
+use crate::material;
+use crate::material::Material;
+use crate::math::Color;
+use crate::math::Vec3;
+use crate::scene::RenderSettings;
+use crate::texture;
+
+fn random_color(rng: &mut StdRng, min: f32, max: f32) -> Color {
+    Color {
+        r: rng.gen_range(min..max),
+        g: rng.gen_range(min..max),
+        b: rng.gen_range(min..max),
+    }
+}
+
+// Always generates the same scene so that timings are comparable.
+pub fn random_spheres() -> Vec<Sphere> {
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut spheres = Vec::new();
+
+    spheres.push(Sphere::new(
+        Vec3 {
+            x: 0.0,
+            y: -1000.0,
+            z: 0.0,
+        },
+        1000.0,
+        Arc::new(material::Lambertian {
+            albedo: texture::solid(Color {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+            }),
+        }),
+    ));
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let center = Vec3 {
+                x: a as f32 + 0.9 * rng.gen_range(0.0..1.0),
+                y: 0.2,
+                z: b as f32 + 0.9 * rng.gen_range(0.0..1.0),
+            };
+            let choose_material: f32 = rng.gen_range(0.0..1.0);
+            let material: Arc<dyn Material> = if choose_material < 0.8 {
+                Arc::new(material::Lambertian {
+                    albedo: texture::solid(
+                        random_color(&mut rng, 0.0, 1.0) * random_color(&mut rng, 0.0, 1.0),
+                    ),
+                })
+            } else if choose_material < 0.95 {
+                Arc::new(material::Metal {
+                    albedo: texture::solid(random_color(&mut rng, 0.5, 1.0)),
+                    roughness: texture::constant(rng.gen_range(0.0..0.5)),
+                })
+            } else {
+                Arc::new(material::Dielectric {
+                    index_of_refraction: 1.5,
+                    roughness: texture::constant(0.0),
+                    absorption: Color::black(),
+                    dispersion: None,
+                })
+            };
+            spheres.push(Sphere::new(center, 0.2, material));
+        }
+    }
+
+    spheres.push(Sphere::new(
+        Vec3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        },
+        1.0,
+        Arc::new(material::Dielectric {
+            index_of_refraction: 1.5,
+            roughness: texture::constant(0.0),
+            absorption: Color::black(),
+            dispersion: None,
+        }),
+    ));
+    spheres.push(Sphere::new(
+        Vec3 {
+            x: -4.0,
+            y: 1.0,
+            z: 0.0,
+        },
+        1.0,
+        Arc::new(material::Lambertian {
+            albedo: texture::solid(Color {
+                r: 0.4,
+                g: 0.2,
+                b: 0.1,
+            }),
+        }),
+    ));
+    spheres.push(Sphere::new(
+        Vec3 {
+            x: 4.0,
+            y: 1.0,
+            z: 0.0,
+        },
+        1.0,
+        Arc::new(material::Metal {
+            albedo: texture::solid(Color {
+                r: 0.7,
+                g: 0.6,
+                b: 0.5,
+            }),
+            roughness: texture::constant(0.0),
+        }),
+    ));
+
+    spheres
+}
+
+fn time_render<I: Intersectable>(
+    world: &I,
+    camera: &dyn Camera,
+    width: usize,
+    height: usize,
+    render_settings: &RenderSettings,
+) -> Duration {
+    let mut image = Image::new(width, height);
+    let start = Instant::now();
+    crate::trace(
+        &mut image,
+        camera,
+        world,
+        &LightList::new(Vec::new()),
+        &Gradient::default(),
+        render_settings,
+    );
+    start.elapsed()
+}
+
+pub fn run(options: &Options) {
+    let width = options.image_width.unwrap_or(400);
+    let height = options.image_height.unwrap_or(225);
+    let render_settings = RenderSettings {
+        samples_per_pixel: options.samples_per_pixel.unwrap_or(8),
+        max_recursion_depth: options.max_recursion_depth.unwrap_or(12),
+        image_encoding: Encoding::Srgb,
+        render_threads: options.render_threads.unwrap_or(16),
+        ..RenderSettings::default()
+    };
+
+    let camera = PerspectiveCamera::new(
+        Vec3 {
+            x: 13.0,
+            y: 2.0,
+            z: 3.0,
+        },
+        Vec3::zero(),
+        Vec3::up(),
+        20.0,
+        width as f32 / height as f32,
+    );
+
+    let mut list = IntersectableList::new();
+    for sphere in random_spheres() {
+        list.add(sphere);
+    }
+    println!(
+        "Rendering {} spheres at {}x{} with {} samples per pixel.",
+        list.len(),
+        width,
+        height,
+        render_settings.samples_per_pixel
+    );
+
+    let list_time = time_render(&list, &camera, width, height, &render_settings);
+    println!("\nIntersectableList: {:.3}s", list_time.as_secs_f32());
+
+    let build_start = Instant::now();
+    let bvh = Bvh::new(random_spheres());
+    let build_time = build_start.elapsed();
+    let bvh_time = time_render(&bvh, &camera, width, height, &render_settings);
+    println!(
+        "\nBvh: {:.3}s (+{:.3}s build)",
+        bvh_time.as_secs_f32(),
+        build_time.as_secs_f32()
+    );
+
+    println!(
+        "Speedup: {:.2}x",
+        list_time.as_secs_f32() / (bvh_time + build_time).as_secs_f32()
+    );
+
+    // Same hierarchy over trait objects, as used by scenes that mix shapes.
+    let boxed: Vec<Box<dyn Intersectable>> = random_spheres()
+        .into_iter()
+        .map(|s| Box::new(s) as Box<dyn Intersectable>)
+        .collect();
+    let boxed_bvh = Bvh::new(boxed);
+    let boxed_time = time_render(&boxed_bvh, &camera, width, height, &render_settings);
+    println!(
+        "\nBvh<Box<dyn Intersectable>>: {:.3}s ({:+.1}% compared to Bvh<Sphere>)",
+        boxed_time.as_secs_f32(),
+        (boxed_time.as_secs_f32() / bvh_time.as_secs_f32() - 1.0) * 100.0
+    );
+}
@@ -0,0 +1,443 @@
+use crate::aabb::Aabb;
+use crate::intersection::HitRecord;
+use crate::intersection::Intersectable;
+use crate::light::Light;
+use crate::math::Ray;
+use crate::math::Vec3;
+
+// Bounding volume hierarchy over a set of objects, built with the surface
+// area heuristic. The tree is stored flattened in depth-first order.
+pub struct Bvh<I: Intersectable> {
+    objects: Vec<I>,
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    bounds: Aabb,
+    // Leaves: index of the first object. Interior nodes: index of the second
+    // child; the first child always directly follows its parent.
+    offset: usize,
+    // Number of objects in a leaf, zero for interior nodes.
+    count: usize,
+    // Split axis of interior nodes, used to visit the nearer child first.
+    axis: usize,
+}
+
+// Per object data needed during construction only.
+struct BuildObject {
+    index: usize,
+    bounds: Aabb,
+    centroid: Vec3,
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bounds: Aabb,
+    count: usize,
+}
+
+const BIN_COUNT: usize = 16;
+const MAX_LEAF_SIZE: usize = 4;
+// Also bounds the traversal stack.
+const MAX_DEPTH: usize = 64;
+// Cost of traversing a node relative to intersecting an object.
+const TRAVERSAL_COST: f32 = 1.0;
+
+impl<I: Intersectable> Bvh<I> {
+    pub fn new(objects: Vec<I>) -> Bvh<I> {
+        let mut build_objects: Vec<BuildObject> = objects
+            .iter()
+            .enumerate()
+            .map(|(index, object)| {
+                let bounds = object.bounding_box();
+                BuildObject {
+                    index,
+                    bounds,
+                    centroid: bounds.center(),
+                }
+            })
+            .collect();
+
+        let mut nodes = Vec::new();
+        if !build_objects.is_empty() {
+            build_recursive(&mut build_objects, 0, 0, &mut nodes);
+        }
+
+        // Reorder the objects so that every leaf references a contiguous range.
+        let mut slots: Vec<Option<I>> = objects.into_iter().map(Some).collect();
+        let objects = build_objects
+            .iter()
+            .map(|b| slots[b.index].take().unwrap())
+            .collect();
+
+        Bvh { objects, nodes }
+    }
+
+    // The closest hit, either of anything or only of surfaces.
+    fn closest_hit<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+        surfaces_only: bool,
+    ) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let inv_direction = Vec3 {
+            x: 1.0 / ray.direction.x,
+            y: 1.0 / ray.direction.y,
+            z: 1.0 / ray.direction.z,
+        };
+        let direction_negative = [
+            inv_direction.x < 0.0,
+            inv_direction.y < 0.0,
+            inv_direction.z < 0.0,
+        ];
+
+        let mut any_hit = false;
+        let mut closest = t_max;
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_size = 0;
+        let mut index = 0;
+
+        loop {
+            let node = &self.nodes[index];
+            if node.bounds.hit(ray, &inv_direction, t_min, closest) {
+                if node.count > 0 {
+                    for object in &self.objects[node.offset..node.offset + node.count] {
+                        let found = if surfaces_only {
+                            object.intersect_surface(ray, t_min, closest, hit)
+                        } else {
+                            object.intersect(ray, t_min, closest, hit)
+                        };
+                        if found {
+                            any_hit = true;
+                            closest = hit.t;
+                        }
+                    }
+                } else {
+                    // Visit the child closer to the ray origin first.
+                    if direction_negative[node.axis] {
+                        stack[stack_size] = index + 1;
+                        index = node.offset;
+                    } else {
+                        stack[stack_size] = node.offset;
+                        index += 1;
+                    }
+                    stack_size += 1;
+                    continue;
+                }
+            }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            index = stack[stack_size];
+        }
+
+        any_hit
+    }
+}
+
+fn leaf(nodes: &mut Vec<Node>, bounds: Aabb, first: usize, count: usize) {
+    nodes.push(Node {
+        bounds,
+        offset: first,
+        count,
+        axis: 0,
+    });
+}
+
+// Builds the subtree for `objects`, which start at index `first` of the final
+// object order, and appends its nodes.
+fn build_recursive(objects: &mut [BuildObject], first: usize, depth: usize, nodes: &mut Vec<Node>) {
+    let bounds = objects
+        .iter()
+        .fold(Aabb::empty(), |b, o| Aabb::union(&b, &o.bounds));
+    let count = objects.len();
+
+    if count <= 1 || depth >= MAX_DEPTH - 1 {
+        leaf(nodes, bounds, first, count);
+        return;
+    }
+
+    let centroid_bounds = objects.iter().fold(Aabb::empty(), |mut b, o| {
+        b.grow(&o.centroid);
+        b
+    });
+
+    // Find the cheapest split among the bin boundaries of all three axes.
+    let mut best: Option<(f32, usize, usize)> = None;
+    for axis in 0..3 {
+        let lo = centroid_bounds.min[axis];
+        let extent = centroid_bounds.max[axis] - lo;
+        if extent <= 0.0 {
+            continue;
+        }
+        let bin_of = |o: &BuildObject| {
+            let b = ((o.centroid[axis] - lo) / extent * BIN_COUNT as f32) as usize;
+            b.min(BIN_COUNT - 1)
+        };
+
+        let mut bins = [Bin {
+            bounds: Aabb::empty(),
+            count: 0,
+        }; BIN_COUNT];
+        for o in objects.iter() {
+            let bin = &mut bins[bin_of(o)];
+            bin.bounds = Aabb::union(&bin.bounds, &o.bounds);
+            bin.count += 1;
+        }
+
+        // Sweep from the right to get the cost of all right hand sides, then
+        // from the left to combine them.
+        let mut right_area = [0.0; BIN_COUNT];
+        let mut right_count = [0; BIN_COUNT];
+        let mut acc = Aabb::empty();
+        let mut acc_count = 0;
+        for i in (1..BIN_COUNT).rev() {
+            acc = Aabb::union(&acc, &bins[i].bounds);
+            acc_count += bins[i].count;
+            right_area[i] = acc.surface_area();
+            right_count[i] = acc_count;
+        }
+
+        let mut acc = Aabb::empty();
+        let mut acc_count = 0;
+        for split in 1..BIN_COUNT {
+            acc = Aabb::union(&acc, &bins[split - 1].bounds);
+            acc_count += bins[split - 1].count;
+            if acc_count == 0 || right_count[split] == 0 {
+                continue;
+            }
+            let cost = acc.surface_area() * acc_count as f32
+                + right_area[split] * right_count[split] as f32;
+            if best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+
+    let leaf_cost = count as f32;
+    let (cost, axis, split) = match best {
+        Some((cost, axis, split)) => (
+            TRAVERSAL_COST + cost / bounds.surface_area().max(f32::MIN_POSITIVE),
+            axis,
+            split,
+        ),
+        // All centroids coincide, there is nothing to split.
+        None => {
+            leaf(nodes, bounds, first, count);
+            return;
+        }
+    };
+    if count <= MAX_LEAF_SIZE && leaf_cost <= cost {
+        leaf(nodes, bounds, first, count);
+        return;
+    }
+
+    // Partition the objects at the chosen bin boundary.
+    let lo = centroid_bounds.min[axis];
+    let extent = centroid_bounds.max[axis] - lo;
+    let mut mid = 0;
+    for i in 0..count {
+        let b = ((objects[i].centroid[axis] - lo) / extent * BIN_COUNT as f32) as usize;
+        if b.min(BIN_COUNT - 1) < split {
+            objects.swap(i, mid);
+            mid += 1;
+        }
+    }
+
+    let index = nodes.len();
+    nodes.push(Node {
+        bounds,
+        offset: 0,
+        count: 0,
+        axis,
+    });
+    let (left, right) = objects.split_at_mut(mid);
+    build_recursive(left, first, depth + 1, nodes);
+    nodes[index].offset = nodes.len();
+    build_recursive(right, first + mid, depth + 1, nodes);
+}
+
+impl<I: Intersectable> Intersectable for Bvh<I> {
+    fn intersect<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord<'a>) -> bool {
+        self.closest_hit(ray, t_min, t_max, hit, false)
+    }
+
+    fn intersect_surface<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> bool {
+        self.closest_hit(ray, t_min, t_max, hit, true)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self.nodes.first() {
+            Some(node) => node.bounds,
+            None => Aabb::empty(),
+        }
+    }
+
+    fn lights(&self, lights: &mut Vec<Box<dyn Light>>) {
+        for object in self.objects.iter() {
+            object.lights(lights);
+        }
+    }
+
+    // Visits every object along the whole segment, not just the closest.
+    fn transmittance<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> f32 {
+        if self.nodes.is_empty() {
+            return 1.0;
+        }
+
+        let inv_direction = Vec3 {
+            x: 1.0 / ray.direction.x,
+            y: 1.0 / ray.direction.y,
+            z: 1.0 / ray.direction.z,
+        };
+
+        let mut transmittance = 1.0;
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_size = 0;
+        let mut index = 0;
+
+        loop {
+            let node = &self.nodes[index];
+            if node.bounds.hit(ray, &inv_direction, t_min, t_max) {
+                if node.count > 0 {
+                    for object in &self.objects[node.offset..node.offset + node.count] {
+                        transmittance *= object.transmittance(ray, t_min, t_max, hit);
+                        if transmittance <= 0.0 {
+                            return 0.0;
+                        }
+                    }
+                } else {
+                    stack[stack_size] = node.offset;
+                    stack_size += 1;
+                    index += 1;
+                    continue;
+                }
+            }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            index = stack[stack_size];
+        }
+
+        transmittance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intersection::IntersectableList;
+    use crate::intersection::Sphere;
+    use crate::material::Constant;
+    use crate::material::Material;
+    use crate::math::Color;
+    use rand::rngs::StdRng;
+    use rand::Rng;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    fn random_point(rng: &mut StdRng, size: f32) -> Vec3 {
+        Vec3 {
+            x: rng.gen_range(-size..size),
+            y: rng.gen_range(-size..size),
+            z: rng.gen_range(-size..size),
+        }
+    }
+
+    // Every ray hits the same as with a plain list of the same spheres.
+    fn compare_with_list(spheres: Vec<Sphere>, rng: &mut StdRng) {
+        let mut list = IntersectableList::new();
+        for sphere in spheres.iter() {
+            list.add(sphere.clone());
+        }
+        let bvh = Bvh::new(spheres);
+
+        for _ in 0..2000 {
+            let ray = Ray {
+                origin: random_point(rng, 12.0),
+                direction: random_point(rng, 1.0),
+                time: 0.0,
+                wavelength: None,
+            };
+            let mut expected = HitRecord::new();
+            let mut actual = HitRecord::new();
+            let list_hit = list.intersect(&ray, 0.001, 100.0, &mut expected);
+            assert_eq!(bvh.intersect(&ray, 0.001, 100.0, &mut actual), list_hit);
+            if list_hit {
+                assert_eq!(actual.t, expected.t);
+            }
+            let transmittance = bvh.transmittance(&ray, 0.001, 100.0, &mut actual);
+            assert_eq!(transmittance, if list_hit { 0.0 } else { 1.0 });
+        }
+    }
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Constant {
+            color: Color::black(),
+        })
+    }
+
+    #[test]
+    fn same_hits_as_list() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let spheres = (0..500)
+            .map(|_| {
+                Sphere::new(
+                    random_point(&mut rng, 10.0),
+                    rng.gen_range(0.05..0.8),
+                    material(),
+                )
+            })
+            .collect();
+        compare_with_list(spheres, &mut rng);
+    }
+
+    #[test]
+    fn coincident_centroids() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let spheres = (0..200)
+            .map(|i| Sphere::new(Vec3::zero(), 0.5 + i as f32 * 0.01, material()))
+            .collect();
+        compare_with_list(spheres, &mut rng);
+    }
+
+    #[test]
+    fn empty() {
+        let bvh: Bvh<Sphere> = Bvh::new(Vec::new());
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3 {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            time: 0.0,
+            wavelength: None,
+        };
+        let mut hit = HitRecord::new();
+        assert!(!bvh.intersect(&ray, 0.0, f32::INFINITY, &mut hit));
+        assert_eq!(bvh.transmittance(&ray, 0.0, f32::INFINITY, &mut hit), 1.0);
+    }
+}
@@ -1,42 +1,335 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+
 use crate::math::Ray;
 use crate::math::Vec3;
 
-pub struct Camera {
+// Maps image coordinates to primary rays. (0, 0) is the bottom left corner of
+// the image and (1, 1) the top right one. Returns None for points that the
+// projection doesn't cover, e.g. outside the image circle of a fisheye.
+//
+// Rays are emitted at time 0, see Shutter for motion blur.
+pub trait Camera: Send + Sync {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray>;
+}
+
+pub struct PerspectiveCamera {
     origin: Vec3,
     horizontal: Vec3,
     vertical: Vec3,
     lower_left_corner: Vec3,
+    // Unit vectors spanning the lens plane.
+    u: Vec3,
+    v: Vec3,
+    lens_radius: f32,
+    // Distance of the plane in perfect focus, measured along the view axis.
+    focus_distance: f32,
+}
+
+// Parallel rays from a rectangle `view_height` units high.
+pub struct OrthographicCamera {
+    lower_left_corner: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FisheyeMapping {
+    // The distance from the image center is proportional to the angle.
+    Equidistant,
+    // Preserves solid angles, like most real fisheye lenses.
+    Equisolid,
+}
+
+// A circular fisheye whose image circle fills the shorter image side.
+pub struct FisheyeCamera {
+    origin: Vec3,
+    frame: Frame,
+    aspect_ratio: f32,
+    // Half of the field of view, in radians.
+    max_angle: f32,
+    mapping: FisheyeMapping,
+}
+
+// Full 360 by 180 degree latitude/longitude panorama, centered on the view
+// direction.
+pub struct EquirectangularCamera {
+    origin: Vec3,
+    frame: Frame,
+}
+
+// All six faces of a cube map side by side, in the order +X, -X, +Y, -Y, +Z
+// and -Z of the camera frame (x right, y up, looking down -z). Each face is
+// one sixth of the image wide, so the image should have an aspect ratio of 6.
+pub struct CubeMapCamera {
+    origin: Vec3,
+    frame: Frame,
+}
+
+// Wraps another camera and spreads its rays uniformly over the time the
+// shutter is open. Frame times run from 0 to 1 for moving objects.
+pub struct Shutter<C: Camera> {
+    camera: C,
+    open: f32,
+    close: f32,
+}
+
+// Orthonormal camera frame: `u` points right, `v` up and `w` backwards.
+struct Frame {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    fn new(position: Vec3, target: Vec3, up: Vec3) -> Frame {
+        let w = (position - target).normalized();
+        let u = (Vec3::cross(&up, &w)).normalized();
+        let v = Vec3::cross(&w, &u);
+        Frame { u, v, w }
+    }
+
+    fn to_world(&self, x: f32, y: f32, z: f32) -> Vec3 {
+        self.u * x + self.v * y + self.w * z
+    }
 }
 
-impl Camera {
-    pub fn new(position: Vec3, target: Vec3, up: Vec3, fovy: f32, aspect_ratio: f32) -> Camera {
+impl PerspectiveCamera {
+    pub fn new(
+        position: Vec3,
+        target: Vec3,
+        up: Vec3,
+        fovy: f32,
+        aspect_ratio: f32,
+    ) -> PerspectiveCamera {
         let theta = fovy.to_radians();
         let h = (theta / 2.0).tan();
 
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
 
-        let w = (position - target).normalized();
-        let u = (Vec3::cross(&up, &w)).normalized();
-        let v = Vec3::cross(&w, &u);
+        let Frame { u, v, w } = Frame::new(position, target, up);
 
         let origin = position;
         let horizontal = u * viewport_width;
         let vertical = v * viewport_height;
 
-        Camera {
-            origin: origin,
-            horizontal: horizontal,
-            vertical: vertical,
+        PerspectiveCamera {
+            origin,
+            horizontal,
+            vertical,
             lower_left_corner: origin - horizontal / 2.0 - vertical / 2.0 - w,
+            u,
+            v,
+            lens_radius: 0.0,
+            focus_distance: 1.0,
         }
     }
 
-    pub fn get_ray(&self, u: f32, v: f32) -> Ray {
-        Ray {
+    // Turns the pinhole into a thin lens with the given aperture diameter.
+    // Everything off the focus plane gets blurred.
+    pub fn with_lens(mut self, aperture: f32, focus_distance: f32) -> PerspectiveCamera {
+        self.lens_radius = aperture / 2.0;
+        self.focus_distance = focus_distance;
+        self
+    }
+}
+
+impl Camera for PerspectiveCamera {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        // Points one unit along the view axis, so scaling the direction by the
+        // focus distance lands on the focus plane.
+        let direction =
+            self.lower_left_corner + self.horizontal * u + self.vertical * v - self.origin;
+        if self.lens_radius <= 0.0 {
+            return Some(Ray {
+                origin: self.origin,
+                direction,
+                time: 0.0,
+                wavelength: None,
+            });
+        }
+
+        let rd = Vec3::random_in_unit_disk() * self.lens_radius;
+        let origin = self.origin + self.u * rd.x + self.v * rd.y;
+        Some(Ray {
+            origin,
+            direction: self.origin + direction * self.focus_distance - origin,
+            time: 0.0,
+            wavelength: None,
+        })
+    }
+}
+
+impl OrthographicCamera {
+    pub fn new(
+        position: Vec3,
+        target: Vec3,
+        up: Vec3,
+        view_height: f32,
+        aspect_ratio: f32,
+    ) -> OrthographicCamera {
+        let frame = Frame::new(position, target, up);
+        let horizontal = frame.u * (view_height * aspect_ratio);
+        let vertical = frame.v * view_height;
+        OrthographicCamera {
+            lower_left_corner: position - horizontal / 2.0 - vertical / 2.0,
+            horizontal,
+            vertical,
+            direction: frame.w * -1.0,
+        }
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        Some(Ray {
+            origin: self.lower_left_corner + self.horizontal * u + self.vertical * v,
+            direction: self.direction,
+            time: 0.0,
+            wavelength: None,
+        })
+    }
+}
+
+impl FisheyeCamera {
+    pub fn new(
+        position: Vec3,
+        target: Vec3,
+        up: Vec3,
+        fov: f32,
+        mapping: FisheyeMapping,
+        aspect_ratio: f32,
+    ) -> FisheyeCamera {
+        FisheyeCamera {
+            origin: position,
+            frame: Frame::new(position, target, up),
+            aspect_ratio,
+            max_angle: fov.to_radians() / 2.0,
+            mapping,
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        // Coordinates relative to the image circle.
+        let (mut x, mut y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+        if self.aspect_ratio > 1.0 {
+            x *= self.aspect_ratio;
+        } else {
+            y /= self.aspect_ratio;
+        }
+        let r = (x * x + y * y).sqrt();
+        if r > 1.0 {
+            return None;
+        }
+
+        let theta = match self.mapping {
+            FisheyeMapping::Equidistant => r * self.max_angle,
+            FisheyeMapping::Equisolid => 2.0 * (r * (self.max_angle / 2.0).sin()).asin(),
+        };
+        let phi = y.atan2(x);
+        let direction = self.frame.to_world(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            -theta.cos(),
+        );
+        Some(Ray {
             origin: self.origin,
-            direction: self.lower_left_corner + self.horizontal * u + self.vertical * v
-                - self.origin,
+            direction,
+            time: 0.0,
+            wavelength: None,
+        })
+    }
+}
+
+impl EquirectangularCamera {
+    pub fn new(position: Vec3, target: Vec3, up: Vec3) -> EquirectangularCamera {
+        EquirectangularCamera {
+            origin: position,
+            frame: Frame::new(position, target, up),
         }
     }
 }
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        let longitude = (u - 0.5) * 2.0 * PI;
+        let latitude = (v - 0.5) * PI;
+        let direction = self.frame.to_world(
+            latitude.cos() * longitude.sin(),
+            latitude.sin(),
+            -latitude.cos() * longitude.cos(),
+        );
+        Some(Ray {
+            origin: self.origin,
+            direction,
+            time: 0.0,
+            wavelength: None,
+        })
+    }
+}
+
+impl CubeMapCamera {
+    pub fn new(position: Vec3, target: Vec3, up: Vec3) -> CubeMapCamera {
+        CubeMapCamera {
+            origin: position,
+            frame: Frame::new(position, target, up),
+        }
+    }
+}
+
+impl Camera for CubeMapCamera {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        let face = ((u * 6.0) as usize).min(5);
+        // Face coordinates as in the OpenGL cube map specification, with t
+        // pointing down.
+        let s = 2.0 * (u * 6.0 - face as f32) - 1.0;
+        let t = 1.0 - 2.0 * v;
+        let (x, y, z) = match face {
+            0 => (1.0, -t, -s),
+            1 => (-1.0, -t, s),
+            2 => (s, 1.0, t),
+            3 => (s, -1.0, -t),
+            4 => (s, -t, 1.0),
+            _ => (-s, -t, -1.0),
+        };
+        Some(Ray {
+            origin: self.origin,
+            direction: self.frame.to_world(x, y, z),
+            time: 0.0,
+            wavelength: None,
+        })
+    }
+}
+
+impl<C: Camera> Shutter<C> {
+    pub fn new(camera: C, open: f32, close: f32) -> Shutter<C> {
+        Shutter {
+            camera,
+            open,
+            close,
+        }
+    }
+}
+
+impl<C: Camera> Camera for Shutter<C> {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        let mut ray = self.camera.get_ray(u, v)?;
+        if self.close > self.open {
+            ray.time = rand::thread_rng().gen_range(self.open..self.close);
+        } else {
+            ray.time = self.open;
+        }
+        Some(ray)
+    }
+}
+
+impl<C: Camera + ?Sized> Camera for Box<C> {
+    fn get_ray(&self, u: f32, v: f32) -> Option<Ray> {
+        (**self).get_ray(u, v)
+    }
+}
@@ -0,0 +1,344 @@
+// Command line parsing. Every option overrides the corresponding value from
+// the scene file.
+
+use std::str::FromStr;
+
+use crate::aov::Aov;
+use crate::image::Encoding;
+use crate::image::ImageFormat;
+use crate::image::MAX_PIXELS;
+use crate::scene::Focus;
+use crate::scene::Projection;
+use crate::scene::Scene;
+use crate::tonemap::ToneMapping;
+
+pub const USAGE: &str = "\
+Usage: rust_tracer [OPTIONS] [SCENE]
+
+Renders SCENE (default: scenes/default.toml) and writes the result to disk.
+
+Options:
+  -o, --output <FILE>      Output image file [default: output.png]
+  -f, --format <FORMAT>    Output format, guessed from the file extension if
+                           omitted. One of: ppm, png (8 bit), png16, pfm,
+                           hdr, exr (half float), exr32 (float)
+  -W, --width <PIXELS>     Image width
+  -H, --height <PIXELS>    Image height
+  -s, --samples <N>        Samples per pixel
+  -d, --max-depth <N>      Maximum ray recursion depth
+  -g, --gamma <GAMMA>      Gamma used to encode the image, or `srgb` for the
+                           sRGB transfer function. Not applied to the linear
+                           pfm, hdr and exr formats
+  -a, --aperture <SIZE>    Lens diameter, 0 for a pinhole camera
+      --focus-distance <D> Distance of the plane in focus
+      --autofocus          Focus on whatever the center of the image shows
+  -e, --exposure <EV>      Exposure adjustment in stops
+  -m, --tone-mapping <OP>  Tone mapping operator. One of: none, reinhard,
+                           reinhard_extended, aces, hable, agx
+  -t, --threads <N>        Number of render threads
+      --spectral           Trace each path at a few wavelengths instead of
+                           in RGB
+  -c, --channels <LIST>    Extra EXR channels, comma separated. Any of:
+                           depth, normal
+      --benchmark          Render the random spheres scene with and without
+                           acceleration structure and report the timings
+  -h, --help               Print this help and exit
+";
+
+pub enum Command {
+    Render(Options),
+    Benchmark(Options),
+    Help,
+}
+
+pub struct Options {
+    pub scene_path: String,
+    pub output_path: String,
+    pub output_format: Option<ImageFormat>,
+    pub image_width: Option<usize>,
+    pub image_height: Option<usize>,
+    pub samples_per_pixel: Option<u32>,
+    pub max_recursion_depth: Option<u32>,
+    pub image_encoding: Option<Encoding>,
+    pub aperture: Option<f32>,
+    pub focus: Option<Focus>,
+    pub exposure: Option<f32>,
+    pub tone_mapping: Option<ToneMapping>,
+    pub render_threads: Option<u32>,
+    pub spectral: bool,
+    pub aovs: Vec<Aov>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            scene_path: "scenes/default.toml".to_string(),
+            output_path: "output.png".to_string(),
+            output_format: None,
+            image_width: None,
+            image_height: None,
+            samples_per_pixel: None,
+            max_recursion_depth: None,
+            image_encoding: None,
+            aperture: None,
+            focus: None,
+            exposure: None,
+            tone_mapping: None,
+            render_threads: None,
+            spectral: false,
+            aovs: vec![],
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value `{}` for `{}`", value, option))
+}
+
+fn parse_positive<T: FromStr + Default + PartialEq>(
+    option: &str,
+    value: &str,
+) -> Result<T, String> {
+    let v = parse_value::<T>(option, value)?;
+    if v == T::default() {
+        return Err(format!("`{}` must be greater than zero", option));
+    }
+    Ok(v)
+}
+
+fn parse_dimension(option: &str, value: &str) -> Result<usize, String> {
+    let v = parse_positive(option, value)?;
+    if v > MAX_PIXELS {
+        return Err(format!("`{}` is too large", option));
+    }
+    Ok(v)
+}
+
+pub fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut scene_path = None;
+    let mut benchmark = false;
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            if scene_path.is_some() {
+                return Err(format!("unexpected argument `{}`", arg));
+            }
+            scene_path = Some(arg);
+            continue;
+        }
+
+        // Accept both `--option value` and `--option=value`.
+        let (option, inline_value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => {
+                (arg[..i].to_string(), Some(arg[i + 1..].to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+
+        if option == "-h" || option == "--help" {
+            return Ok(Command::Help);
+        }
+        if option == "--benchmark" {
+            benchmark = true;
+            continue;
+        }
+        if option == "--autofocus" {
+            options.focus = Some(Focus::Auto);
+            continue;
+        }
+        if option == "--spectral" {
+            options.spectral = true;
+            continue;
+        }
+
+        let value = match inline_value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(format!("missing value for `{}`", option)),
+        };
+
+        match option.as_str() {
+            "-o" | "--output" => options.output_path = value,
+            "-f" | "--format" => {
+                options.output_format = Some(
+                    ImageFormat::from_name(&value)
+                        .ok_or_else(|| format!("unknown output format `{}`", value))?,
+                )
+            }
+            "-W" | "--width" => options.image_width = Some(parse_dimension(&option, &value)?),
+            "-H" | "--height" => options.image_height = Some(parse_dimension(&option, &value)?),
+            "-s" | "--samples" => {
+                options.samples_per_pixel = Some(parse_positive(&option, &value)?)
+            }
+            "-d" | "--max-depth" => {
+                options.max_recursion_depth = Some(parse_positive(&option, &value)?)
+            }
+            "-g" | "--gamma" => {
+                if value == "srgb" {
+                    options.image_encoding = Some(Encoding::Srgb);
+                } else {
+                    let gamma: f32 = parse_value(&option, &value)?;
+                    if !(gamma.is_finite() && gamma > 0.0) {
+                        return Err(format!("`{}` must be a positive number or `srgb`", option));
+                    }
+                    options.image_encoding = Some(Encoding::Gamma(gamma));
+                }
+            }
+            "-a" | "--aperture" => {
+                let aperture: f32 = parse_value(&option, &value)?;
+                if !(aperture.is_finite() && aperture >= 0.0) {
+                    return Err(format!("`{}` must not be negative", option));
+                }
+                options.aperture = Some(aperture);
+            }
+            "--focus-distance" => {
+                let distance: f32 = parse_value(&option, &value)?;
+                if !(distance.is_finite() && distance > 0.0) {
+                    return Err(format!("`{}` must be positive", option));
+                }
+                options.focus = Some(Focus::Distance(distance));
+            }
+            "-e" | "--exposure" => {
+                let exposure: f32 = parse_value(&option, &value)?;
+                if !exposure.is_finite() {
+                    return Err(format!("`{}` must be a finite number", option));
+                }
+                options.exposure = Some(exposure);
+            }
+            "-m" | "--tone-mapping" => {
+                options.tone_mapping = Some(
+                    ToneMapping::from_name(&value)
+                        .ok_or_else(|| format!("unknown tone mapping operator `{}`", value))?,
+                )
+            }
+            "-t" | "--threads" => options.render_threads = Some(parse_positive(&option, &value)?),
+            "-c" | "--channels" => {
+                for name in value.split(',') {
+                    let aov = Aov::from_name(name.trim())
+                        .ok_or_else(|| format!("unknown channel `{}`", name))?;
+                    if !options.aovs.contains(&aov) {
+                        options.aovs.push(aov);
+                    }
+                }
+            }
+            _ => return Err(format!("unknown option `{}`", option)),
+        }
+    }
+
+    if let (Some(width), Some(height)) = (options.image_width, options.image_height) {
+        if width * height > MAX_PIXELS {
+            return Err(format!(
+                "an image of {}x{} pixels is too large",
+                width, height
+            ));
+        }
+    }
+    if let Some(path) = scene_path {
+        options.scene_path = path;
+    }
+    if benchmark {
+        return Ok(Command::Benchmark(options));
+    }
+    Ok(Command::Render(options))
+}
+
+impl Options {
+    // Output format given on the command line or guessed from the file name.
+    pub fn output_format(&self) -> Result<ImageFormat, String> {
+        match self.output_format {
+            Some(format) => Ok(format),
+            None => ImageFormat::from_path(&self.output_path).ok_or_else(|| {
+                format!(
+                    "cannot determine the output format of `{}`, use `--format`",
+                    self.output_path
+                )
+            }),
+        }
+    }
+
+    pub fn apply(&self, scene: &mut Scene) -> Result<(), String> {
+        if let Some(width) = self.image_width {
+            scene.image_width = width;
+        }
+        if let Some(height) = self.image_height {
+            scene.image_height = height;
+        }
+
+        if self.aperture.is_some() || self.focus.is_some() {
+            match &mut scene.camera.projection {
+                Projection::Perspective {
+                    aperture, focus, ..
+                } => {
+                    if let Some(a) = self.aperture {
+                        *aperture = a;
+                    }
+                    if let Some(f) = self.focus {
+                        *focus = f;
+                    }
+                }
+                _ => return Err("depth of field needs a perspective camera".to_string()),
+            }
+        }
+
+        let settings = &mut scene.render_settings;
+        if let Some(samples) = self.samples_per_pixel {
+            settings.samples_per_pixel = samples;
+        }
+        if let Some(depth) = self.max_recursion_depth {
+            settings.max_recursion_depth = depth;
+        }
+        if let Some(encoding) = self.image_encoding {
+            settings.image_encoding = encoding;
+        }
+        if let Some(exposure) = self.exposure {
+            settings.exposure = exposure;
+        }
+        if let Some(tone_mapping) = self.tone_mapping {
+            // Keep the white point from the scene file, it can't be given on
+            // the command line.
+            let keep = matches!(
+                (settings.tone_mapping, tone_mapping),
+                (
+                    ToneMapping::ExtendedReinhard { .. },
+                    ToneMapping::ExtendedReinhard { .. }
+                )
+            );
+            if !keep {
+                settings.tone_mapping = tone_mapping;
+            }
+        }
+        if let Some(threads) = self.render_threads {
+            settings.render_threads = threads;
+        }
+        if self.spectral {
+            settings.spectral = true;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn image_size() {
+        match parse(&["--width", "640", "--height=480"]) {
+            Ok(Command::Render(options)) => {
+                assert_eq!(options.image_width, Some(640));
+                assert_eq!(options.image_height, Some(480));
+            }
+            _ => panic!("expected render options"),
+        }
+        assert!(parse(&["--width", "0"]).is_err());
+        assert!(parse(&["--width", "-5"]).is_err());
+        assert!(parse(&["--height", "18446744073709551615"]).is_err());
+        assert!(parse(&["--width", "100000", "--height", "100000"]).is_err());
+    }
+}
@@ -0,0 +1,432 @@
+// OpenEXR images: scanline files with ZIP compression, in half or full float
+// precision and with any number of channels.
+//
+// See: https://openexr.com/en/latest/OpenEXRFileLayout.html
+
+use std::io::BufWriter;
+use std::io::Write;
+
+use crate::zlib;
+
+// Scanlines per chunk for ZIP compression.
+const LINES_PER_CHUNK: usize = 16;
+const ZIP_COMPRESSION: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PixelType {
+    Half,
+    Float,
+}
+
+// One named channel with width * height values, top row first.
+pub struct Channel {
+    pub name: String,
+    pub values: Vec<f32>,
+}
+
+impl PixelType {
+    fn id(self) -> i32 {
+        match self {
+            PixelType::Half => 1,
+            PixelType::Float => 2,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PixelType::Half => 2,
+            PixelType::Float => 4,
+        }
+    }
+}
+
+// Shifts right, rounding to nearest with ties to even like IEEE 754.
+fn shift_rounded(value: u32, shift: u32) -> u32 {
+    let result = value >> shift;
+    let remainder = value & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    if remainder > halfway || (remainder == halfway && result & 1 == 1) {
+        result + 1
+    } else {
+        result
+    }
+}
+
+// Converts to IEEE 754 half precision, rounding to nearest even. Values too
+// large for a half become infinity.
+fn to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let e = exponent - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if e <= 0 {
+        // Subnormal half, or zero.
+        if e < -10 {
+            return sign;
+        }
+        let m = mantissa | 0x80_0000;
+        return sign | shift_rounded(m, (14 - e) as u32) as u16;
+    }
+    // A carry out of the mantissa correctly bumps the exponent.
+    sign | shift_rounded((e as u32) << 23 | mantissa, 13) as u16
+}
+
+fn attribute<W: Write>(
+    writer: &mut W,
+    name: &str,
+    kind: &str,
+    value: &[u8],
+) -> std::io::Result<()> {
+    writer.write_all(name.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(kind.as_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(&(value.len() as i32).to_le_bytes())?;
+    writer.write_all(value)
+}
+
+fn i32s(values: &[i32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+// Compresses a chunk the way the ZIP compressor does: bytes are split into
+// two halves and delta encoded before deflating. Chunks that don't get
+// smaller are stored as is.
+fn compress(raw: &[u8]) -> Vec<u8> {
+    let mut reordered = Vec::with_capacity(raw.len());
+    reordered.extend(raw.iter().step_by(2).copied());
+    reordered.extend(raw.iter().skip(1).step_by(2).copied());
+
+    let mut previous = reordered[0];
+    for byte in reordered.iter_mut().skip(1) {
+        let current = *byte;
+        *byte = current.wrapping_sub(previous).wrapping_add(128);
+        previous = current;
+    }
+
+    let compressed = zlib::compress(&reordered);
+    if compressed.len() < raw.len() {
+        compressed
+    } else {
+        raw.to_vec()
+    }
+}
+
+pub fn write(
+    filename: &str,
+    width: usize,
+    height: usize,
+    channels: &[Channel],
+    pixel_type: PixelType,
+) -> std::io::Result<()> {
+    // Channels have to be stored in alphabetical order.
+    let mut channels: Vec<&Channel> = channels.iter().collect();
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+    for channel in &channels {
+        assert_eq!(channel.values.len(), width * height);
+    }
+
+    let mut header = Vec::new();
+    let mut channel_list = Vec::new();
+    for channel in &channels {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend(i32s(&[pixel_type.id()]));
+        // Not perceptually linear, three reserved bytes, no subsampling.
+        channel_list.extend_from_slice(&[0, 0, 0, 0]);
+        channel_list.extend(i32s(&[1, 1]));
+    }
+    channel_list.push(0);
+    let window = i32s(&[0, 0, width as i32 - 1, height as i32 - 1]);
+    attribute(&mut header, "channels", "chlist", &channel_list)?;
+    attribute(
+        &mut header,
+        "compression",
+        "compression",
+        &[ZIP_COMPRESSION],
+    )?;
+    attribute(&mut header, "dataWindow", "box2i", &window)?;
+    attribute(&mut header, "displayWindow", "box2i", &window)?;
+    attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0f32.to_le_bytes(),
+    )?;
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0f32.to_le_bytes(),
+    )?;
+    header.push(0);
+
+    // Within a chunk each scanline stores all values of one channel after
+    // another.
+    let mut chunks = Vec::new();
+    for y0 in (0..height).step_by(LINES_PER_CHUNK) {
+        let y1 = (y0 + LINES_PER_CHUNK).min(height);
+        let mut raw = Vec::with_capacity((y1 - y0) * width * channels.len() * pixel_type.size());
+        for y in y0..y1 {
+            for channel in &channels {
+                for &value in &channel.values[y * width..(y + 1) * width] {
+                    match pixel_type {
+                        PixelType::Half => raw.extend_from_slice(&to_half(value).to_le_bytes()),
+                        PixelType::Float => raw.extend_from_slice(&value.to_le_bytes()),
+                    }
+                }
+            }
+        }
+        chunks.push((y0, compress(&raw)));
+    }
+
+    let mut file = BufWriter::new(std::fs::File::create(filename)?);
+    // Magic number and version 2, single part scanline file.
+    file.write_all(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0])?;
+    file.write_all(&header)?;
+
+    // The offset table points at each chunk from the start of the file.
+    let mut offset = (8 + header.len() + 8 * chunks.len()) as u64;
+    for (_, data) in &chunks {
+        file.write_all(&offset.to_le_bytes())?;
+        offset += 8 + data.len() as u64;
+    }
+    for (y, data) in &chunks {
+        file.write_all(&(*y as i32).to_le_bytes())?;
+        file.write_all(&(data.len() as i32).to_le_bytes())?;
+        file.write_all(data)?;
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn from_half(half: u16) -> f32 {
+        let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = ((half >> 10) & 0x1f) as i32;
+        let mantissa = (half & 0x3ff) as f32;
+        sign * match exponent {
+            0 => mantissa * 2f32.powi(-24),
+            0x1f if mantissa == 0.0 => f32::INFINITY,
+            0x1f => f32::NAN,
+            _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+        }
+    }
+
+    #[test]
+    fn exact_values() {
+        assert_eq!(to_half(0.0), 0x0000);
+        assert_eq!(to_half(-0.0), 0x8000);
+        assert_eq!(to_half(1.0), 0x3c00);
+        assert_eq!(to_half(-2.0), 0xc000);
+        assert_eq!(to_half(65504.0), 0x7bff);
+        assert_eq!(to_half(2f32.powi(-24)), 0x0001);
+        assert_eq!(to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(to_half(1e6), 0x7c00);
+        assert_eq!(to_half(f32::NAN) & 0x7c00, 0x7c00);
+        assert_ne!(to_half(f32::NAN) & 0x3ff, 0);
+        for half in 0..0x7c00u16 {
+            assert_eq!(to_half(from_half(half)), half);
+            assert_eq!(to_half(-from_half(half)), half | 0x8000);
+        }
+    }
+
+    #[test]
+    fn ties_to_even() {
+        // Halfway between 1 and the next half, and between that and the one
+        // after it.
+        assert_eq!(to_half(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(to_half(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+        // The same for subnormals, and between the largest half and infinity.
+        assert_eq!(to_half(2f32.powi(-25)), 0x0000);
+        assert_eq!(to_half(3.0 * 2f32.powi(-25)), 0x0002);
+        assert_eq!(to_half(65520.0), 0x7c00);
+        for half in 0..0x7bffu16 {
+            let middle = (from_half(half) + from_half(half + 1)) / 2.0;
+            let even = if half & 1 == 0 { half } else { half + 1 };
+            assert_eq!(to_half(middle), even);
+            // Just off the middle rounds to the closer one.
+            assert_eq!(to_half(f32::from_bits(middle.to_bits() - 1)), half);
+            assert_eq!(to_half(f32::from_bits(middle.to_bits() + 1)), half + 1);
+        }
+    }
+
+    fn i32_at(data: &[u8], offset: usize) -> i32 {
+        i32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    }
+
+    fn string_at(data: &[u8], offset: &mut usize) -> String {
+        let end = *offset + data[*offset..].iter().position(|&b| b == 0).unwrap();
+        let s = String::from_utf8(data[*offset..end].to_vec()).unwrap();
+        *offset = end + 1;
+        s
+    }
+
+    // Reads back a file as written by `write`, returning the size and the
+    // channels in file order.
+    fn read_back(filename: &str) -> (usize, usize, Vec<(PixelType, Channel)>) {
+        let data = std::fs::read(filename).unwrap();
+        assert_eq!(data[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+        let mut offset = 8;
+        let mut channels = Vec::new();
+        let mut window = [0; 4];
+        loop {
+            let name = string_at(&data, &mut offset);
+            if name.is_empty() {
+                break;
+            }
+            let kind = string_at(&data, &mut offset);
+            let size = i32_at(&data, offset) as usize;
+            let value = &data[offset + 4..offset + 4 + size];
+            offset += 4 + size;
+            match (name.as_str(), kind.as_str()) {
+                ("channels", "chlist") => {
+                    let mut i = 0;
+                    while value[i] != 0 {
+                        let name = string_at(value, &mut i);
+                        let pixel_type = match i32_at(value, i) {
+                            1 => PixelType::Half,
+                            2 => PixelType::Float,
+                            other => panic!("unexpected pixel type {}", other),
+                        };
+                        channels.push((
+                            pixel_type,
+                            Channel {
+                                name,
+                                values: Vec::new(),
+                            },
+                        ));
+                        i += 16;
+                    }
+                }
+                ("compression", _) => assert_eq!(value, [ZIP_COMPRESSION]),
+                ("dataWindow", "box2i") => {
+                    for (k, w) in window.iter_mut().enumerate() {
+                        *w = i32_at(value, 4 * k);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let width = (window[2] - window[0] + 1) as usize;
+        let height = (window[3] - window[1] + 1) as usize;
+
+        let pixel_size: usize = channels.iter().map(|c| c.0.size()).sum();
+        for chunk in 0..height.div_ceil(LINES_PER_CHUNK) {
+            let start = u64::from_le_bytes(
+                data[offset + 8 * chunk..offset + 8 * chunk + 8]
+                    .try_into()
+                    .unwrap(),
+            ) as usize;
+            let y0 = i32_at(&data, start) as usize;
+            assert_eq!(y0, chunk * LINES_PER_CHUNK);
+            let size = i32_at(&data, start + 4) as usize;
+            let stored = &data[start + 8..start + 8 + size];
+            let lines = (height - y0).min(LINES_PER_CHUNK);
+            let raw_size = lines * width * pixel_size;
+
+            // Undo the delta encoding and the split into two halves.
+            let raw = if size < raw_size {
+                let mut reordered = zlib::decompress(stored).unwrap();
+                for i in 1..reordered.len() {
+                    reordered[i] = reordered[i - 1]
+                        .wrapping_add(reordered[i])
+                        .wrapping_sub(128);
+                }
+                let half = raw_size.div_ceil(2);
+                (0..raw_size)
+                    .map(|i| reordered[i / 2 + if i % 2 == 0 { 0 } else { half }])
+                    .collect()
+            } else {
+                stored.to_vec()
+            };
+            assert_eq!(raw.len(), raw_size);
+
+            let mut i = 0;
+            for _ in 0..lines {
+                for (pixel_type, channel) in channels.iter_mut() {
+                    for _ in 0..width {
+                        channel.values.push(match pixel_type {
+                            PixelType::Half => from_half(u16::from_le_bytes([raw[i], raw[i + 1]])),
+                            PixelType::Float => {
+                                f32::from_le_bytes([raw[i], raw[i + 1], raw[i + 2], raw[i + 3]])
+                            }
+                        });
+                        i += pixel_type.size();
+                    }
+                }
+            }
+        }
+        (width, height, channels)
+    }
+
+    fn round_trip(pixel_type: PixelType, values: &dyn Fn(usize) -> f32) {
+        let (width, height) = (29, 37);
+        let channels: Vec<Channel> = ["Z", "B", "G", "R"]
+            .iter()
+            .enumerate()
+            .map(|(c, name)| Channel {
+                name: name.to_string(),
+                values: (0..width * height)
+                    .map(|i| values(c * width * height + i))
+                    .collect(),
+            })
+            .collect();
+        let path = std::env::temp_dir().join(format!(
+            "rust_tracer_test_{}_{:?}.exr",
+            std::process::id(),
+            pixel_type
+        ));
+        write(path.to_str().unwrap(), width, height, &channels, pixel_type).unwrap();
+        let (read_width, read_height, read_channels) = read_back(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((read_width, read_height), (width, height));
+        let names: Vec<&str> = read_channels.iter().map(|c| c.1.name.as_str()).collect();
+        assert_eq!(names, ["B", "G", "R", "Z"]);
+        for (read_type, read_channel) in &read_channels {
+            assert_eq!(*read_type, pixel_type);
+            let channel = channels.iter().find(|c| c.name == read_channel.name);
+            assert_eq!(read_channel.values, channel.unwrap().values);
+        }
+    }
+
+    #[test]
+    fn round_trip_half() {
+        // Smooth values compress, so this goes through the ZIP path.
+        round_trip(PixelType::Half, &|i| from_half((i / 7) as u16 % 0x7c00));
+    }
+
+    #[test]
+    fn round_trip_float() {
+        // Noise doesn't compress and is stored as is.
+        round_trip(PixelType::Float, &|i| {
+            let mut x = (i as u32).wrapping_mul(2_654_435_761);
+            x ^= x >> 15;
+            x = x.wrapping_mul(0x2c1b_3c6d);
+            x ^= x >> 12;
+            f32::from_bits(x % 0x7f00_0000)
+        });
+        round_trip(PixelType::Float, &|i| (i % 100) as f32 * 0.25);
+    }
+}
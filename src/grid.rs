@@ -0,0 +1,290 @@
+// Dense voxel grids of a single quantity like density or temperature, read
+// from Mitsuba's volume files (.vol): the bytes "VOL" and the version 3, then
+// the encoding (1 for 32 bit floats, 3 for bytes mapped to [0, 1]), the
+// resolution in x, y and z, the number of channels and the bounding box as
+// min and max, all little endian 32 bit integers and floats. The voxels follow
+// in the same encoding with x varying fastest, then y, then z. Only the first
+// channel is used. OpenVDB and NanoVDB files have to be converted first.
+
+use std::io::BufReader;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::path::Path;
+
+use crate::aabb::Aabb;
+use crate::math::Vec3;
+
+// Magic bytes, encoding, resolution, channels and bounds.
+const HEADER_SIZE: u64 = 48;
+
+pub struct Grid {
+    resolution: [usize; 3],
+    bounds: Aabb,
+    values: Vec<f32>,
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> std::io::Result<i32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+// A positive count like a resolution.
+fn read_count<R: Read>(reader: &mut R, what: &str) -> std::io::Result<usize> {
+    match read_i32(reader)? {
+        count if count > 0 => Ok(count as usize),
+        _ => Err(invalid(&format!("invalid {}", what))),
+    }
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> std::io::Result<f32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+impl Grid {
+    pub fn read(path: &Path) -> std::io::Result<Grid> {
+        let file = std::fs::File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic[..3] != b"VOL" {
+            return Err(invalid("not a volume file"));
+        }
+        if magic[3] != 3 {
+            return Err(invalid("unsupported volume file version"));
+        }
+        let encoding = read_i32(&mut reader)?;
+        let mut resolution = [0usize; 3];
+        for r in resolution.iter_mut() {
+            *r = read_count(&mut reader, "resolution")?;
+        }
+        let channels = read_count(&mut reader, "number of channels")?;
+        let mut corners = [0.0f32; 6];
+        for c in corners.iter_mut() {
+            *c = read_f32(&mut reader)?;
+        }
+        let bounds = Aabb::new(
+            Vec3 {
+                x: corners[0],
+                y: corners[1],
+                z: corners[2],
+            },
+            Vec3 {
+                x: corners[3],
+                y: corners[4],
+                z: corners[5],
+            },
+        );
+
+        // Check the size against the file before allocating anything.
+        let value_size = match encoding {
+            1 => 4,
+            3 => 1,
+            _ => return Err(invalid("unsupported volume encoding")),
+        };
+        let count = resolution
+            .iter()
+            .try_fold(channels, |count, &r| count.checked_mul(r))
+            .filter(|&count| {
+                count
+                    .checked_mul(value_size)
+                    .is_some_and(|size| size as u64 <= length.saturating_sub(HEADER_SIZE))
+            })
+            .ok_or_else(|| invalid("volume file too short for its resolution"))?;
+
+        let mut bytes = vec![0u8; count * value_size];
+        reader.read_exact(&mut bytes)?;
+        let values: Vec<f32> = bytes
+            .chunks_exact(value_size * channels)
+            .map(|b| match encoding {
+                1 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                _ => b[0] as f32 / 255.0,
+            })
+            .collect();
+        // Negative values would make densities negative.
+        if values.iter().any(|v| !v.is_finite() || *v < 0.0) {
+            return Err(invalid("volume values must be finite and not negative"));
+        }
+
+        Ok(Grid {
+            resolution,
+            bounds,
+            values,
+        })
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.bounds
+    }
+
+    pub fn max(&self) -> f32 {
+        self.values.iter().fold(0.0, |max, &v| max.max(v))
+    }
+
+    // Trilinear interpolation between the voxels, whose first and last ones
+    // along each axis lie on the faces of the bounds. Zero outside of them.
+    pub fn value(&self, p: &Vec3) -> f32 {
+        let mut cell = [0usize; 3];
+        let mut fraction = [0.0f32; 3];
+        for axis in 0..3 {
+            let relative =
+                (p[axis] - self.bounds.min[axis]) / (self.bounds.max[axis] - self.bounds.min[axis]);
+            if !(0.0..=1.0).contains(&relative) {
+                return 0.0;
+            }
+            let last = self.resolution[axis] - 1;
+            let x = relative * last as f32;
+            cell[axis] = (x as usize).min(last.saturating_sub(1));
+            fraction[axis] = if last == 0 {
+                0.0
+            } else {
+                x - cell[axis] as f32
+            };
+        }
+
+        let voxel = |x: usize, y: usize, z: usize| {
+            let x = (cell[0] + x).min(self.resolution[0] - 1);
+            let y = (cell[1] + y).min(self.resolution[1] - 1);
+            let z = (cell[2] + z).min(self.resolution[2] - 1);
+            self.values[(z * self.resolution[1] + y) * self.resolution[0] + x]
+        };
+        let lerp = |a: f32, b: f32, t: f32| a * (1.0 - t) + b * t;
+        let [fx, fy, fz] = fraction;
+        let v00 = lerp(voxel(0, 0, 0), voxel(1, 0, 0), fx);
+        let v10 = lerp(voxel(0, 1, 0), voxel(1, 1, 0), fx);
+        let v01 = lerp(voxel(0, 0, 1), voxel(1, 0, 1), fx);
+        let v11 = lerp(voxel(0, 1, 1), voxel(1, 1, 1), fx);
+        lerp(lerp(v00, v10, fy), lerp(v01, v11, fy), fz)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A volume file with unit bounds.
+    fn volume(encoding: i32, resolution: [i32; 3], channels: i32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"VOL\x03".to_vec();
+        for value in [
+            encoding,
+            resolution[0],
+            resolution[1],
+            resolution[2],
+            channels,
+        ]
+        .iter()
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0.0f32, 0.0, 0.0, 1.0, 1.0, 1.0].iter() {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn floats(values: &[f32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    fn read_bytes(name: &str, data: &[u8]) -> std::io::Result<Grid> {
+        let path = std::env::temp_dir().join(format!(
+            "rust_tracer_test_{}_{}.vol",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, data).unwrap();
+        let result = Grid::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn error(name: &str, data: &[u8]) -> String {
+        read_bytes(name, data).err().unwrap().to_string()
+    }
+
+    fn point(x: f32, y: f32, z: f32) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    #[test]
+    fn interpolates_voxels() {
+        let values: Vec<f32> = (0..8).map(|i| i as f32).collect();
+        let grid = read_bytes("floats", &volume(1, [2, 2, 2], 1, &floats(&values))).unwrap();
+        assert_eq!(grid.max(), 7.0);
+        assert_eq!(grid.value(&point(0.0, 0.0, 0.0)), 0.0);
+        assert_eq!(grid.value(&point(1.0, 0.0, 0.0)), 1.0);
+        assert_eq!(grid.value(&point(0.0, 1.0, 0.0)), 2.0);
+        assert_eq!(grid.value(&point(0.0, 0.0, 1.0)), 4.0);
+        assert_eq!(grid.value(&point(1.0, 1.0, 1.0)), 7.0);
+        assert!((grid.value(&point(0.5, 0.5, 0.5)) - 3.5).abs() < 1e-6);
+        assert!((grid.value(&point(0.25, 0.0, 1.0)) - 4.25).abs() < 1e-6);
+        assert_eq!(grid.value(&point(1.5, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.value(&point(0.5, -0.1, 0.5)), 0.0);
+    }
+
+    #[test]
+    fn reads_bytes_and_first_channel() {
+        let data = [0, 1, 255, 2, 51, 3];
+        let grid = read_bytes("bytes", &volume(3, [3, 1, 1], 2, &data)).unwrap();
+        assert_eq!(grid.max(), 1.0);
+        assert_eq!(grid.value(&point(0.0, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.value(&point(0.5, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.value(&point(1.0, 0.5, 0.5)), 0.2);
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        let one = floats(&[1.0]);
+        let mut magic = volume(1, [1, 1, 1], 1, &one);
+        magic[0] = b'X';
+        assert_eq!(error("magic", &magic), "not a volume file");
+        let mut version = volume(1, [1, 1, 1], 1, &one);
+        version[3] = 2;
+        assert_eq!(
+            error("version", &version),
+            "unsupported volume file version"
+        );
+        assert_eq!(
+            error("encoding", &volume(2, [1, 1, 1], 1, &one)),
+            "unsupported volume encoding"
+        );
+        assert_eq!(
+            error("resolution", &volume(1, [1, 0, 1], 1, &one)),
+            "invalid resolution"
+        );
+        assert_eq!(
+            error("channels", &volume(1, [1, 1, 1], -1, &one)),
+            "invalid number of channels"
+        );
+        assert_eq!(
+            error("short", &volume(1, [2, 1, 1], 1, &one)),
+            "volume file too short for its resolution"
+        );
+        assert_eq!(
+            error("huge", &volume(1, [i32::MAX, i32::MAX, i32::MAX], 1, &one)),
+            "volume file too short for its resolution"
+        );
+        assert_eq!(
+            error("negative", &volume(1, [1, 1, 1], 1, &floats(&[-1.0]))),
+            "volume values must be finite and not negative"
+        );
+        assert_eq!(
+            error("nan", &volume(1, [1, 1, 1], 1, &floats(&[f32::NAN]))),
+            "volume values must be finite and not negative"
+        );
+        assert!(read_bytes("truncated", &volume(1, [1, 1, 1], 1, &[])[..20]).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+// Radiance RGBE (.hdr) images.
+//
+// See: https://www.graphics.cornell.edu/~bjw/rgbe.html
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+
+use crate::image::Image;
+use crate::math::Color;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn rgbe_to_color(rgbe: [u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::black();
+    }
+    let f = 2.0f32.powi(rgbe[3] as i32 - (128 + 8));
+    Color {
+        r: rgbe[0] as f32 * f,
+        g: rgbe[1] as f32 * f,
+        b: rgbe[2] as f32 * f,
+    }
+}
+
+fn color_to_rgbe(color: &Color) -> [u8; 4] {
+    let max = color.r.max(color.g).max(color.b);
+    if max.is_nan() || max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    // Scale so that the largest component ends up in [128, 256).
+    let exponent = max.log2().floor() as i32 + 1;
+    let exponent = exponent.min(127);
+    let f = 2.0f32.powi(8 - exponent);
+    let component = |c: f32| (c.max(0.0) * f).min(255.0) as u8;
+    [
+        component(color.r),
+        component(color.g),
+        component(color.b),
+        (exponent + 128) as u8,
+    ]
+}
+
+// Reads one scanline, either flat or run length encoded per component.
+fn read_scanline<R: Read>(reader: &mut R, width: usize) -> std::io::Result<Vec<[u8; 4]>> {
+    let mut first = [0u8; 4];
+    reader.read_exact(&mut first)?;
+
+    let is_rle =
+        (8..0x8000).contains(&width) && first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0;
+    if !is_rle {
+        let mut line = vec![first; width];
+        for pixel in line.iter_mut().skip(1) {
+            reader.read_exact(pixel)?;
+        }
+        return Ok(line);
+    }
+
+    if ((first[2] as usize) << 8 | first[3] as usize) != width {
+        return Err(invalid("scanline width mismatch"));
+    }
+
+    let mut line = vec![[0u8; 4]; width];
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            reader.read_exact(&mut count)?;
+            let count = count[0] as usize;
+            if count > 128 {
+                // A run of a single value.
+                let count = count - 128;
+                if x + count > width {
+                    return Err(invalid("run length exceeds scanline"));
+                }
+                let mut value = [0u8; 1];
+                reader.read_exact(&mut value)?;
+                for pixel in &mut line[x..x + count] {
+                    pixel[component] = value[0];
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid("invalid run length"));
+                }
+                let mut values = vec![0u8; count];
+                reader.read_exact(&mut values)?;
+                for (pixel, value) in line[x..x + count].iter_mut().zip(values) {
+                    pixel[component] = value;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(line)
+}
+
+pub fn read(filename: &str) -> std::io::Result<Image> {
+    let mut reader = BufReader::new(std::fs::File::open(filename)?);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid("not a Radiance HDR file"));
+    }
+
+    // Header lines up to an empty line, then the resolution string.
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("unexpected end of header"));
+        }
+        let l = line.trim();
+        if l.is_empty() {
+            break;
+        }
+        if let Some(format) = l.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid("unsupported pixel format"));
+            }
+        }
+    }
+
+    line.clear();
+    reader.read_line(&mut line)?;
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let (flip_y, height, width) = match tokens.as_slice() {
+        ["-Y", h, "+X", w] => (false, h, w),
+        ["+Y", h, "+X", w] => (true, h, w),
+        _ => return Err(invalid("unsupported image orientation")),
+    };
+    let height: usize = height.parse().map_err(|_| invalid("invalid height"))?;
+    let width: usize = width.parse().map_err(|_| invalid("invalid width"))?;
+    Image::check_size(width, height)?;
+
+    let mut data = vec![Color::black(); width * height];
+    for y in 0..height {
+        let row = if flip_y { height - 1 - y } else { y };
+        let scanline = read_scanline(&mut reader, width)?;
+        for (x, rgbe) in scanline.into_iter().enumerate() {
+            data[row * width + x] = rgbe_to_color(rgbe);
+        }
+    }
+
+    Ok(Image::from_data(width, height, data))
+}
+
+// Run length encodes one component of a scanline. Runs of at least three
+// equal values are stored as runs, everything else as literal spans.
+fn write_component<W: Write>(writer: &mut W, values: &[u8]) -> std::io::Result<()> {
+    let mut i = 0;
+    while i < values.len() {
+        let run = values[i..]
+            .iter()
+            .take(127)
+            .take_while(|&&v| v == values[i])
+            .count();
+        if run >= 3 {
+            writer.write_all(&[128 + run as u8, values[i]])?;
+            i += run;
+            continue;
+        }
+
+        // Collect literals up to the next run worth encoding.
+        let start = i;
+        while i < values.len() && i - start < 128 {
+            if i + 2 < values.len() && values[i] == values[i + 1] && values[i] == values[i + 2] {
+                break;
+            }
+            i += 1;
+        }
+        writer.write_all(&[(i - start) as u8])?;
+        writer.write_all(&values[start..i])?;
+    }
+    Ok(())
+}
+
+pub fn write(image: &Image, filename: &str) -> std::io::Result<()> {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+
+    let mut file = BufWriter::new(std::fs::File::create(filename)?);
+    write!(
+        file,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        height, width
+    )?;
+
+    let mut scanline = Vec::with_capacity(width);
+    for y in 0..height as u32 {
+        scanline.clear();
+        scanline.extend((0..width as u32).map(|x| color_to_rgbe(image.get_pixel(x, y))));
+
+        // Only scanlines of this width can be run length encoded.
+        if !(8..0x8000).contains(&width) {
+            for rgbe in &scanline {
+                file.write_all(rgbe)?;
+            }
+            continue;
+        }
+        file.write_all(&[2, 2, (width >> 8) as u8, width as u8])?;
+        for component in 0..4 {
+            let values: Vec<u8> = scanline.iter().map(|rgbe| rgbe[component]).collect();
+            write_component(&mut file, &values)?;
+        }
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs of equal pixels on the left, literal spans on the right and a
+    // large dynamic range.
+    fn test_image(width: usize, height: usize) -> Image {
+        let data = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let v = if x < width / 2 {
+                    (x / 10 + y) as f32
+                } else {
+                    ((x * 7919 + y * 104_729) % 1000) as f32
+                };
+                Color {
+                    r: v * 0.01,
+                    g: 2.0f32.powi(y as i32 - 8) * (1.0 + v),
+                    b: if x % 3 == 0 { 0.0 } else { 1000.0 - v },
+                }
+            })
+            .collect();
+        Image::from_data(width, height, data)
+    }
+
+    fn round_trip(width: usize) {
+        let image = test_image(width, 12);
+        let path = std::env::temp_dir().join(format!(
+            "rust_tracer_test_{}_{}.hdr",
+            std::process::id(),
+            width
+        ));
+        let filename = path.to_str().unwrap();
+        write(&image, filename).unwrap();
+        let result = read(filename);
+        std::fs::remove_file(&path).unwrap();
+        let read_back = result.unwrap();
+
+        assert_eq!(read_back.width(), image.width());
+        assert_eq!(read_back.height(), image.height());
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let expected = image.get_pixel(x, y);
+                let actual = read_back.get_pixel(x, y);
+                // The shared exponent leaves 8 bits for the largest component.
+                let tolerance = expected.r.max(expected.g).max(expected.b) / 128.0;
+                for (a, e) in [
+                    (actual.r, expected.r),
+                    (actual.g, expected.g),
+                    (actual.b, expected.b),
+                ] {
+                    assert!((a - e).abs() <= tolerance, "{} instead of {}", a, e);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_flat() {
+        round_trip(5);
+    }
+
+    #[test]
+    fn round_trip_run_length_encoded() {
+        round_trip(300);
+    }
+
+    #[test]
+    fn exact_values() {
+        let color = Color {
+            r: 1.0,
+            g: 0.5,
+            b: 0.0,
+        };
+        let rgbe = color_to_rgbe(&color);
+        assert_eq!(rgbe, [128, 64, 0, 129]);
+        let back = rgbe_to_color(rgbe);
+        assert_eq!((back.r, back.g, back.b), (1.0, 0.5, 0.0));
+        assert_eq!(color_to_rgbe(&Color::black()), [0, 0, 0, 0]);
+    }
+}
@@ -1,6 +1,37 @@
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::ErrorKind;
 use std::io::Write;
+use std::path::Path;
 
+use crate::exr;
+use crate::hdr;
 use crate::math::Color;
+use crate::pfm;
+use crate::png;
+use crate::tonemap::ToneMapping;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Ppm,
+    Png8,
+    Png16,
+    Pfm,
+    Hdr,
+    ExrHalf,
+    ExrFloat,
+}
+
+// Transfer function from linear values to what ends up in 8 or 16 bit files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Srgb,
+    Gamma(f32),
+}
+
+// Largest number of pixels accepted from files, so that corrupt headers fail
+// instead of exhausting memory.
+pub const MAX_PIXELS: usize = 1 << 28;
 
 pub struct Image {
     width: usize,
@@ -8,6 +39,14 @@ pub struct Image {
     data: Vec<Color>,
 }
 
+// How texture lookups treat coordinates outside of [0, 1].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
 pub struct Tile {
     pub x: u32,
     pub y: u32,
@@ -17,9 +56,50 @@ pub struct Tile {
 impl Image {
     pub fn new(width: usize, height: usize) -> Image {
         Image {
-            width: width,
-            height: height,
-            data: vec![Color::black(); width * height as usize],
+            width,
+            height,
+            data: vec![Color::black(); width * height],
+        }
+    }
+
+    // Checks the dimensions from an image file header before they are used
+    // to allocate pixels.
+    pub fn check_size(width: usize, height: usize) -> std::io::Result<()> {
+        if width == 0 || height == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "empty image"));
+        }
+        if width.checked_mul(height).is_none_or(|n| n > MAX_PIXELS) {
+            return Err(Error::new(ErrorKind::InvalidData, "image too large"));
+        }
+        Ok(())
+    }
+
+    pub fn from_data(width: usize, height: usize, data: Vec<Color>) -> Image {
+        assert_eq!(data.len(), width * height);
+        Image {
+            width,
+            height,
+            data,
+        }
+    }
+
+    // Reads an image, the format is chosen by extension. Values are returned
+    // as stored, so 8 and 16 bit formats are usually still sRGB encoded, see
+    // ImageFormat::is_linear().
+    pub fn read(filename: &str) -> std::io::Result<Image> {
+        let extension = Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hdr") | Some("pic") => hdr::read(filename),
+            Some("pfm") => pfm::read(filename),
+            Some("png") => png::read(filename),
+            Some("ppm") => read_ppm(filename),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "unsupported image format",
+            )),
         }
     }
 
@@ -28,8 +108,8 @@ impl Image {
         assert!(y as usize + height <= self.height);
 
         let mut tile = Tile {
-            x: x,
-            y: y,
+            x,
+            y,
             image: Image::new(width, height),
         };
         for u in 0..width as u32 {
@@ -40,16 +120,20 @@ impl Image {
         tile
     }
 
+    // Splits the image into tiles_w * tiles_h tiles. Tiles at the right and
+    // bottom border may be larger if the image size is not divisible by the
+    // tile count; empty tiles are skipped for tiny images.
     pub fn split_into_tiles(&self, tiles_w: usize, tiles_h: usize) -> Vec<Tile> {
-        let size_w = self.width / tiles_w;
-        let size_h = self.height / tiles_h;
-        assert_eq!(size_w * tiles_w, self.width);
-        assert_eq!(size_h * tiles_h, self.height);
-
         let mut tiles = vec![];
         for u in 0..tiles_w {
+            let x0 = u * self.width / tiles_w;
+            let x1 = (u + 1) * self.width / tiles_w;
             for v in 0..tiles_h {
-                tiles.push(self.get_tile((u * size_w) as u32, (v * size_h) as u32, size_w, size_h));
+                let y0 = v * self.height / tiles_h;
+                let y1 = (v + 1) * self.height / tiles_h;
+                if x1 > x0 && y1 > y0 {
+                    tiles.push(self.get_tile(x0 as u32, y0 as u32, x1 - x0, y1 - y0));
+                }
             }
         }
         tiles
@@ -80,6 +164,27 @@ impl Image {
         &self.data[self.width * y as usize + x as usize]
     }
 
+    // Bilinearly filtered lookup. (0, 0) is the top left corner of the image
+    // and (1, 1) the bottom right one.
+    pub fn sample_bilinear(&self, u: f32, v: f32, wrap_u: Wrap, wrap_v: Wrap) -> Color {
+        let x = u * self.width as f32 - 0.5;
+        let y = v * self.height as f32 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+
+        let pixel = |x: i64, y: i64| {
+            let x = wrap(x, self.width, wrap_u);
+            let y = wrap(y, self.height, wrap_v);
+            self.data[self.width * y + x]
+        };
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = Color::lerp(&pixel(x0, y0), &pixel(x0 + 1, y0), fx);
+        let bottom = Color::lerp(&pixel(x0, y0 + 1), &pixel(x0 + 1, y0 + 1), fx);
+        Color::lerp(&top, &bottom, fy)
+    }
+
     pub fn width(&self) -> u32 {
         self.width as u32
     }
@@ -96,15 +201,93 @@ impl Image {
         }
     }
 
-    pub fn write_ppm(&self, filename: String) {
-        let mut file = std::fs::File::create(filename).expect("File creation failed.");
-        file.write_all(format!("P3\n{} {}\n255\n  ", self.width, self.height).as_bytes())
-            .expect("File writing failed.");
+    // Scales by 2^exposure and maps the result to the display range.
+    pub fn tone_map(&mut self, exposure: f32, tone_mapping: ToneMapping) {
+        let scale = 2.0f32.powf(exposure);
+        for c in self.data.iter_mut() {
+            *c = tone_mapping.apply(*c * scale);
+        }
+    }
+
+    pub fn encode(&mut self, encoding: Encoding) {
+        match encoding {
+            Encoding::Srgb => {
+                for c in self.data.iter_mut() {
+                    c.r = srgb_encode(c.r);
+                    c.g = srgb_encode(c.g);
+                    c.b = srgb_encode(c.b);
+                }
+            }
+            Encoding::Gamma(gamma) => self.gamma_correct(gamma),
+        }
+    }
+
+    // Undoes `encode`, turning stored values back into linear ones.
+    pub fn decode(&mut self, encoding: Encoding) {
+        match encoding {
+            Encoding::Srgb => {
+                for c in self.data.iter_mut() {
+                    *c = c.map(srgb_decode);
+                }
+            }
+            Encoding::Gamma(gamma) => {
+                for c in self.data.iter_mut() {
+                    *c = c.map(|v| v.max(0.0).powf(gamma));
+                }
+            }
+        }
+    }
+
+    pub fn write(&self, filename: &str, format: ImageFormat) -> std::io::Result<()> {
+        match format {
+            ImageFormat::Ppm => self.write_ppm(filename),
+            ImageFormat::Png8 => png::write(self, filename, 8),
+            ImageFormat::Png16 => png::write(self, filename, 16),
+            ImageFormat::Pfm => pfm::write(self, filename),
+            ImageFormat::Hdr => hdr::write(self, filename),
+            ImageFormat::ExrHalf => self.write_exr(filename, exr::PixelType::Half, vec![]),
+            ImageFormat::ExrFloat => self.write_exr(filename, exr::PixelType::Float, vec![]),
+        }
+    }
+
+    // Writes the image as R, G and B channels next to any extra channels.
+    pub fn write_exr(
+        &self,
+        filename: &str,
+        pixel_type: exr::PixelType,
+        extra_channels: Vec<exr::Channel>,
+    ) -> std::io::Result<()> {
+        let mut channels = self.channels(&["R", "G", "B"]);
+        channels.extend(extra_channels);
+        exr::write(filename, self.width, self.height, &channels, pixel_type)
+    }
+
+    // Splits the image into one channel per color component, named in r, g, b
+    // order.
+    pub fn channels(&self, names: &[&str; 3]) -> Vec<exr::Channel> {
+        vec![
+            self.channel(names[0], |c| c.r),
+            self.channel(names[1], |c| c.g),
+            self.channel(names[2], |c| c.b),
+        ]
+    }
+
+    // A single channel holding `component` of every pixel.
+    pub fn channel(&self, name: &str, component: fn(&Color) -> f32) -> exr::Channel {
+        exr::Channel {
+            name: name.to_string(),
+            values: self.data.iter().map(component).collect(),
+        }
+    }
+
+    pub fn write_ppm(&self, filename: &str) -> std::io::Result<()> {
+        let mut file = BufWriter::new(std::fs::File::create(filename)?);
+        file.write_all(format!("P3\n{} {}\n255\n  ", self.width, self.height).as_bytes())?;
 
         for i in 0..self.width * self.height {
-            file.write_all(self.write_pixel(i).as_bytes())
-                .expect("File writing failed.");
+            file.write_all(self.write_pixel(i).as_bytes())?;
         }
+        file.flush()
     }
 
     fn write_pixel(&self, idx: usize) -> String {
@@ -113,8 +296,204 @@ impl Image {
     }
 }
 
+// The sRGB transfer function, see IEC 61966-2-1.
+fn srgb_encode(linear: f32) -> f32 {
+    if linear <= 0.0031308 {
+        12.92 * linear.max(0.0)
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn srgb_decode(encoded: f32) -> f32 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn wrap(i: i64, size: usize, mode: Wrap) -> usize {
+    let size = size as i64;
+    match mode {
+        Wrap::Repeat => i.rem_euclid(size) as usize,
+        Wrap::Clamp => i.clamp(0, size - 1) as usize,
+        Wrap::Mirror => {
+            let i = i.rem_euclid(2 * size);
+            (if i < size { i } else { 2 * size - 1 - i }) as usize
+        }
+    }
+}
+
+// Reads binary (P6) and plain (P3) PPM files, values are scaled to [0, 1].
+fn read_ppm(filename: &str) -> std::io::Result<Image> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+    let data = std::fs::read(filename)?;
+
+    // Header tokens are separated by whitespace and comments.
+    let mut position = 0;
+    let mut token = || -> std::io::Result<String> {
+        loop {
+            match data.get(position) {
+                Some(b'#') => {
+                    while data.get(position).is_some_and(|&c| c != b'\n') {
+                        position += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => position += 1,
+                Some(_) => break,
+                None => return Err(invalid("unexpected end of header")),
+            }
+        }
+        let start = position;
+        while data.get(position).is_some_and(|c| !c.is_ascii_whitespace()) {
+            position += 1;
+        }
+        Ok(String::from_utf8_lossy(&data[start..position]).into_owned())
+    };
+
+    let magic = token()?;
+    if magic != "P3" && magic != "P6" {
+        return Err(invalid("not a PPM file"));
+    }
+    let mut number = || -> std::io::Result<usize> {
+        token()?
+            .parse()
+            .map_err(|_| invalid("invalid number in header"))
+    };
+    let width = number()?;
+    let height = number()?;
+    Image::check_size(width, height)?;
+    let max = number()?;
+    if max == 0 || max > 65535 {
+        return Err(invalid("invalid maximum value"));
+    }
+
+    let count = width * height * 3;
+    let values: Vec<f32> = if magic == "P3" {
+        (0..count)
+            .map(|_| number().map(|v| v as f32 / max as f32))
+            .collect::<std::io::Result<_>>()?
+    } else {
+        // A single whitespace character separates the header from the data.
+        let start = position + 1;
+        let size = if max < 256 { 1 } else { 2 };
+        let bytes = count
+            .checked_mul(size)
+            .and_then(|length| start.checked_add(length))
+            .and_then(|end| data.get(start..end))
+            .ok_or_else(|| invalid("not enough image data"))?;
+        if size == 1 {
+            bytes.iter().map(|&v| v as f32 / max as f32).collect()
+        } else {
+            bytes
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as f32 / max as f32)
+                .collect()
+        }
+    };
+
+    let pixels = values
+        .chunks_exact(3)
+        .map(|c| Color {
+            r: c[0],
+            g: c[1],
+            b: c[2],
+        })
+        .collect();
+    Ok(Image::from_data(width, height, pixels))
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" | "png8" => Some(ImageFormat::Png8),
+            "png16" => Some(ImageFormat::Png16),
+            "pfm" => Some(ImageFormat::Pfm),
+            "hdr" => Some(ImageFormat::Hdr),
+            "exr" => Some(ImageFormat::ExrHalf),
+            "exr32" => Some(ImageFormat::ExrFloat),
+            _ => None,
+        }
+    }
+
+    // Whether the format stores linear radiance as is, without encoding.
+    pub fn is_linear(self) -> bool {
+        match self {
+            ImageFormat::Ppm | ImageFormat::Png8 | ImageFormat::Png16 => false,
+            ImageFormat::Pfm | ImageFormat::Hdr | ImageFormat::ExrHalf | ImageFormat::ExrFloat => {
+                true
+            }
+        }
+    }
+
+    pub fn exr_pixel_type(self) -> Option<exr::PixelType> {
+        match self {
+            ImageFormat::ExrHalf => Some(exr::PixelType::Half),
+            ImageFormat::ExrFloat => Some(exr::PixelType::Float),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &str) -> Option<ImageFormat> {
+        let extension = Path::new(path).extension()?.to_str()?;
+        ImageFormat::from_name(extension)
+    }
+}
+
 impl Tile {
     pub fn tile_to_image_coordinates(&self, x: u32, y: u32) -> (u32, u32) {
         (self.x + x, self.y + y)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads `data` as if it came from a PPM file.
+    fn read_bytes(name: &str, data: &[u8]) -> std::io::Result<Image> {
+        let path = std::env::temp_dir().join(format!(
+            "rust_tracer_test_{}_{}.ppm",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, data).unwrap();
+        let result = Image::read(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn reads_ppm() {
+        let image = read_bytes("p3", b"P3\n# comment\n2 1\n15\n15 0 5 0 15 3\n").unwrap();
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.get_pixel(0, 0).to_u8(), (255, 0, 85));
+        assert_eq!(image.get_pixel(1, 0).to_u8(), (0, 255, 51));
+
+        let image = read_bytes("p6", b"P6 1 1 65535\n\xff\xff\x00\x00\x80\x00").unwrap();
+        assert_eq!(image.get_pixel(0, 0).to_u8(), (255, 0, 128));
+    }
+
+    #[test]
+    fn rejects_empty_ppm() {
+        let error = read_bytes("empty", b"P6\n0 4\n255\n").err().unwrap();
+        assert_eq!(error.to_string(), "empty image");
+        let error = read_bytes("empty_plain", b"P3\n4 0\n255\n").err().unwrap();
+        assert_eq!(error.to_string(), "empty image");
+    }
+
+    #[test]
+    fn rejects_huge_ppm() {
+        let header = format!("P6\n{} {}\n255\n", usize::MAX / 2, 3);
+        let error = read_bytes("huge", header.as_bytes()).err().unwrap();
+        assert_eq!(error.to_string(), "image too large");
+    }
+
+    #[test]
+    fn rejects_short_ppm() {
+        let error = read_bytes("short", b"P6\n2 2\n255\n\0\0\0").err().unwrap();
+        assert_eq!(error.to_string(), "not enough image data");
+    }
+}
@@ -1,13 +1,19 @@
+use crate::aabb::Aabb;
+use crate::light::Light;
+use crate::light::LightSample;
 use crate::material::Constant;
 use crate::material::Material;
 use crate::math::Color;
 use crate::math::Ray;
 use crate::math::Vec3;
+use crate::texture::sphere_uv;
 
+use rand::Rng;
+use std::f32::consts::PI;
 use std::sync::Arc;
 
 #[derive(Clone)]
-pub struct HitRecord {
+pub struct HitRecord<'a> {
     // Intersection point in world coordinates.
     pub point: Vec3,
     // Normal at intersection point in world coordinates.
@@ -16,21 +22,70 @@ pub struct HitRecord {
     pub t: f32,
     // Set to true if the ray hit the front facing.
     pub front_face: bool,
+    // Surface coordinates at the intersection point.
+    pub u: f32,
+    pub v: f32,
 
     pub material: Arc<dyn Material>,
+    // The registered light that was hit, if any.
+    pub light: Option<&'a dyn Light>,
 }
 
 // All the objects we can intersect.
-pub trait Intersectable {
-    fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord) -> bool;
+pub trait Intersectable: Send + Sync {
+    fn intersect<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord<'a>) -> bool;
+
+    // Like intersect, but passes through participating media, which scatter
+    // at random distances. For queries that have to be repeatable, like
+    // autofocus.
+    fn intersect_surface<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> bool {
+        self.intersect(ray, t_min, t_max, hit)
+    }
+
+    // World space box enclosing the object, used to build acceleration
+    // structures.
+    fn bounding_box(&self) -> Aabb;
+
+    // Appends a light for every emissive surface of the object.
+    fn lights(&self, _lights: &mut Vec<Box<dyn Light>>) {}
+
+    // Fraction of the light that gets through the object between t_min and
+    // t_max along the ray, used for shadow rays: zero if a surface is in the
+    // way, anything in between for media. `hit` is scratch space.
+    fn transmittance<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> f32 {
+        if self.intersect(ray, t_min, t_max, hit) {
+            0.0
+        } else {
+            1.0
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct Sphere {
+    // Center at frame time 0, and how far it moves until time 1.
     center: Vec3,
+    motion: Vec3,
     radius: f32,
     material: Arc<dyn Material>,
 }
 
+// Containers are generic over the object type, so that a list of a single
+// concrete type is intersected without dynamic dispatch. Scenes that mix
+// different types use IntersectableList<Box<dyn Intersectable>> instead,
+// ideally inside a Bvh to keep the number of virtual calls per ray low.
 // See: https://bennetthardwick.com/blog/dont-use-boxed-trait-objects-for-struct-internals/
 pub struct IntersectableList<I: Intersectable> {
     objects: Vec<I>,
@@ -39,20 +94,48 @@ pub struct IntersectableList<I: Intersectable> {
 impl Sphere {
     pub fn new(center: Vec3, radius: f32, material: Arc<dyn Material>) -> Sphere {
         Sphere {
-            center: center,
-            radius: radius,
-            material: material,
+            center,
+            motion: Vec3::zero(),
+            radius,
+            material,
         }
     }
+
+    // A sphere moving linearly between two keyframes at frame times 0 and 1.
+    // It stays put before and after.
+    pub fn moving(
+        center0: Vec3,
+        center1: Vec3,
+        radius: f32,
+        material: Arc<dyn Material>,
+    ) -> Sphere {
+        Sphere {
+            center: center0,
+            motion: center1 - center0,
+            radius,
+            material,
+        }
+    }
+
+    fn center(&self, time: f32) -> Vec3 {
+        self.center + self.motion * time.clamp(0.0, 1.0)
+    }
+
+    // Whether the sphere is registered as a light.
+    fn is_light(&self) -> bool {
+        self.material.is_light() && self.motion.near_zero()
+    }
 }
 
-impl HitRecord {
-    pub fn new() -> HitRecord {
+impl<'a> HitRecord<'a> {
+    pub fn new() -> HitRecord<'a> {
         HitRecord {
             point: Vec3::zero(),
             normal: Vec3::zero(),
             t: 0.0,
             front_face: false,
+            u: 0.0,
+            v: 0.0,
             material: Arc::new(Constant {
                 color: Color {
                     r: 1.0,
@@ -60,6 +143,7 @@ impl HitRecord {
                     b: 1.0,
                 },
             }),
+            light: None,
         }
     }
 
@@ -73,8 +157,9 @@ impl HitRecord {
 }
 
 impl Intersectable for Sphere {
-    fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord) -> bool {
-        let oc = ray.origin - self.center;
+    fn intersect<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord<'a>) -> bool {
+        let center = self.center(ray.time);
+        let oc = ray.origin - center;
         let a = ray.direction.mag_squared();
         let half_b = Vec3::dot(&oc, &ray.direction);
         let c = oc.mag_squared() - self.radius * self.radius;
@@ -96,11 +181,86 @@ impl Intersectable for Sphere {
 
         hit.t = root;
         hit.point = ray.at(root);
-        let normal = (hit.point - self.center) / self.radius;
+        let normal = (hit.point - center) / self.radius;
+        (hit.u, hit.v) = sphere_uv(&normal);
         hit.set_face_normal(ray, normal);
         hit.material = self.material.clone();
+        hit.light = if self.is_light() { Some(self) } else { None };
         true
     }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3 {
+            x: self.radius.abs(),
+            y: self.radius.abs(),
+            z: self.radius.abs(),
+        };
+        let end = self.center + self.motion;
+        Aabb::union(
+            &Aabb::new(self.center - r, self.center + r),
+            &Aabb::new(end - r, end + r),
+        )
+    }
+
+    fn lights(&self, lights: &mut Vec<Box<dyn Light>>) {
+        if self.is_light() {
+            lights.push(Box::new(self.clone()));
+        }
+    }
+}
+
+// Samples the cone of directions under which the sphere is seen, which is
+// much better than sampling its area for small or distant spheres.
+impl Light for Sphere {
+    fn sample(&self, origin: &Vec3) -> Option<LightSample> {
+        let to_center = self.center - *origin;
+        let distance_squared = to_center.mag_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return None;
+        }
+        let w = to_center / distance_squared.sqrt();
+        let (u, v) = w.orthonormal_basis();
+
+        let sin_squared_max = radius_squared / distance_squared;
+        let cos_max = (1.0 - sin_squared_max).sqrt();
+        // 1 - cos_max without cancellation for tiny cones.
+        let one_minus_cos_max = sin_squared_max / (1.0 + cos_max);
+
+        let mut rng = rand::thread_rng();
+        let cos_theta = 1.0 - rng.gen_range(0.0..1.0) * one_minus_cos_max;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen_range(0.0..1.0);
+        let direction = u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + w * cos_theta;
+
+        // Nearest intersection along the unit direction.
+        let half_b = -Vec3::dot(&to_center, &direction);
+        let discriminant = (half_b * half_b - distance_squared + radius_squared).max(0.0);
+        Some(LightSample {
+            direction,
+            distance: -half_b - discriminant.sqrt(),
+            pdf: 1.0 / (2.0 * PI * one_minus_cos_max),
+        })
+    }
+
+    fn pdf(&self, origin: &Vec3, direction: &Vec3, max_distance: f32) -> f32 {
+        let ray = Ray {
+            origin: *origin,
+            direction: direction.normalized(),
+            time: 0.0,
+            wavelength: None,
+        };
+        let mut hit = HitRecord::new();
+        if !self.intersect(&ray, 0.0001, max_distance * 1.001, &mut hit) {
+            return 0.0;
+        }
+        let sin_squared_max = self.radius * self.radius / (self.center - *origin).mag_squared();
+        if sin_squared_max >= 1.0 {
+            return 0.0;
+        }
+        let one_minus_cos_max = sin_squared_max / (1.0 + (1.0 - sin_squared_max).sqrt());
+        1.0 / (2.0 * PI * one_minus_cos_max)
+    }
 }
 
 impl<I: Intersectable> IntersectableList<I> {
@@ -113,20 +273,139 @@ impl<I: Intersectable> IntersectableList<I> {
     pub fn add(&mut self, object: I) {
         self.objects.push(object);
     }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
 }
 
 impl<I: Intersectable> Intersectable for IntersectableList<I> {
-    fn intersect(&self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord) -> bool {
+    fn intersect<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord<'a>) -> bool {
+        // Objects only write to the record on a hit closer than `closest`, so
+        // it can be passed on directly.
+        let mut any_hit = false;
+        let mut closest = t_max;
+        for obj in self.objects.iter() {
+            if obj.intersect(ray, t_min, closest, hit) {
+                any_hit = true;
+                closest = hit.t;
+            }
+        }
+        any_hit
+    }
+
+    fn intersect_surface<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> bool {
         let mut any_hit = false;
         let mut closest = t_max;
-        let mut record: HitRecord = HitRecord::new();
         for obj in self.objects.iter() {
-            if obj.intersect(&ray, t_min, closest, &mut record) {
+            if obj.intersect_surface(ray, t_min, closest, hit) {
                 any_hit = true;
-                closest = record.t;
-                *hit = record.clone();
+                closest = hit.t;
             }
         }
         any_hit
     }
+
+    fn bounding_box(&self) -> Aabb {
+        self.objects
+            .iter()
+            .fold(Aabb::empty(), |b, o| Aabb::union(&b, &o.bounding_box()))
+    }
+
+    fn lights(&self, lights: &mut Vec<Box<dyn Light>>) {
+        for obj in self.objects.iter() {
+            obj.lights(lights);
+        }
+    }
+
+    fn transmittance<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> f32 {
+        let mut transmittance = 1.0;
+        for obj in self.objects.iter() {
+            transmittance *= obj.transmittance(ray, t_min, t_max, hit);
+            if transmittance <= 0.0 {
+                break;
+            }
+        }
+        transmittance
+    }
+}
+
+impl<I: Intersectable + ?Sized> Intersectable for Box<I> {
+    fn intersect<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord<'a>) -> bool {
+        (**self).intersect(ray, t_min, t_max, hit)
+    }
+
+    fn intersect_surface<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> bool {
+        (**self).intersect_surface(ray, t_min, t_max, hit)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+
+    fn lights(&self, lights: &mut Vec<Box<dyn Light>>) {
+        (**self).lights(lights)
+    }
+
+    fn transmittance<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> f32 {
+        (**self).transmittance(ray, t_min, t_max, hit)
+    }
+}
+
+impl<I: Intersectable + ?Sized> Intersectable for Arc<I> {
+    fn intersect<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord<'a>) -> bool {
+        (**self).intersect(ray, t_min, t_max, hit)
+    }
+
+    fn intersect_surface<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> bool {
+        (**self).intersect_surface(ray, t_min, t_max, hit)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        (**self).bounding_box()
+    }
+
+    fn lights(&self, lights: &mut Vec<Box<dyn Light>>) {
+        (**self).lights(lights)
+    }
+
+    fn transmittance<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> f32 {
+        (**self).transmittance(ray, t_min, t_max, hit)
+    }
 }
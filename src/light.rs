@@ -0,0 +1,76 @@
+// Lights are the emissive surfaces of the scene that can be sampled directly.
+// They are registered in addition to being part of the world: the shape is
+// still intersected as usual, its light only provides the sampling. Only
+// spheres that don't move and triangles are registered, emitters inside
+// transformed objects are found by BSDF sampling alone.
+
+use rand::Rng;
+
+use crate::intersection::Intersectable;
+use crate::math::Vec3;
+
+pub struct LightSample {
+    // Unit vector from the shaded point towards the sampled point.
+    pub direction: Vec3,
+    pub distance: f32,
+    // With respect to solid angle at the shaded point.
+    pub pdf: f32,
+}
+
+pub trait Light: Intersectable {
+    // Picks a point on the light as seen from `origin`. None if there is
+    // nothing to sample, e.g. from inside a spherical light.
+    fn sample(&self, origin: &Vec3) -> Option<LightSample>;
+
+    // Density with which `sample` picks `direction`, counting only the part
+    // of the light that is hit no further than `max_distance` away.
+    fn pdf(&self, origin: &Vec3, direction: &Vec3, max_distance: f32) -> f32;
+}
+
+// All lights of a scene, picked uniformly.
+pub struct LightList {
+    lights: Vec<Box<dyn Light>>,
+}
+
+impl LightList {
+    pub fn new(lights: Vec<Box<dyn Light>>) -> LightList {
+        LightList { lights }
+    }
+
+    // Also returns the light that was picked.
+    pub fn sample(&self, origin: &Vec3) -> Option<(&dyn Light, LightSample)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let light = &*self.lights[rand::thread_rng().gen_range(0..self.lights.len())];
+        let mut sample = light.sample(origin)?;
+        sample.pdf /= self.lights.len() as f32;
+        Some((light, sample))
+    }
+
+    // Density with which `sample` picks `direction` through `light`, one of
+    // the lights in the list.
+    pub fn pdf(
+        &self,
+        light: &dyn Light,
+        origin: &Vec3,
+        direction: &Vec3,
+        max_distance: f32,
+    ) -> f32 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+        light.pdf(origin, direction, max_distance) / self.lights.len() as f32
+    }
+}
+
+// Veach's power heuristic with an exponent of 2, the weight of a sample taken
+// with density `pdf` when `other_pdf` could also have produced it.
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
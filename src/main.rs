@@ -1,85 +1,217 @@
+mod aabb;
+mod aov;
+mod background;
+mod benchmark;
+mod bvh;
 mod camera;
+mod cli;
+mod exr;
+mod grid;
+mod hdr;
 mod image;
 mod intersection;
+mod light;
 mod material;
 mod math;
+mod mesh;
+mod microfacet;
+mod obj;
+mod pfm;
+mod png;
+mod scene;
+mod spectrum;
+mod texture;
+mod toml;
+mod tonemap;
+mod transform;
+mod volume;
+mod zlib;
 
 use math::Color;
 use math::Ray;
-use math::Vec3;
 
 use rand::Rng;
 
-use std::sync::Arc;
-
 use crate::intersection::Intersectable;
-use crate::intersection::IntersectableList;
+use background::Background;
 use camera::Camera;
 use intersection::HitRecord;
-use intersection::Sphere;
+use light::power_heuristic;
+use light::LightList;
+use material::Material;
+use scene::RenderSettings;
+use scene::Scene;
+use spectrum::PathWavelengths;
+use spectrum::SampledSpectrum;
 
 extern crate scoped_threadpool;
 use scoped_threadpool::Pool;
 
-struct RenderSettings {
-    samples_per_pixel: u32,
-    max_recursion_depth: u32,
-    image_gamma: f32,
-    render_threads: u32,
-}
+fn ray_color<I: Intersectable>(
+    ray: &Ray,
+    world: &I,
+    lights: &LightList,
+    background: &dyn Background,
+    max_depth: u32,
+    spectral: bool,
+) -> Color {
+    let wavelengths = PathWavelengths::sample(spectral);
+    let mut color = SampledSpectrum::zero();
+    // Product of the sample weights along the path so far.
+    let mut throughput = SampledSpectrum::one();
+    let mut ray = *ray;
+    // Density with which the previous bounce picked `ray`. None for camera
+    // rays and after bounces without light sampling, whose emission hits
+    // count fully.
+    let mut scatter_pdf = None;
 
-fn ray_color(ray: &Ray, world: &IntersectableList<Sphere>, depth: u32) -> Color {
-    let mut hit_record = HitRecord::new();
+    for _ in 0..max_depth {
+        let mut hit_record = HitRecord::new();
+        if !world.intersect(&ray, 0.0001, 10000.0, &mut hit_record) {
+            color += throughput * wavelengths.color(&background.color(&ray.direction));
+            break;
+        }
+        let material = &*hit_record.material;
 
-    if depth <= 0 {
-        return Color::black();
-    }
+        // Multiple importance sampling: lights found by BSDF sampling are
+        // weighted against the chance of having sampled them directly.
+        let emitted = material.emitted(&ray, &hit_record);
+        if !emitted.is_black() {
+            let mut emitted = material_spectrum(&wavelengths, material, &emitted);
+            if let (Some(pdf), Some(light)) = (scatter_pdf, hit_record.light) {
+                let distance = hit_record.t * ray.direction.mag();
+                let light_pdf = lights.pdf(light, &ray.origin, &ray.direction, distance);
+                emitted *= power_heuristic(pdf, light_pdf);
+            }
+            color += throughput * emitted;
+        }
 
-    if world.intersect(ray, 0.0001, 10000.0, &mut hit_record) {
-        let mut scattered = Ray {
-            origin: Vec3::zero(),
-            direction: Vec3::zero(),
+        // The path continues with a single wavelength from here on, whose
+        // color stands in for the full spectrum.
+        if ray.wavelength.is_none() && material.is_dispersive() {
+            ray.wavelength = Some(wavelengths.single(&mut throughput));
+        }
+
+        // Light sampling doesn't depend on the BSDF sample, which may fail.
+        color += throughput * sample_light(&ray, &hit_record, world, lights, &wavelengths);
+
+        let sample = match material.sample(&ray, &hit_record) {
+            Some(sample) => sample,
+            None => break,
+        };
+        // Specular lobes can't be found by light sampling.
+        scatter_pdf = if sample.lobe.is_specular() {
+            None
+        } else {
+            Some(sample.pdf)
         };
-        let mut attenuation = Color::black();
 
-        if hit_record
-            .material
-            .scatter(ray, &hit_record, &mut attenuation, &mut scattered)
-        {
-            return attenuation * ray_color(&scattered, &world, depth - 1);
-        }
+        throughput *= material_spectrum(&wavelengths, material, &sample.weight());
+        ray = Ray {
+            origin: hit_record.point,
+            direction: sample.direction,
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+    }
 
-        return Color::black();
+    wavelengths.rgb(&color)
+}
 
-        //let target = hit_record.point + hit_record.normal + Vec3::random_in_unit_sphere().normalized();
-        //return ray_color(&Ray{origin: hit_record.point, direction: target - hit_record.point}, world, depth-1) * 0.5
+// A color returned by `material` at the wavelengths of the path, filtered by
+// the material's spectrum if it has one.
+fn material_spectrum(
+    wavelengths: &PathWavelengths,
+    material: &dyn Material,
+    color: &Color,
+) -> SampledSpectrum {
+    let value = wavelengths.color(color);
+    match material.spectrum() {
+        Some(spectrum) => value * wavelengths.spectrum(spectrum),
+        None => value,
     }
+}
 
-    // Background color
-    let unit_direction = ray.direction.normalized();
-    let t = 0.5 * (unit_direction.y + 1.0);
-    let white = Color {
-        r: 1.0,
-        g: 1.0,
-        b: 1.0,
+// Next event estimation: light arriving at the hit point directly from one
+// sampled point on a light, weighted against BSDF sampling.
+fn sample_light<I: Intersectable>(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    world: &I,
+    lights: &LightList,
+    wavelengths: &PathWavelengths,
+) -> SampledSpectrum {
+    let (light, sample) = match lights.sample(&hit_record.point) {
+        Some(sample) => sample,
+        None => return SampledSpectrum::zero(),
     };
-    let blueish = Color {
-        r: 0.5,
-        g: 0.7,
-        b: 1.0,
+    let material = &*hit_record.material;
+    let f = material.eval(ray, hit_record, &sample.direction);
+    if f.luminance() <= 0.0 {
+        return SampledSpectrum::zero();
+    }
+
+    // The shadow ray has to reach the sampled point, dimmed by any media on
+    // the way. The light itself provides the emission there.
+    let shadow_ray = Ray {
+        origin: hit_record.point,
+        direction: sample.direction,
+        time: ray.time,
+        wavelength: ray.wavelength,
     };
+    let mut light_hit = HitRecord::new();
+    let transmittance =
+        world.transmittance(&shadow_ray, 0.0001, sample.distance * 0.999, &mut light_hit);
+    if transmittance <= 0.0
+        || !light.intersect(
+            &shadow_ray,
+            sample.distance * 0.999,
+            sample.distance * 1.001,
+            &mut light_hit,
+        )
+    {
+        return SampledSpectrum::zero();
+    }
 
-    Color::lerp(&white, &blueish, t)
+    let light_material = &*light_hit.material;
+    let emitted = light_material.emitted(&shadow_ray, &light_hit);
+    let bsdf_pdf = material.pdf(ray, hit_record, &sample.direction);
+    material_spectrum(wavelengths, material, &f)
+        * material_spectrum(wavelengths, light_material, &emitted)
+        * (transmittance * power_heuristic(sample.pdf, bsdf_pdf) / sample.pdf)
 }
 
-fn trace(
+fn trace<I: Intersectable>(
     image: &mut image::Image,
-    camera: &Camera,
-    world: &IntersectableList<Sphere>,
+    camera: &dyn Camera,
+    world: &I,
+    lights: &LightList,
+    background: &dyn Background,
     render_settings: &RenderSettings,
+) {
+    render_pass(image, camera, render_settings, |ray| {
+        ray_color(
+            ray,
+            world,
+            lights,
+            background,
+            render_settings.max_recursion_depth,
+            render_settings.spectral,
+        )
+    });
+}
+
+// Renders the image by averaging `sample` over jittered camera rays through
+// each pixel.
+fn render_pass<F: Fn(&Ray) -> Color + Sync>(
+    image: &mut image::Image,
+    camera: &dyn Camera,
+    render_settings: &RenderSettings,
+    sample: F,
 ) {
     let image_w = image.width();
     let image_h = image.height();
+    let sample = &sample;
 
     // Threading
     let mut pool = Pool::new(render_settings.render_threads);
@@ -103,14 +235,14 @@ fn trace(
                         for _sample in 0..render_settings.samples_per_pixel {
                             let coord = tile.tile_to_image_coordinates(i, j);
 
-                            let u =
-                                (coord.0 as f32 + rng.gen_range(0.0..1.0)) / (image_w as f32 - 1.0);
-                            let v = 1.0
-                                - (coord.1 as f32 + rng.gen_range(0.0..1.0))
-                                    / (image_h as f32 - 1.0);
+                            let u = (coord.0 as f32 + rng.gen_range(0.0..1.0)) / image_w as f32;
+                            let v =
+                                1.0 - (coord.1 as f32 + rng.gen_range(0.0..1.0)) / image_h as f32;
 
-                            let ray = camera.get_ray(u, v);
-                            color += ray_color(&ray, &world, render_settings.max_recursion_depth);
+                            // Points outside of the projection stay black.
+                            if let Some(ray) = camera.get_ray(u, v) {
+                                color += sample(&ray);
+                            }
                         }
 
                         tile.image.put_pixel(
@@ -129,97 +261,81 @@ fn trace(
     }
 }
 
-fn main() {
-    // Image
-    let mut image = image::Image::new(1024, 1024);
-
-    let render_settings = RenderSettings {
-        samples_per_pixel: 50,
-        max_recursion_depth: 12,
-        image_gamma: 2.0,
-        render_threads: 16,
-    };
+fn run(options: cli::Options) -> Result<(), String> {
+    let output_format = options.output_format()?;
+    if !options.aovs.is_empty() && output_format.exr_pixel_type().is_none() {
+        return Err("extra channels can only be written to EXR files".to_string());
+    }
+
+    let mut scene =
+        Scene::load(&options.scene_path).map_err(|e| format!("{}: {}", options.scene_path, e))?;
+    options.apply(&mut scene)?;
 
-    // World
-    let mut world = IntersectableList::<Sphere>::new();
-    world.add(Sphere::new(
-        Vec3 {
-            x: -0.5,
-            y: 0.0,
-            z: -1.0,
-        },
-        0.25,
-        Arc::new(material::Dielectric {
-            index_of_refraction: 1.5,
-        }),
-    ));
-    world.add(Sphere::new(
-        Vec3 {
-            x: -0.0,
-            y: 0.0,
-            z: -1.0,
-        },
-        0.25,
-        Arc::new(material::Lambertian {
-            albedo: Color {
-                r: 0.1,
-                g: 0.2,
-                b: 0.5,
-            },
-        }),
-    ));
-    world.add(Sphere::new(
-        Vec3 {
-            x: 0.5,
-            y: 0.0,
-            z: -1.0,
-        },
-        0.25,
-        Arc::new(material::Metal {
-            albedo: Color {
-                r: 0.8,
-                g: 0.6,
-                b: 0.2,
-            },
-            roughness: 0.1,
-        }),
-    ));
-    world.add(Sphere::new(
-        Vec3 {
-            x: 0.0,
-            y: -100.25,
-            z: -1.0,
-        },
-        100.0,
-        Arc::new(material::Lambertian {
-            albedo: Color {
-                r: 0.8,
-                g: 0.8,
-                b: 0.3,
-            },
-        }),
-    ));
+    // Image. The size may still be too large with only one of the dimensions
+    // given on the command line.
+    image::Image::check_size(scene.image_width, scene.image_height)
+        .map_err(|e| format!("{}x{} pixels: {}", scene.image_width, scene.image_height, e))?;
+    let mut image = image::Image::new(scene.image_width, scene.image_height);
 
     // Camera
-    let camera = Camera::new(
-        Vec3 {
-            x: -2.0,
-            y: 2.0,
-            z: 1.0,
-        },
-        Vec3 {
-            x: 0.0,
-            y: 0.0,
-            z: -1.0,
-        },
-        Vec3::up(),
-        60.0,
-        image.aspect_ratio(),
+    let camera = scene.camera.build(image.aspect_ratio(), &scene.world);
+
+    trace(
+        &mut image,
+        camera.as_ref(),
+        &scene.world,
+        &scene.lights,
+        scene.background.as_ref(),
+        &scene.render_settings,
     );
 
-    trace(&mut image, &camera, &world, &render_settings);
+    // File output. Linear formats get the radiance as is.
+    let result = match output_format.exr_pixel_type() {
+        Some(pixel_type) if !options.aovs.is_empty() => {
+            let mut channels = vec![];
+            for aov in &options.aovs {
+                let mut aov_image = image::Image::new(scene.image_width, scene.image_height);
+                render_pass(
+                    &mut aov_image,
+                    camera.as_ref(),
+                    &scene.render_settings,
+                    |ray| aov.sample(ray, &scene.world),
+                );
+                channels.extend(aov.channels(&aov_image));
+            }
+            image.write_exr(&options.output_path, pixel_type, channels)
+        }
+        _ => {
+            if !output_format.is_linear() {
+                let settings = &scene.render_settings;
+                image.tone_map(settings.exposure, settings.tone_mapping);
+                image.encode(settings.image_encoding);
+            }
+            image.write(&options.output_path, output_format)
+        }
+    };
+    result.map_err(|e| format!("{}: {}", options.output_path, e))
+}
 
-    // File output.
-    image.gamma_correct(render_settings.image_gamma);
-    image.write_ppm("output.ppm".to_string());
+fn main() {
+    let options = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(cli::Command::Render(options)) => options,
+        Ok(cli::Command::Benchmark(options)) => {
+            benchmark::run(&options);
+            return;
+        }
+        Ok(cli::Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {}\nRun with --help for usage.", e);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(options) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -2,17 +2,63 @@ use crate::intersection::HitRecord;
 use crate::math::Color;
 use crate::math::Ray;
 use crate::math::Vec3;
+use crate::microfacet::fresnel_conductor;
+use crate::microfacet::fresnel_dielectric;
+use crate::microfacet::fresnel_schlick;
+use crate::microfacet::reflect;
+use crate::microfacet::refract;
+use crate::microfacet::Ggx;
+use crate::microfacet::ShadingFrame;
+use crate::spectrum::Dispersion;
+use crate::spectrum::Spectrum;
+use crate::texture::Texture;
 
 use rand::Rng;
+use std::f32::consts::PI;
+use std::ops;
+use std::sync::Arc;
 
+// Surfaces are described by their BSDF. All directions point away from the
+// surface: light leaves towards the ray origin and arrives from the sampled
+// or evaluated `direction`.
 pub trait Material: MaterialClone + Send + Sync {
-    fn scatter(
-        &self,
-        ray_in: &Ray,
-        hit: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool;
+    // Picks a direction for the next ray of the path. None if the path ends
+    // here, e.g. on lights or when the ray is absorbed.
+    fn sample(&self, ray_in: &Ray, hit: &HitRecord) -> Option<BsdfSample>;
+
+    // BSDF times the cosine of the angle to the normal. Specular lobes are
+    // left out since they can't be hit by an arbitrary direction.
+    fn eval(&self, _ray_in: &Ray, _hit: &HitRecord, _direction: &Vec3) -> Color {
+        Color::black()
+    }
+
+    // Solid angle density with which `sample` picks `direction`, again
+    // without specular lobes.
+    fn pdf(&self, _ray_in: &Ray, _hit: &HitRecord, _direction: &Vec3) -> f32 {
+        0.0
+    }
+
+    // Light emitted from the hit point towards the ray origin.
+    fn emitted(&self, _ray_in: &Ray, _hit: &HitRecord) -> Color {
+        Color::black()
+    }
+
+    // Whether surfaces with this material are sampled as lights.
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    // Whether the material depends on the wavelength of the ray. Paths
+    // hitting it get one assigned first.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+
+    // Measured spectrum that filters everything the material reflects and
+    // emits, applied by the tracer at the wavelengths of the path.
+    fn spectrum(&self) -> Option<&Spectrum> {
+        None
+    }
 }
 
 // See: https://stackoverflow.com/questions/30353462/how-to-clone-a-struct-storing-a-boxed-trait-object
@@ -20,25 +66,194 @@ pub trait MaterialClone {
     fn clone_box(&self) -> Box<dyn Material>;
 }
 
+// Flags describing the lobe a sample was taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lobe(u8);
+
+pub struct BsdfSample {
+    // Unit vector away from the surface.
+    pub direction: Vec3,
+    // BSDF times cosine, as returned by eval for non-specular lobes.
+    pub value: Color,
+    // Solid angle density of the direction. Specular lobes have a delta
+    // distribution instead; their pdf is the probability of picking the lobe,
+    // with the value scaled to match, so that value / pdf is always the
+    // weight of the sample.
+    pub pdf: f32,
+    pub lobe: Lobe,
+}
+
+// Unlit material that shows its color regardless of lighting.
 #[derive(Clone, Copy)]
 pub struct Constant {
     pub color: Color,
 }
 
+// Area light. Only emits from the front face.
 #[derive(Clone, Copy)]
+pub struct DiffuseLight {
+    pub emit: Color,
+}
+
+#[derive(Clone)]
 pub struct Lambertian {
-    pub albedo: Color,
+    pub albedo: Arc<dyn Texture>,
 }
 
-#[derive(Clone, Copy)]
+// Mirror reflection, blurred by offsetting the reflected direction with a
+// random point in a sphere of radius `roughness`.
+#[derive(Clone)]
 pub struct Metal {
-    pub albedo: Color,
-    pub roughness: f32,
+    pub albedo: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
 }
 
-#[derive(Clone, Copy)]
+// GGX microfacet metal with a complex index of refraction `eta` + i`k`, per
+// color channel. See CONDUCTORS for measured values.
+#[derive(Clone)]
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color,
+    pub roughness: Arc<dyn Texture>,
+}
+
+// Glass and other transparent materials, frosted if `roughness` is above
+// zero. With `dispersion` the index of refraction depends on the wavelength,
+// `index_of_refraction` is then only used by rays without one. Light
+// travelling through the inside is absorbed following the Beer-Lambert law
+// with the coefficients `absorption`, per unit distance and color channel;
+// black for clear glass.
+#[derive(Clone)]
 pub struct Dielectric {
     pub index_of_refraction: f32,
+    pub roughness: Arc<dyn Texture>,
+    pub absorption: Color,
+    pub dispersion: Option<Dispersion>,
+}
+
+// Disney style "principled" material after Burley 2012 and 2015, "Physically
+// Based Shading at Disney" and "Extending the Disney BRDF to a BSDF with
+// Integrated Subsurface Scattering". A diffuse base with sheen, a GGX
+// specular layer that turns into a metal with `metallic`, rough glass with
+// `transmission` and a clearcoat on top. Parameters are in [0, 1].
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    // Reflectance of the dielectric specular layer; 0.5 is 4%, the
+    // reflectance of most common materials.
+    pub specular: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    // How much the sheen takes on the base color instead of white.
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_roughness: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub index_of_refraction: f32,
+    // Emitted from the front like DiffuseLight. None for surfaces that don't
+    // glow, which keeps them out of the light list.
+    pub emission: Option<Arc<dyn Texture>>,
+    pub emission_strength: f32,
+}
+
+// Filters another material with a measured spectrum, e.g. a white
+// Lambertian with the reflectance of a paint sample or a white light with the
+// emission of a lamp. RGB renders use the spectrum's color instead.
+#[derive(Clone)]
+pub struct SpectralTint {
+    pub material: Arc<dyn Material>,
+    pub spectrum: Arc<Spectrum>,
+}
+
+// Indices of refraction (eta, k) of common metals at the red, green and blue
+// wavelengths.
+pub const CONDUCTORS: [(&str, Color, Color); 4] = [
+    (
+        "gold",
+        Color {
+            r: 0.143119,
+            g: 0.374957,
+            b: 1.44248,
+        },
+        Color {
+            r: 3.98316,
+            g: 2.38572,
+            b: 1.60322,
+        },
+    ),
+    (
+        "copper",
+        Color {
+            r: 0.200438,
+            g: 0.924033,
+            b: 1.10221,
+        },
+        Color {
+            r: 3.91295,
+            g: 2.45285,
+            b: 2.14219,
+        },
+    ),
+    (
+        "aluminium",
+        Color {
+            r: 1.65746,
+            g: 0.880369,
+            b: 0.521229,
+        },
+        Color {
+            r: 9.22387,
+            g: 6.26952,
+            b: 4.837,
+        },
+    ),
+    (
+        "silver",
+        Color {
+            r: 0.155265,
+            g: 0.116723,
+            b: 0.138342,
+        },
+        Color {
+            r: 4.82835,
+            g: 3.12225,
+            b: 2.14696,
+        },
+    ),
+];
+
+impl Lobe {
+    pub const DIFFUSE: Lobe = Lobe(1);
+    pub const GLOSSY: Lobe = Lobe(2);
+    pub const SPECULAR: Lobe = Lobe(4);
+    pub const REFLECTION: Lobe = Lobe(8);
+    pub const TRANSMISSION: Lobe = Lobe(16);
+    // Scattering by the particles of a medium.
+    pub const VOLUME: Lobe = Lobe(32);
+
+    pub fn contains(self, other: Lobe) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_specular(self) -> bool {
+        self.contains(Lobe::SPECULAR)
+    }
+}
+
+impl ops::BitOr for Lobe {
+    type Output = Lobe;
+
+    fn bitor(self, other: Lobe) -> Lobe {
+        Lobe(self.0 | other.0)
+    }
+}
+
+impl BsdfSample {
+    // Factor by which the sample scales the light carried along the path.
+    pub fn weight(&self) -> Color {
+        self.value / self.pdf
+    }
 }
 
 fn get_scatter_direction(normal: Vec3) -> Vec3 {
@@ -65,91 +280,765 @@ impl Clone for Box<dyn Material> {
 }
 
 impl Material for Constant {
-    fn scatter(
-        &self,
-        _ray_in: &Ray,
-        _hit: &HitRecord,
-        attenuation: &mut Color,
-        _scattered: &mut Ray,
-    ) -> bool {
-        *attenuation = self.color;
-        false
+    fn sample(&self, _ray_in: &Ray, _hit: &HitRecord) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, _ray_in: &Ray, _hit: &HitRecord) -> Color {
+        self.color
     }
 }
 
-impl Material for Lambertian {
-    fn scatter(
-        &self,
-        _ray_in: &Ray,
-        hit: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        let scatter_direction = get_scatter_direction(hit.normal);
-        scattered.origin = hit.point;
-        scattered.direction = scatter_direction;
-        *attenuation = self.albedo;
+impl Material for DiffuseLight {
+    fn sample(&self, _ray_in: &Ray, _hit: &HitRecord) -> Option<BsdfSample> {
+        None
+    }
+
+    fn emitted(&self, _ray_in: &Ray, hit: &HitRecord) -> Color {
+        if hit.front_face {
+            self.emit
+        } else {
+            Color::black()
+        }
+    }
+
+    fn is_light(&self) -> bool {
         true
     }
 }
 
+impl Material for SpectralTint {
+    fn sample(&self, ray_in: &Ray, hit: &HitRecord) -> Option<BsdfSample> {
+        self.material.sample(ray_in, hit)
+    }
+
+    fn eval(&self, ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> Color {
+        self.material.eval(ray_in, hit, direction)
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        self.material.pdf(ray_in, hit, direction)
+    }
+
+    fn emitted(&self, ray_in: &Ray, hit: &HitRecord) -> Color {
+        self.material.emitted(ray_in, hit)
+    }
+
+    fn is_light(&self) -> bool {
+        self.material.is_light()
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.material.is_dispersive()
+    }
+
+    fn spectrum(&self) -> Option<&Spectrum> {
+        Some(&self.spectrum)
+    }
+}
+
+impl Material for Lambertian {
+    // Cosine weighted, see get_scatter_direction.
+    fn sample(&self, ray_in: &Ray, hit: &HitRecord) -> Option<BsdfSample> {
+        let direction = get_scatter_direction(hit.normal).normalized();
+        // Exactly tangent directions have zero density.
+        let pdf = self.pdf(ray_in, hit, &direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction,
+            value: self.eval(ray_in, hit, &direction),
+            pdf,
+            lobe: Lobe::DIFFUSE | Lobe::REFLECTION,
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> Color {
+        let albedo = self.albedo.value(hit.u, hit.v, &hit.point, &hit.normal);
+        albedo * self.pdf(ray_in, hit, direction)
+    }
+
+    fn pdf(&self, _ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        Vec3::dot(&direction.normalized(), &hit.normal).max(0.0) / PI
+    }
+}
+
+impl Metal {
+    fn roughness(&self, hit: &HitRecord) -> f32 {
+        self.roughness
+            .value(hit.u, hit.v, &hit.point, &hit.normal)
+            .luminance()
+            .max(0.0)
+    }
+
+    // Density of the direction of `reflected + roughness * p` for p uniform
+    // in the unit ball: the volume of the cone through the ball along
+    // `direction`, over the volume of the ball. Directions below the surface
+    // are absorbed.
+    fn fuzz_pdf(reflected: &Vec3, roughness: f32, hit: &HitRecord, direction: &Vec3) -> f32 {
+        let direction = direction.normalized();
+        if Vec3::dot(&direction, &hit.normal) <= 0.0 {
+            return 0.0;
+        }
+        // The chord of the ball along `direction` runs from t0 to t1.
+        let b = Vec3::dot(&direction, reflected);
+        let discriminant = b * b - 1.0 + roughness * roughness;
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let half_chord = discriminant.sqrt();
+        let t1 = b + half_chord;
+        if t1 <= 0.0 {
+            return 0.0;
+        }
+        let t0 = (b - half_chord).max(0.0);
+        // (t1^3 - t0^3) / 3 without cancellation for thin chords.
+        let volume = (t1 - t0) * (t1 * t1 + t1 * t0 + t0 * t0) / 3.0;
+        volume / (4.0 / 3.0 * PI * roughness * roughness * roughness)
+    }
+}
+
 impl Material for Metal {
-    fn scatter(
-        &self,
-        ray_in: &Ray,
-        hit: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
+    fn sample(&self, ray_in: &Ray, hit: &HitRecord) -> Option<BsdfSample> {
         let reflected = Vec3::reflect(&ray_in.direction.normalized(), &hit.normal);
-        scattered.origin = hit.point;
-        scattered.direction = reflected + Vec3::random_in_unit_sphere() * self.roughness;
-        *attenuation = self.albedo;
-        Vec3::dot(&scattered.direction, &hit.normal) > 0.0
+        let albedo = self.albedo.value(hit.u, hit.v, &hit.point, &hit.normal);
+        let roughness = self.roughness(hit);
+        if roughness <= 0.0 {
+            return Some(BsdfSample {
+                direction: reflected,
+                value: albedo,
+                pdf: 1.0,
+                lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+            });
+        }
+
+        let direction = (reflected + Vec3::random_in_unit_sphere() * roughness).normalized();
+        let pdf = Metal::fuzz_pdf(&reflected, roughness, hit, &direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction,
+            value: albedo * pdf,
+            pdf,
+            lobe: Lobe::GLOSSY | Lobe::REFLECTION,
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> Color {
+        let albedo = self.albedo.value(hit.u, hit.v, &hit.point, &hit.normal);
+        albedo * self.pdf(ray_in, hit, direction)
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        let roughness = self.roughness(hit);
+        if roughness <= 0.0 {
+            return 0.0;
+        }
+        let reflected = Vec3::reflect(&ray_in.direction.normalized(), &hit.normal);
+        Metal::fuzz_pdf(&reflected, roughness, hit, direction)
+    }
+}
+
+impl Conductor {
+    fn ggx(&self, hit: &HitRecord) -> Option<Ggx> {
+        let roughness = self.roughness.value(hit.u, hit.v, &hit.point, &hit.normal);
+        Ggx::from_roughness(roughness.luminance())
+    }
+
+    fn eval_local(&self, ggx: &Ggx, wo: &Vec3, wi: &Vec3) -> Color {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::black();
+        }
+        let h = (*wo + *wi).normalized();
+        let fresnel = fresnel_conductor(Vec3::dot(wo, &h), &self.eta, &self.k);
+        fresnel * (ggx.d(&h) * ggx.g(wo, wi) / (4.0 * wo.z))
+    }
+
+    fn pdf_local(ggx: &Ggx, wo: &Vec3, wi: &Vec3) -> f32 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let h = (*wo + *wi).normalized();
+        ggx.visible_normal_pdf(wo, &h) / (4.0 * Vec3::dot(wo, &h))
+    }
+}
+
+impl Material for Conductor {
+    fn sample(&self, ray_in: &Ray, hit: &HitRecord) -> Option<BsdfSample> {
+        let frame = ShadingFrame::new(&hit.normal);
+        let wo = frame.to_local(&(ray_in.direction.normalized() * -1.0));
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let ggx = match self.ggx(hit) {
+            Some(ggx) => ggx,
+            None => {
+                return Some(BsdfSample {
+                    direction: frame.to_world(&reflect(&wo, &Vec3::up_z())),
+                    value: fresnel_conductor(wo.z, &self.eta, &self.k),
+                    pdf: 1.0,
+                    lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+                })
+            }
+        };
+
+        let h = ggx.sample_visible_normal(&wo);
+        let wi = reflect(&wo, &h);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        let pdf = Conductor::pdf_local(&ggx, &wo, &wi);
+        if !pdf.is_finite() || pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction: frame.to_world(&wi),
+            value: self.eval_local(&ggx, &wo, &wi),
+            pdf,
+            lobe: Lobe::GLOSSY | Lobe::REFLECTION,
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> Color {
+        match self.ggx(hit) {
+            Some(ggx) => {
+                let frame = ShadingFrame::new(&hit.normal);
+                let wo = frame.to_local(&(ray_in.direction.normalized() * -1.0));
+                self.eval_local(&ggx, &wo, &frame.to_local(&direction.normalized()))
+            }
+            None => Color::black(),
+        }
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        match self.ggx(hit) {
+            Some(ggx) => {
+                let frame = ShadingFrame::new(&hit.normal);
+                let wo = frame.to_local(&(ray_in.direction.normalized() * -1.0));
+                Conductor::pdf_local(&ggx, &wo, &frame.to_local(&direction.normalized()))
+            }
+            None => 0.0,
+        }
     }
 }
 
+// Rough dielectrics follow Walter et al. 2007, "Microfacet Models for
+// Refraction through Rough Surfaces". Transmitted radiance is scaled by
+// 1/eta^2 as it gets compressed into a smaller solid angle.
 impl Dielectric {
-    fn reflectance(cosine: f32, ref_index: f32) -> f32 {
-        // Schlick's reflectance approximation.
-        let mut r0 = (1.0 - ref_index) / (1.0 + ref_index);
-        r0 = r0 * r0;
-        r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
+    // Index of refraction behind the surface relative to the side of the
+    // incoming ray.
+    fn relative_eta(&self, ray_in: &Ray, hit: &HitRecord) -> f32 {
+        let index_of_refraction = match (&self.dispersion, ray_in.wavelength) {
+            (Some(dispersion), Some(lambda)) => dispersion.index_of_refraction(lambda),
+            _ => self.index_of_refraction,
+        };
+        if hit.front_face {
+            index_of_refraction
+        } else {
+            1.0 / index_of_refraction
+        }
+    }
+
+    fn ggx(&self, hit: &HitRecord) -> Option<Ggx> {
+        let roughness = self.roughness.value(hit.u, hit.v, &hit.point, &hit.normal);
+        Ggx::from_roughness(roughness.luminance())
+    }
+
+    // Absorption coefficients that leave `color` of the light after it
+    // travelled a distance of 1 / `density`.
+    pub fn absorption(color: &Color, density: f32) -> Color {
+        color.map(|c| -c.clamp(1e-6, 1.0).ln() * density)
+    }
+
+    // Fraction of the light that made it through the inside on its way to
+    // `hit`. Rays only reach the back of a surface from the inside, so the
+    // distance travelled is the length of the ray up to the hit.
+    fn transmittance(&self, ray_in: &Ray, hit: &HitRecord) -> Color {
+        if hit.front_face {
+            return Color::white();
+        }
+        let distance = hit.t * ray_in.direction.mag();
+        self.absorption.map(|a| (-a * distance).exp())
+    }
+
+    // The microfacet normal that reflects or refracts `wo` into `wi`, and the
+    // relative index of refraction along the way. None if it faces away from
+    // either direction.
+    fn half_vector(wo: &Vec3, wi: &Vec3, eta: f32) -> Option<(Vec3, f32)> {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return None;
+        }
+        let eta = if wi.z > 0.0 { 1.0 } else { eta };
+        let mut h = *wi * eta + *wo;
+        if h.mag_squared() == 0.0 {
+            return None;
+        }
+        h = h.normalized();
+        if h.z < 0.0 {
+            h *= -1.0;
+        }
+        if Vec3::dot(&h, wi) * wi.z < 0.0 || Vec3::dot(&h, wo) <= 0.0 {
+            return None;
+        }
+        Some((h, eta))
+    }
+
+    fn eval_local(ggx: &Ggx, wo: &Vec3, wi: &Vec3, eta: f32) -> Color {
+        let (h, etap) = match Dielectric::half_vector(wo, wi, eta) {
+            Some(half) => half,
+            None => return Color::black(),
+        };
+        let reflectance = fresnel_dielectric(Vec3::dot(wo, &h), eta);
+        let d_g = ggx.d(&h) * ggx.g(wo, wi);
+        let value = if wi.z > 0.0 {
+            d_g * reflectance / (4.0 * wo.z)
+        } else {
+            let denominator = (Vec3::dot(wi, &h) + Vec3::dot(wo, &h) / etap).powi(2);
+            let f = (1.0 - reflectance) * d_g * Vec3::dot(wi, &h) * Vec3::dot(wo, &h)
+                / (wi.z * wo.z * denominator);
+            f.abs() * wi.z.abs() / (etap * etap)
+        };
+        Color::white() * value
+    }
+
+    fn pdf_local(ggx: &Ggx, wo: &Vec3, wi: &Vec3, eta: f32) -> f32 {
+        let (h, etap) = match Dielectric::half_vector(wo, wi, eta) {
+            Some(half) => half,
+            None => return 0.0,
+        };
+        let reflectance = fresnel_dielectric(Vec3::dot(wo, &h), eta);
+        let pdf = ggx.visible_normal_pdf(wo, &h);
+        if wi.z > 0.0 {
+            pdf / (4.0 * Vec3::dot(wo, &h)) * reflectance
+        } else {
+            let denominator = (Vec3::dot(wi, &h) + Vec3::dot(wo, &h) / etap).powi(2);
+            pdf * Vec3::dot(wi, &h).abs() / denominator * (1.0 - reflectance)
+        }
     }
 }
 
 impl Material for Dielectric {
-    fn scatter(
-        &self,
-        ray_in: &Ray,
-        hit: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
-        *attenuation = Color::white();
-        let refraction_ratio = if hit.front_face {
-            1.0 / self.index_of_refraction
+    // Reflects or refracts with the probability given by the Fresnel term.
+    fn sample(&self, ray_in: &Ray, hit: &HitRecord) -> Option<BsdfSample> {
+        let frame = ShadingFrame::new(&hit.normal);
+        let wo = frame.to_local(&(ray_in.direction.normalized() * -1.0));
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let eta = self.relative_eta(ray_in, hit);
+        let ggx = self.ggx(hit);
+        let h = match &ggx {
+            Some(ggx) => ggx.sample_visible_normal(&wo),
+            None => Vec3::up_z(),
+        };
+
+        let reflectance = fresnel_dielectric(Vec3::dot(&wo, &h), eta);
+        let is_reflection = reflectance > rand::thread_rng().gen_range(0.0..1.0);
+        let wi = if is_reflection {
+            reflect(&wo, &h)
+        } else {
+            refract(&wo, &h, eta)?
+        };
+
+        let ggx = match ggx {
+            Some(ggx) => ggx,
+            None if is_reflection => {
+                return Some(BsdfSample {
+                    direction: frame.to_world(&wi),
+                    value: self.transmittance(ray_in, hit) * reflectance,
+                    pdf: reflectance,
+                    lobe: Lobe::SPECULAR | Lobe::REFLECTION,
+                })
+            }
+            None => {
+                return Some(BsdfSample {
+                    direction: frame.to_world(&wi),
+                    value: self.transmittance(ray_in, hit) * ((1.0 - reflectance) / (eta * eta)),
+                    pdf: 1.0 - reflectance,
+                    lobe: Lobe::SPECULAR | Lobe::TRANSMISSION,
+                })
+            }
+        };
+
+        // Microfacets can send the ray to the wrong side of the surface.
+        if (wi.z > 0.0) != is_reflection || wi.z == 0.0 {
+            return None;
+        }
+        let pdf = Dielectric::pdf_local(&ggx, &wo, &wi, eta);
+        if !pdf.is_finite() || pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction: frame.to_world(&wi),
+            value: self.transmittance(ray_in, hit) * Dielectric::eval_local(&ggx, &wo, &wi, eta),
+            pdf,
+            lobe: Lobe::GLOSSY
+                | if is_reflection {
+                    Lobe::REFLECTION
+                } else {
+                    Lobe::TRANSMISSION
+                },
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> Color {
+        match self.ggx(hit) {
+            Some(ggx) => {
+                let frame = ShadingFrame::new(&hit.normal);
+                let wo = frame.to_local(&(ray_in.direction.normalized() * -1.0));
+                let wi = frame.to_local(&direction.normalized());
+                self.transmittance(ray_in, hit)
+                    * Dielectric::eval_local(&ggx, &wo, &wi, self.relative_eta(ray_in, hit))
+            }
+            None => Color::black(),
+        }
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        match self.ggx(hit) {
+            Some(ggx) => {
+                let frame = ShadingFrame::new(&hit.normal);
+                let wo = frame.to_local(&(ray_in.direction.normalized() * -1.0));
+                let wi = frame.to_local(&direction.normalized());
+                Dielectric::pdf_local(&ggx, &wo, &wi, self.relative_eta(ray_in, hit))
+            }
+            None => 0.0,
+        }
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.dispersion.is_some()
+    }
+}
+
+// The principled parameters at a hit point, combined into lobe weights.
+struct PrincipledLobes {
+    base_color: Color,
+    roughness: f32,
+    specular_f0: Color,
+    sheen: Color,
+    clearcoat: f32,
+    // Diffuse and sheen come from the opaque dielectric part, glass from the
+    // transmissive one. The specular lobe covers both the opaque dielectric
+    // and the metallic part.
+    diffuse_weight: f32,
+    specular_weight: f32,
+    glass_weight: f32,
+    ggx: Ggx,
+    clearcoat_ggx: Ggx,
+    eta: f32,
+}
+
+impl PrincipledLobes {
+    // Probabilities of sampling the diffuse, specular, clearcoat and glass
+    // lobes, roughly in proportion to the light they reflect.
+    fn probabilities(&self, wo: &Vec3) -> [f32; 4] {
+        let weights = [
+            self.diffuse_weight * (self.base_color.luminance() + self.sheen.luminance()),
+            self.specular_weight * fresnel_schlick(wo.z, &self.specular_f0).luminance(),
+            self.clearcoat * fresnel_schlick(wo.z, &PRINCIPLED_CLEARCOAT_F0).luminance(),
+            self.glass_weight,
+        ];
+        let sum: f32 = weights.iter().sum();
+        if sum <= 0.0 {
+            return [0.0; 4];
+        }
+        weights.map(|w| w / sum)
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Color {
+        let mut value = Color::black();
+        if wo.z > 0.0 && wi.z > 0.0 {
+            let h = (*wo + *wi).normalized();
+            let cos_d = Vec3::dot(wi, &h);
+
+            // Burley's diffuse with retro-reflection at grazing angles.
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let fl = (1.0 - wi.z).powi(5);
+            let fv = (1.0 - wo.z).powi(5);
+            let diffuse =
+                self.base_color * ((1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv) / PI);
+            let sheen = self.sheen * (1.0 - cos_d).powi(5);
+            value += (diffuse + sheen) * (self.diffuse_weight * wi.z);
+
+            let specular = fresnel_schlick(Vec3::dot(wo, &h), &self.specular_f0);
+            value += specular
+                * (self.specular_weight * self.ggx.d(&h) * self.ggx.g(wo, wi) / (4.0 * wo.z));
+
+            let clearcoat = fresnel_schlick(Vec3::dot(wo, &h), &PRINCIPLED_CLEARCOAT_F0);
+            let ggx = &self.clearcoat_ggx;
+            value += clearcoat * (self.clearcoat * ggx.d(&h) * ggx.g(wo, wi) / (4.0 * wo.z));
+        }
+        if self.glass_weight > 0.0 {
+            // Only transmitted light takes on the base color.
+            let tint = if wi.z < 0.0 {
+                self.base_color
+            } else {
+                Color::white()
+            };
+            value += tint * Dielectric::eval_local(&self.ggx, wo, wi, self.eta) * self.glass_weight;
+        }
+        value
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        let [diffuse, specular, clearcoat, glass] = self.probabilities(wo);
+        let mut pdf = 0.0;
+        if wo.z > 0.0 && wi.z > 0.0 {
+            let h = (*wo + *wi).normalized();
+            let reflection = 4.0 * Vec3::dot(wo, &h);
+            pdf += diffuse * wi.z / PI;
+            pdf += specular * self.ggx.visible_normal_pdf(wo, &h) / reflection;
+            pdf += clearcoat * self.clearcoat_ggx.visible_normal_pdf(wo, &h) / reflection;
+        }
+        if glass > 0.0 {
+            pdf += glass * Dielectric::pdf_local(&self.ggx, wo, wi, self.eta);
+        }
+        pdf
+    }
+}
+
+// Reflectance of the clearcoat at normal incidence, that of an index of
+// refraction of 1.5.
+const PRINCIPLED_CLEARCOAT_F0: Color = Color {
+    r: 0.04,
+    g: 0.04,
+    b: 0.04,
+};
+
+impl Principled {
+    fn lobes(&self, hit: &HitRecord) -> PrincipledLobes {
+        let value = |t: &Arc<dyn Texture>| t.value(hit.u, hit.v, &hit.point, &hit.normal);
+        let scalar = |t: &Arc<dyn Texture>| value(t).luminance().clamp(0.0, 1.0);
+
+        let base_color = value(&self.base_color);
+        let metallic = scalar(&self.metallic);
+        let roughness = scalar(&self.roughness);
+        let transmission = scalar(&self.transmission);
+
+        let dielectric_f0 = Color::white() * (0.08 * scalar(&self.specular));
+        let tint = if base_color.luminance() > 0.0 {
+            base_color / base_color.luminance()
         } else {
-            self.index_of_refraction
+            Color::white()
         };
-        let unit_direction = ray_in.direction.normalized();
+        let sheen_color = Color::lerp(&Color::white(), &tint, scalar(&self.sheen_tint));
 
-        let cos_theta = Vec3::dot(&(unit_direction * -1.0), &hit.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let glass_weight = (1.0 - metallic) * transmission;
+        PrincipledLobes {
+            base_color,
+            roughness,
+            specular_f0: Color::lerp(&dielectric_f0, &base_color, metallic),
+            sheen: sheen_color * scalar(&self.sheen),
+            // Burley scales the clearcoat down to keep it subtle.
+            clearcoat: 0.25 * scalar(&self.clearcoat),
+            diffuse_weight: (1.0 - metallic) * (1.0 - transmission),
+            specular_weight: 1.0 - glass_weight,
+            glass_weight,
+            ggx: Ggx::glossy(roughness),
+            clearcoat_ggx: Ggx::glossy(scalar(&self.clearcoat_roughness)),
+            eta: if hit.front_face {
+                self.index_of_refraction
+            } else {
+                1.0 / self.index_of_refraction
+            },
+        }
+    }
+}
 
-        let cannot_refract = (refraction_ratio * sin_theta) > 1.0;
+impl Material for Principled {
+    fn sample(&self, ray_in: &Ray, hit: &HitRecord) -> Option<BsdfSample> {
+        let frame = ShadingFrame::new(&hit.normal);
+        let wo = frame.to_local(&(ray_in.direction.normalized() * -1.0));
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let lobes = self.lobes(hit);
+        let [diffuse, specular, clearcoat, _] = lobes.probabilities(&wo);
 
-        let mut rng = rand::thread_rng();
-        let direction = if cannot_refract
-            || Dielectric::reflectance(cos_theta, refraction_ratio) > rng.gen_range(0.0..1.0)
-        {
-            Vec3::reflect(&unit_direction, &hit.normal)
+        let u = rand::thread_rng().gen_range(0.0..1.0);
+        let (wi, lobe) = if u < diffuse {
+            let direction = get_scatter_direction(hit.normal).normalized();
+            (frame.to_local(&direction), Lobe::DIFFUSE)
+        } else if u < diffuse + specular {
+            let h = lobes.ggx.sample_visible_normal(&wo);
+            (reflect(&wo, &h), Lobe::GLOSSY)
+        } else if u < diffuse + specular + clearcoat {
+            let h = lobes.clearcoat_ggx.sample_visible_normal(&wo);
+            (reflect(&wo, &h), Lobe::GLOSSY)
         } else {
-            Vec3::refract(&unit_direction, &hit.normal, refraction_ratio)
+            let h = lobes.ggx.sample_visible_normal(&wo);
+            let reflectance = fresnel_dielectric(Vec3::dot(&wo, &h), lobes.eta);
+            if reflectance > rand::thread_rng().gen_range(0.0..1.0) {
+                (reflect(&wo, &h), Lobe::GLOSSY)
+            } else {
+                (refract(&wo, &h, lobes.eta)?, Lobe::GLOSSY)
+            }
         };
+        if wi.z == 0.0 {
+            return None;
+        }
 
-        scattered.origin = hit.point;
-        scattered.direction = direction;
-        true
+        let pdf = lobes.pdf(&wo, &wi);
+        if !pdf.is_finite() || pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction: frame.to_world(&wi),
+            value: lobes.eval(&wo, &wi),
+            pdf,
+            lobe: lobe
+                | if wi.z > 0.0 {
+                    Lobe::REFLECTION
+                } else {
+                    Lobe::TRANSMISSION
+                },
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> Color {
+        let frame = ShadingFrame::new(&hit.normal);
+        let wo = frame.to_local(&(ray_in.direction.normalized() * -1.0));
+        let wi = frame.to_local(&direction.normalized());
+        self.lobes(hit).eval(&wo, &wi)
+    }
+
+    fn pdf(&self, ray_in: &Ray, hit: &HitRecord, direction: &Vec3) -> f32 {
+        let frame = ShadingFrame::new(&hit.normal);
+        let wo = frame.to_local(&(ray_in.direction.normalized() * -1.0));
+        let wi = frame.to_local(&direction.normalized());
+        self.lobes(hit).pdf(&wo, &wi)
+    }
+
+    fn emitted(&self, _ray_in: &Ray, hit: &HitRecord) -> Color {
+        match &self.emission {
+            Some(emission) if hit.front_face => {
+                emission.value(hit.u, hit.v, &hit.point, &hit.normal) * self.emission_strength
+            }
+            _ => Color::black(),
+        }
+    }
+
+    fn is_light(&self) -> bool {
+        self.emission.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture;
+
+    // A hit on a surface with normal +z, by a ray arriving at `theta` from
+    // the normal on the front or from below on the back.
+    fn hit(theta: f32, front: bool) -> (Ray, HitRecord<'static>) {
+        let z = if front { -theta.cos() } else { theta.cos() };
+        let ray = Ray {
+            origin: Vec3::zero(),
+            direction: Vec3 {
+                x: theta.sin(),
+                y: 0.0,
+                z,
+            },
+            time: 0.0,
+            wavelength: None,
+        };
+        let mut hit = HitRecord::new();
+        hit.t = 1.0;
+        hit.set_face_normal(&ray, Vec3::up_z());
+        (ray, hit)
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-3 * a.abs().max(b.abs()).max(1e-3)
+    }
+
+    // Samples agree with eval and pdf, their density integrates to the
+    // fraction of samples that succeed, and their mean weight stays below
+    // `max_weight`, which is above one only where refraction concentrates
+    // radiance.
+    fn check_sampling(material: &dyn Material, ray: &Ray, hit: &HitRecord, max_weight: f32) {
+        let count = 50_000;
+        let mut successes = 0;
+        let mut weight = 0.0;
+        for _ in 0..count {
+            let sample = match material.sample(ray, hit) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            assert!(!sample.lobe.contains(Lobe::SPECULAR));
+            successes += 1;
+            let pdf = material.pdf(ray, hit, &sample.direction);
+            let value = material.eval(ray, hit, &sample.direction);
+            assert!(close(sample.pdf, pdf), "pdf {} != {}", sample.pdf, pdf);
+            assert!(
+                close(sample.value.g, value.g),
+                "{} != {}",
+                sample.value.g,
+                value.g
+            );
+            weight += sample.value.g / sample.pdf / count as f32;
+        }
+        assert!(weight <= 1.01 * max_weight, "reflects {}", weight);
+
+        let (n_theta, n_phi) = (600, 600);
+        let (d_theta, d_phi) = (PI / n_theta as f32, 2.0 * PI / n_phi as f32);
+        let mut total = 0.0f64;
+        for i in 0..n_theta {
+            let theta = (i as f32 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f32 + 0.5) * d_phi;
+                let direction = Vec3 {
+                    x: theta.sin() * phi.cos(),
+                    y: theta.sin() * phi.sin(),
+                    z: theta.cos(),
+                };
+                total += (material.pdf(ray, hit, &direction) * theta.sin()) as f64;
+            }
+        }
+        let total = total as f32 * d_theta * d_phi;
+        let fraction = successes as f32 / count as f32;
+        assert!(
+            (total - fraction).abs() < 0.02,
+            "pdf integrates to {} but {} of the samples succeed",
+            total,
+            fraction
+        );
+    }
+
+    #[test]
+    fn conductor_sampling() {
+        let (_, eta, k) = CONDUCTORS[0];
+        for &roughness in &[0.4, 0.8] {
+            let conductor = Conductor {
+                eta,
+                k,
+                roughness: texture::constant(roughness),
+            };
+            for &theta in &[0.0, 0.7, 1.3] {
+                let (ray, hit) = hit(theta, true);
+                check_sampling(&conductor, &ray, &hit, 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_sampling() {
+        for &roughness in &[0.5, 0.8] {
+            let glass = Dielectric {
+                index_of_refraction: 1.5,
+                roughness: texture::constant(roughness),
+                absorption: Color::black(),
+                dispersion: None,
+            };
+            for &(theta, front) in &[(0.0, true), (0.8, true), (0.3, false), (1.0, false)] {
+                let (ray, hit) = hit(theta, front);
+                let eta = glass.relative_eta(&ray, &hit);
+                check_sampling(&glass, &ray, &hit, (1.0 / (eta * eta)).max(1.0));
+            }
+        }
     }
 }
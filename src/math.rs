@@ -15,10 +15,20 @@ pub struct Color {
     pub b: f32,
 }
 
+// Affine transform acting on column vectors, stored row by row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f32; 4]; 4],
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    // Point in time within the frame, used for motion blur.
+    pub time: f32,
+    // In nanometers, once the path went through a dispersive material.
+    pub wavelength: Option<f32>,
 }
 
 impl Color {
@@ -31,11 +41,37 @@ impl Color {
         }
     }
 
-    pub fn to_u8(&self) -> (u8, u8, u8) {
+    // Relative luminance of linear Rec. 709 / sRGB primaries.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    pub fn is_black(&self) -> bool {
+        self.r == 0.0 && self.g == 0.0 && self.b == 0.0
+    }
+
+    pub fn map<F: Fn(f32) -> f32>(&self, f: F) -> Color {
+        Color {
+            r: f(self.r),
+            g: f(self.g),
+            b: f(self.b),
+        }
+    }
+
+    // Quantizes each channel, values outside of [0, 1] are clamped.
+    pub fn to_u8(self) -> (u8, u8, u8) {
+        (
+            (self.r * 255.0 + 0.5) as u8,
+            (self.g * 255.0 + 0.5) as u8,
+            (self.b * 255.0 + 0.5) as u8,
+        )
+    }
+
+    pub fn to_u16(self) -> (u16, u16, u16) {
         (
-            (self.r * 255.0) as u8,
-            (self.g * 255.0) as u8,
-            (self.b * 255.0) as u8,
+            (self.r * 65535.0 + 0.5) as u16,
+            (self.g * 65535.0 + 0.5) as u16,
+            (self.b * 65535.0 + 0.5) as u16,
         )
     }
 
@@ -82,7 +118,7 @@ impl Vec3 {
     }
 
     pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
-        return *v - *n * Vec3::dot(v, n) * 2.0;
+        *v - *n * Vec3::dot(v, n) * 2.0
     }
 
     pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f32) -> Vec3 {
@@ -104,7 +140,16 @@ impl Vec3 {
         Vec3 {
             x: 0.0,
             y: 1.0,
-            z: 0.0
+            z: 0.0,
+        }
+    }
+
+    // The normal in shading frames.
+    pub fn up_z() -> Vec3 {
+        Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
         }
     }
 
@@ -126,10 +171,63 @@ impl Vec3 {
         }
     }
 
+    // Uniformly distributed in the unit disk in the xy plane.
+    pub fn random_in_unit_disk() -> Vec3 {
+        let mut rng = rand::thread_rng();
+        loop {
+            let v = Vec3 {
+                x: rng.gen_range(-1.0..1.0),
+                y: rng.gen_range(-1.0..1.0),
+                z: 0.0,
+            };
+            if v.mag_squared() < 1.0 {
+                return v;
+            }
+        }
+    }
+
     pub fn random_unit_vector() -> Vec3 {
         Vec3::random_in_unit_sphere().normalized()
     }
 
+    // Two unit vectors that form a right handed orthonormal basis with this
+    // unit vector. See Duff et al., "Building an Orthonormal Basis, Revisited".
+    pub fn orthonormal_basis(&self) -> (Vec3, Vec3) {
+        let sign = 1.0f32.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (
+            Vec3 {
+                x: 1.0 + sign * self.x * self.x * a,
+                y: sign * b,
+                z: -sign * self.x,
+            },
+            Vec3 {
+                x: b,
+                y: sign + self.y * self.y * a,
+                z: -self.y,
+            },
+        )
+    }
+
+    // Component-wise minimum.
+    pub fn min(a: &Vec3, b: &Vec3) -> Vec3 {
+        Vec3 {
+            x: a.x.min(b.x),
+            y: a.y.min(b.y),
+            z: a.z.min(b.z),
+        }
+    }
+
+    // Component-wise maximum.
+    pub fn max(a: &Vec3, b: &Vec3) -> Vec3 {
+        Vec3 {
+            x: a.x.max(b.x),
+            y: a.y.max(b.y),
+            z: a.z.max(b.z),
+        }
+    }
+
     pub fn near_zero(&self) -> bool {
         let e = 1e-8;
         self.x.abs() < e && self.y.abs() < e && self.z.abs() < e
@@ -142,6 +240,159 @@ impl Ray {
     }
 }
 
+impl Mat4 {
+    pub fn identity() -> Mat4 {
+        Mat4::scaling(&Vec3 {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        })
+    }
+
+    pub fn translation(offset: &Vec3) -> Mat4 {
+        Mat4 {
+            m: [
+                [1.0, 0.0, 0.0, offset.x],
+                [0.0, 1.0, 0.0, offset.y],
+                [0.0, 0.0, 1.0, offset.z],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn scaling(factors: &Vec3) -> Mat4 {
+        Mat4 {
+            m: [
+                [factors.x, 0.0, 0.0, 0.0],
+                [0.0, factors.y, 0.0, 0.0],
+                [0.0, 0.0, factors.z, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    // Counterclockwise rotation by `angle` radians around `axis`, looking
+    // against the axis.
+    pub fn rotation(axis: &Vec3, angle: f32) -> Mat4 {
+        let a = axis.normalized();
+        let (sin, cos) = angle.sin_cos();
+        let t = 1.0 - cos;
+        Mat4 {
+            m: [
+                [
+                    t * a.x * a.x + cos,
+                    t * a.x * a.y - sin * a.z,
+                    t * a.x * a.z + sin * a.y,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.y + sin * a.z,
+                    t * a.y * a.y + cos,
+                    t * a.y * a.z - sin * a.x,
+                    0.0,
+                ],
+                [
+                    t * a.x * a.z - sin * a.y,
+                    t * a.y * a.z + sin * a.x,
+                    t * a.z * a.z + cos,
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Mat4 { m }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting. None for singular
+    // matrices, e.g. a scale of zero.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap();
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inv.swap(column, pivot);
+
+            let scale = 1.0 / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inv[column][j] *= scale;
+            }
+            for row in 0..4 {
+                if row == column {
+                    continue;
+                }
+                let factor = a[row][column];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[column][j];
+                    inv[row][j] -= factor * inv[column][j];
+                }
+            }
+        }
+        Some(Mat4 { m: inv })
+    }
+
+    pub fn transform_point(&self, p: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            y: m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            z: m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        }
+    }
+
+    // Ignores the translation.
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+}
+
+impl ops::Mul<Mat4> for Mat4 {
+    type Output = Mat4;
+
+    // The result applies `rhs` first.
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
+
+impl ops::Index<usize> for Vec3 {
+    type Output = f32;
+    fn index(&self, axis: usize) -> &f32 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", axis),
+        }
+    }
+}
+
 impl ops::Add<Vec3> for Vec3 {
     type Output = Vec3;
     fn add(self, _rhs: Vec3) -> Vec3 {
@@ -241,6 +492,17 @@ impl ops::AddAssign<Color> for Color {
     }
 }
 
+impl ops::Sub<Color> for Color {
+    type Output = Color;
+    fn sub(self, _rhs: Color) -> Color {
+        Color {
+            r: self.r - _rhs.r,
+            g: self.g - _rhs.g,
+            b: self.b - _rhs.b,
+        }
+    }
+}
+
 impl ops::Div<f32> for Color {
     type Output = Color;
     fn div(self, _rhs: f32) -> Color {
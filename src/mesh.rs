@@ -0,0 +1,451 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::intersection::HitRecord;
+use crate::intersection::Intersectable;
+use crate::light::Light;
+use crate::light::LightSample;
+use crate::material::Material;
+use crate::math::Ray;
+use crate::math::Vec3;
+
+// Indexed triangle mesh. Vertex attributes are shared between triangles;
+// normals and uvs are optional, but if present there is one per position.
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f32, f32)>,
+    indices: Vec<[usize; 3]>,
+    material: Arc<dyn Material>,
+}
+
+// A single triangle of a mesh.
+pub struct Triangle {
+    mesh: Arc<TriangleMesh>,
+    index: usize,
+}
+
+impl TriangleMesh {
+    // Fails if an index is out of range or the attribute counts don't match.
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f32, f32)>,
+        indices: Vec<[usize; 3]>,
+        material: Arc<dyn Material>,
+    ) -> Result<TriangleMesh, String> {
+        if !normals.is_empty() && normals.len() != positions.len() {
+            return Err(format!(
+                "mesh has {} positions but {} normals",
+                positions.len(),
+                normals.len()
+            ));
+        }
+        if !uvs.is_empty() && uvs.len() != positions.len() {
+            return Err(format!(
+                "mesh has {} positions but {} uvs",
+                positions.len(),
+                uvs.len()
+            ));
+        }
+        if let Some(i) = indices.iter().flatten().find(|&&i| i >= positions.len()) {
+            return Err(format!(
+                "vertex index {} out of range for {} positions",
+                i,
+                positions.len()
+            ));
+        }
+
+        Ok(TriangleMesh {
+            positions,
+            normals,
+            uvs,
+            indices,
+            material,
+        })
+    }
+
+    // An axis-aligned box between the corners `a` and `b`, with its faces
+    // pointing outwards.
+    pub fn cuboid(a: &Vec3, b: &Vec3, material: Arc<dyn Material>) -> TriangleMesh {
+        let (min, max) = (Vec3::min(a, b), Vec3::max(a, b));
+        let positions = (0..8)
+            .map(|i| Vec3 {
+                x: if i & 1 == 0 { min.x } else { max.x },
+                y: if i & 2 == 0 { min.y } else { max.y },
+                z: if i & 4 == 0 { min.z } else { max.z },
+            })
+            .collect();
+        let indices = vec![
+            [0, 2, 3],
+            [0, 3, 1],
+            [4, 5, 7],
+            [4, 7, 6],
+            [0, 4, 6],
+            [0, 6, 2],
+            [1, 3, 7],
+            [1, 7, 5],
+            [0, 1, 5],
+            [0, 5, 4],
+            [2, 6, 7],
+            [2, 7, 3],
+        ];
+        TriangleMesh {
+            positions,
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices,
+            material,
+        }
+    }
+
+    pub fn set_material(&mut self, material: Arc<dyn Material>) {
+        self.material = material;
+    }
+
+    pub fn triangles(mesh: &Arc<TriangleMesh>) -> Vec<Triangle> {
+        (0..mesh.indices.len())
+            .map(|index| Triangle {
+                mesh: mesh.clone(),
+                index,
+            })
+            .collect()
+    }
+
+    // Builds a hierarchy over all triangles of the mesh.
+    pub fn into_bvh(self) -> Bvh<Triangle> {
+        Bvh::new(TriangleMesh::triangles(&Arc::new(self)))
+    }
+}
+
+impl Triangle {
+    // A triangle that does not share its vertices with anything else.
+    pub fn new(a: Vec3, b: Vec3, c: Vec3, material: Arc<dyn Material>) -> Triangle {
+        let mesh = TriangleMesh {
+            positions: vec![a, b, c],
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: vec![[0, 1, 2]],
+            material,
+        };
+        Triangle {
+            mesh: Arc::new(mesh),
+            index: 0,
+        }
+    }
+
+    fn vertices(&self) -> [usize; 3] {
+        self.mesh.indices[self.index]
+    }
+
+    fn positions(&self) -> [Vec3; 3] {
+        let [i0, i1, i2] = self.vertices();
+        [
+            self.mesh.positions[i0],
+            self.mesh.positions[i1],
+            self.mesh.positions[i2],
+        ]
+    }
+}
+
+impl Intersectable for Triangle {
+    // Möller-Trumbore intersection.
+    fn intersect<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord<'a>) -> bool {
+        let [i0, i1, i2] = self.vertices();
+        let p0 = self.mesh.positions[i0];
+        let p1 = self.mesh.positions[i1];
+        let p2 = self.mesh.positions[i2];
+
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let p = Vec3::cross(&ray.direction, &edge2);
+        let det = Vec3::dot(&edge1, &p);
+        if det.abs() < 1e-12 {
+            return false;
+        }
+        let inv_det = 1.0 / det;
+
+        let s = ray.origin - p0;
+        let b1 = Vec3::dot(&s, &p) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return false;
+        }
+
+        let q = Vec3::cross(&s, &edge1);
+        let b2 = Vec3::dot(&ray.direction, &q) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return false;
+        }
+
+        let t = Vec3::dot(&edge2, &q) * inv_det;
+        if t < t_min || t_max < t {
+            return false;
+        }
+        let b0 = 1.0 - b1 - b2;
+
+        hit.t = t;
+        hit.point = ray.at(t);
+        let geometric_normal = Vec3::cross(&edge1, &edge2).normalized();
+        hit.set_face_normal(ray, geometric_normal);
+
+        // Smooth shading: use the interpolated vertex normal, but keep it on
+        // the same side as the geometric one.
+        if !self.mesh.normals.is_empty() {
+            let n = &self.mesh.normals;
+            let mut shading_normal = (n[i0] * b0 + n[i1] * b1 + n[i2] * b2).normalized();
+            if Vec3::dot(&shading_normal, &hit.normal) < 0.0 {
+                shading_normal *= -1.0;
+            }
+            hit.normal = shading_normal;
+        }
+
+        if self.mesh.uvs.is_empty() {
+            hit.u = b1;
+            hit.v = b2;
+        } else {
+            let uv = &self.mesh.uvs;
+            hit.u = uv[i0].0 * b0 + uv[i1].0 * b1 + uv[i2].0 * b2;
+            hit.v = uv[i0].1 * b0 + uv[i1].1 * b1 + uv[i2].1 * b2;
+        }
+
+        hit.material = self.mesh.material.clone();
+        hit.light = if self.mesh.material.is_light() {
+            Some(self)
+        } else {
+            None
+        };
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [i0, i1, i2] = self.vertices();
+        let mut bounds = Aabb::new(self.mesh.positions[i0], self.mesh.positions[i1]);
+        bounds.grow(&self.mesh.positions[i2]);
+        bounds
+    }
+
+    fn lights(&self, lights: &mut Vec<Box<dyn Light>>) {
+        if self.mesh.material.is_light() {
+            lights.push(Box::new(Triangle {
+                mesh: self.mesh.clone(),
+                index: self.index,
+            }));
+        }
+    }
+}
+
+// Samples the area uniformly. Emitters only light their front face, so
+// there is nothing to sample from behind.
+impl Light for Triangle {
+    fn sample(&self, origin: &Vec3) -> Option<LightSample> {
+        if !self.faces(origin) {
+            return None;
+        }
+        let [p0, p1, p2] = self.positions();
+        let mut rng = rand::thread_rng();
+        let su = rng.gen_range(0.0f32..1.0).sqrt();
+        let b0 = 1.0 - su;
+        let b1 = rng.gen_range(0.0..1.0) * su;
+        let point = p0 * b0 + p1 * b1 + p2 * (1.0 - b0 - b1);
+
+        let to_point = point - *origin;
+        let distance = to_point.mag();
+        let direction = to_point / distance;
+        let pdf = self.pdf_at(&direction, distance);
+        if pdf <= 0.0 || !pdf.is_finite() {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            pdf,
+        })
+    }
+
+    fn pdf(&self, origin: &Vec3, direction: &Vec3, max_distance: f32) -> f32 {
+        if !self.faces(origin) {
+            return 0.0;
+        }
+        let direction = direction.normalized();
+        let ray = Ray {
+            origin: *origin,
+            direction,
+            time: 0.0,
+            wavelength: None,
+        };
+        let mut hit = HitRecord::new();
+        if !self.intersect(&ray, 0.0001, max_distance * 1.001, &mut hit) {
+            return 0.0;
+        }
+        self.pdf_at(&direction, hit.t)
+    }
+}
+
+impl Triangle {
+    // Whether `origin` is in front of the triangle.
+    fn faces(&self, origin: &Vec3) -> bool {
+        let [p0, p1, p2] = self.positions();
+        Vec3::dot(&Vec3::cross(&(p1 - p0), &(p2 - p0)), &(*origin - p0)) > 0.0
+    }
+
+    // Converts the area density to solid angle for a point `distance` away
+    // along the unit vector `direction`.
+    fn pdf_at(&self, direction: &Vec3, distance: f32) -> f32 {
+        let [p0, p1, p2] = self.positions();
+        let cross = Vec3::cross(&(p1 - p0), &(p2 - p0));
+        let double_area = cross.mag();
+        let cosine = Vec3::dot(&cross, direction).abs() / double_area;
+        if cosine < 1e-6 {
+            return 0.0;
+        }
+        2.0 * distance * distance / (cosine * double_area)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Constant;
+    use crate::math::Color;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Constant {
+            color: Color::black(),
+        })
+    }
+
+    fn vec3(x: f32, y: f32, z: f32) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    fn ray(origin: Vec3, direction: Vec3) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time: 0.0,
+            wavelength: None,
+        }
+    }
+
+    fn unit_triangle() -> Triangle {
+        Triangle::new(
+            vec3(0.0, 0.0, 0.0),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+            material(),
+        )
+    }
+
+    #[test]
+    fn front_and_back_hits() {
+        let triangle = unit_triangle();
+        let mut hit = HitRecord::new();
+        let down = ray(vec3(0.25, 0.5, 2.0), vec3(0.0, 0.0, -1.0));
+        assert!(triangle.intersect(&down, 0.001, f32::INFINITY, &mut hit));
+        assert_eq!(hit.t, 2.0);
+        assert!(hit.front_face);
+        assert_eq!((hit.normal.x, hit.normal.y, hit.normal.z), (0.0, 0.0, 1.0));
+        assert_eq!((hit.u, hit.v), (0.25, 0.5));
+
+        let up = ray(vec3(0.25, 0.5, -1.0), vec3(0.0, 0.0, 2.0));
+        assert!(triangle.intersect(&up, 0.001, f32::INFINITY, &mut hit));
+        assert_eq!(hit.t, 0.5);
+        assert!(!hit.front_face);
+        assert_eq!(hit.normal.z, -1.0);
+    }
+
+    #[test]
+    fn misses() {
+        let triangle = unit_triangle();
+        let mut hit = HitRecord::new();
+        let down = vec3(0.0, 0.0, -1.0);
+        // Outside of each edge.
+        for origin in [
+            vec3(0.6, 0.6, 1.0),
+            vec3(-0.1, 0.5, 1.0),
+            vec3(0.5, -0.1, 1.0),
+        ] {
+            assert!(!triangle.intersect(&ray(origin, down), 0.001, f32::INFINITY, &mut hit));
+        }
+        // Parallel to the plane, and outside of [t_min, t_max].
+        let parallel = ray(vec3(-1.0, 0.2, 0.0), vec3(1.0, 0.0, 0.0));
+        assert!(!triangle.intersect(&parallel, 0.001, f32::INFINITY, &mut hit));
+        let inside = ray(vec3(0.2, 0.2, 1.0), down);
+        assert!(!triangle.intersect(&inside, 0.001, 0.5, &mut hit));
+        assert!(!triangle.intersect(&inside, 1.5, f32::INFINITY, &mut hit));
+    }
+
+    #[test]
+    fn interpolated_attributes() {
+        let mesh = TriangleMesh::new(
+            vec![
+                vec3(0.0, 0.0, 0.0),
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, 1.0, 0.0),
+            ],
+            vec![
+                vec3(0.0, 0.0, 1.0),
+                vec3(1.0, 0.0, 1.0),
+                vec3(0.0, 0.0, -1.0),
+            ],
+            vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)],
+            vec![[0, 1, 2]],
+            material(),
+        )
+        .unwrap();
+        let triangles = TriangleMesh::triangles(&Arc::new(mesh));
+        let mut hit = HitRecord::new();
+        let down = ray(vec3(0.5, 0.25, 1.0), vec3(0.0, 0.0, -1.0));
+        assert!(triangles[0].intersect(&down, 0.001, f32::INFINITY, &mut hit));
+        assert_eq!((hit.u, hit.v), (0.75, 0.25));
+        // Half of the second normal and a quarter of the other two.
+        let expected = vec3(1.0, 0.0, 1.0).normalized();
+        assert!((hit.normal - expected).mag() < 1e-6);
+    }
+
+    #[test]
+    fn invalid_meshes() {
+        let positions = vec![Vec3::zero(); 3];
+        let error = TriangleMesh::new(
+            positions.clone(),
+            vec![],
+            vec![],
+            vec![[0, 1, 3]],
+            material(),
+        );
+        assert_eq!(
+            error.err().unwrap(),
+            "vertex index 3 out of range for 3 positions"
+        );
+        let normals = vec![Vec3::zero(); 2];
+        let error = TriangleMesh::new(positions, normals, vec![], vec![[0, 1, 2]], material());
+        assert_eq!(error.err().unwrap(), "mesh has 3 positions but 2 normals");
+    }
+
+    #[test]
+    fn cuboid_faces_point_outwards() {
+        let cuboid =
+            TriangleMesh::cuboid(&vec3(1.0, 2.0, 3.0), &vec3(-1.0, -2.0, -3.0), material())
+                .into_bvh();
+        let mut hit = HitRecord::new();
+        for axis in 0..3 {
+            for &sign in &[-1.0, 1.0] {
+                let direction = match axis {
+                    0 => vec3(sign, 0.0, 0.0),
+                    1 => vec3(0.0, sign, 0.0),
+                    _ => vec3(0.0, 0.0, sign),
+                };
+                let outside = ray(direction * -10.0, direction);
+                assert!(cuboid.intersect(&outside, 0.001, f32::INFINITY, &mut hit));
+                assert!(hit.front_face);
+                assert_eq!(hit.t, 10.0 - (axis + 1) as f32);
+                let inside = ray(Vec3::zero(), direction);
+                assert!(cuboid.intersect(&inside, 0.001, f32::INFINITY, &mut hit));
+                assert!(!hit.front_face);
+            }
+        }
+    }
+}
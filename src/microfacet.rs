@@ -0,0 +1,321 @@
+// Building blocks for microfacet materials: the GGX (Trowbridge-Reitz)
+// distribution of normals with visible normal sampling, and the Fresnel
+// equations. Directions are in a shading frame with z along the normal.
+
+use std::f32::consts::PI;
+
+use rand::Rng;
+
+use crate::math::Color;
+use crate::math::Vec3;
+
+// Orthonormal frame around a unit shading normal.
+pub struct ShadingFrame {
+    t: Vec3,
+    b: Vec3,
+    n: Vec3,
+}
+
+// Isotropic GGX distribution with width `alpha`.
+pub struct Ggx {
+    alpha: f32,
+}
+
+// Below this width surfaces are treated as perfectly smooth.
+const MIN_ALPHA: f32 = 1e-3;
+
+impl ShadingFrame {
+    pub fn new(normal: &Vec3) -> ShadingFrame {
+        let (t, b) = normal.orthonormal_basis();
+        ShadingFrame { t, b, n: *normal }
+    }
+
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3 {
+            x: Vec3::dot(v, &self.t),
+            y: Vec3::dot(v, &self.b),
+            z: Vec3::dot(v, &self.n),
+        }
+    }
+
+    pub fn to_world(&self, v: &Vec3) -> Vec3 {
+        self.t * v.x + self.b * v.y + self.n * v.z
+    }
+}
+
+impl Ggx {
+    // Maps the perceptually more linear roughness in [0, 1] to the width
+    // of the distribution. None for smooth surfaces.
+    pub fn from_roughness(roughness: f32) -> Option<Ggx> {
+        let alpha = roughness.clamp(0.0, 1.0).powi(2);
+        if alpha < MIN_ALPHA {
+            None
+        } else {
+            Some(Ggx { alpha })
+        }
+    }
+
+    // Like from_roughness, but keeps even the narrowest lobe glossy so that
+    // it can always be evaluated.
+    pub fn glossy(roughness: f32) -> Ggx {
+        Ggx {
+            alpha: roughness.clamp(0.0, 1.0).powi(2).max(MIN_ALPHA),
+        }
+    }
+
+    // Density of microfacet normals `h`, per projected area.
+    pub fn d(&self, h: &Vec3) -> f32 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let t = h.z * h.z * (a2 - 1.0) + 1.0;
+        a2 / (PI * t * t)
+    }
+
+    // Smith's auxiliary function.
+    fn lambda(&self, w: &Vec3) -> f32 {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return f32::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    // Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Height correlated masking and shadowing.
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Samples a normal among the microfacets visible from `wo`, see Heitz,
+    // "Sampling the GGX Distribution of Visible Normals" (JCGT 2018).
+    pub fn sample_visible_normal(&self, wo: &Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        // Stretch the view direction to the hemisphere configuration.
+        let vh = Vec3 {
+            x: self.alpha * wo.x,
+            y: self.alpha * wo.y,
+            z: wo.z,
+        }
+        .normalized();
+        let length_squared = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length_squared > 0.0 {
+            Vec3 {
+                x: -vh.y,
+                y: vh.x,
+                z: 0.0,
+            } / length_squared.sqrt()
+        } else {
+            Vec3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            }
+        };
+        let t2 = Vec3::cross(&vh, &t1);
+
+        // Point on the projected half disk.
+        let r = rng.gen_range(0.0f32..1.0).sqrt();
+        let phi = 2.0 * PI * rng.gen_range(0.0..1.0);
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        Vec3 {
+            x: self.alpha * nh.x,
+            y: self.alpha * nh.y,
+            z: nh.z.max(0.0),
+        }
+        .normalized()
+    }
+
+    // Density of `sample_visible_normal` picking `h`.
+    pub fn visible_normal_pdf(&self, wo: &Vec3, h: &Vec3) -> f32 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * Vec3::dot(wo, h).max(0.0) * self.d(h) / wo.z
+    }
+}
+
+// Mirrors `wo` about `h`.
+pub fn reflect(wo: &Vec3, h: &Vec3) -> Vec3 {
+    *h * (2.0 * Vec3::dot(wo, h)) - *wo
+}
+
+// Refracts `wo` through a surface with normal `h` on its side, where `eta`
+// is the ratio of the indices of refraction behind and in front of it. None
+// for total internal reflection.
+pub fn refract(wo: &Vec3, h: &Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = Vec3::dot(wo, h);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(*wo * (-1.0 / eta) + *h * (cos_i / eta - cos_t))
+}
+
+// Unpolarized reflectance of a dielectric interface, for light arriving at
+// `cos_i` to the normal. `eta` is the index of refraction on the other side
+// relative to the incident one.
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_i < 0.0 {
+        (-cos_i, 1.0 / eta)
+    } else {
+        (cos_i.min(1.0), eta)
+    };
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+}
+
+// Schlick's approximation of the reflectance, given the reflectance `f0` at
+// normal incidence.
+pub fn fresnel_schlick(cos_i: f32, f0: &Color) -> Color {
+    let t = (1.0 - cos_i.clamp(0.0, 1.0)).powi(5);
+    Color::lerp(f0, &Color::white(), t)
+}
+
+// Reflectance of a conductor with complex index of refraction eta + ik, per
+// color channel.
+pub fn fresnel_conductor(cos_i: f32, eta: &Color, k: &Color) -> Color {
+    Color {
+        r: fresnel_complex(cos_i, eta.r, k.r),
+        g: fresnel_complex(cos_i, eta.g, k.g),
+        b: fresnel_complex(cos_i, eta.b, k.b),
+    }
+}
+
+fn fresnel_complex(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cos_i.clamp(0.0, 1.0) * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    (rs + rp) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn direction(theta: f32, phi: f32) -> Vec3 {
+        Vec3 {
+            x: theta.sin() * phi.cos(),
+            y: theta.sin() * phi.sin(),
+            z: theta.cos(),
+        }
+    }
+
+    // Midpoint rule over the upper hemisphere.
+    fn integrate_hemisphere<F: Fn(&Vec3) -> f32>(f: F) -> f32 {
+        let (n_theta, n_phi) = (400, 400);
+        let (d_theta, d_phi) = (0.5 * PI / n_theta as f32, 2.0 * PI / n_phi as f32);
+        let mut sum = 0.0f64;
+        for i in 0..n_theta {
+            let theta = (i as f32 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f32 + 0.5) * d_phi;
+                sum += (f(&direction(theta, phi)) * theta.sin()) as f64;
+            }
+        }
+        sum as f32 * d_theta * d_phi
+    }
+
+    #[test]
+    fn projected_normals_cover_the_surface() {
+        for &roughness in &[0.4, 0.7, 1.0] {
+            let ggx = Ggx::glossy(roughness);
+            let area = integrate_hemisphere(|h| ggx.d(h) * h.z);
+            assert!(
+                (area - 1.0).abs() < 0.01,
+                "{} at roughness {}",
+                area,
+                roughness
+            );
+        }
+    }
+
+    // The visible normal pdf integrates to one, and sampled normals have the
+    // same mean as the pdf says they should.
+    #[test]
+    fn visible_normal_sampling_matches_pdf() {
+        for &(roughness, theta) in &[(0.5, 0.3), (0.8, 1.2), (1.0, 0.0)] {
+            let ggx = Ggx::glossy(roughness);
+            let wo = direction(theta, 0.4);
+            let total = integrate_hemisphere(|h| ggx.visible_normal_pdf(&wo, h));
+            assert!((total - 1.0).abs() < 0.01, "pdf integrates to {}", total);
+
+            let mean = |c: fn(&Vec3) -> f32| {
+                integrate_hemisphere(|h| c(h) * ggx.visible_normal_pdf(&wo, h))
+            };
+            let expected = [mean(|h| h.x), mean(|h| h.y), mean(|h| h.z)];
+            let count = 100_000;
+            let mut sampled = [0.0f32; 3];
+            for _ in 0..count {
+                let h = ggx.sample_visible_normal(&wo);
+                assert!(h.z >= 0.0);
+                sampled[0] += h.x / count as f32;
+                sampled[1] += h.y / count as f32;
+                sampled[2] += h.z / count as f32;
+            }
+            for axis in 0..3 {
+                assert!(
+                    (sampled[axis] - expected[axis]).abs() < 0.01,
+                    "mean {} instead of {} at roughness {}",
+                    sampled[axis],
+                    expected[axis],
+                    roughness
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn fresnel() {
+        // Normal incidence on glass, and total internal reflection.
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-6);
+        assert_eq!(fresnel_dielectric(0.1, 1.0 / 1.5), 1.0);
+        assert!((fresnel_dielectric(0.0, 1.5) - 1.0).abs() < 1e-6);
+        // A conductor without absorption is a dielectric.
+        let eta = Color {
+            r: 1.5,
+            g: 1.5,
+            b: 1.5,
+        };
+        let f = fresnel_conductor(0.6, &eta, &Color::black());
+        assert!((f.g - fresnel_dielectric(0.6, 1.5)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn refraction_follows_snell() {
+        let wo = direction(0.5, 1.0);
+        let wi = refract(&wo, &Vec3::up_z(), 1.5).unwrap();
+        let sin_i = (1.0 - wo.z * wo.z).sqrt();
+        let sin_t = (1.0 - wi.z * wi.z).sqrt();
+        assert!(wi.z < 0.0);
+        assert!((sin_i - 1.5 * sin_t).abs() < 1e-5);
+        assert!(refract(&direction(1.2, 0.0), &Vec3::up_z(), 1.0 / 1.5).is_none());
+    }
+}
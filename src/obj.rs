@@ -0,0 +1,611 @@
+// Wavefront OBJ and MTL loading.
+//
+// Polygons are triangulated as fans. Every group (`g` or `o`) and material
+// (`usemtl`) combination becomes its own TriangleMesh. MTL materials are
+// mapped onto the closest Material this crate has:
+//   - any of the PBR extension's `Pr`, `Pm`, `Ps`, `Pc` or `Pcr`: Principled
+//     with `Kd` as base color, `Ke` as emission and `1 - d` as transmission,
+//   - a non-black `Ke`: DiffuseLight,
+//   - `d` < 1 (or `Tr` > 0) or `illum` 4, 6, 7: Dielectric with `Ni`,
+//   - `illum` 3 or a black `Kd` with a non-black `Ks`: Metal with `Ks` as
+//     albedo and a roughness derived from the `Ns` exponent,
+//   - anything else: Lambertian with `Kd`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::bvh::Bvh;
+use crate::material;
+use crate::material::Material;
+use crate::math::Color;
+use crate::math::Vec3;
+use crate::mesh::Triangle;
+use crate::mesh::TriangleMesh;
+use crate::texture;
+
+#[derive(Debug)]
+pub struct ObjError {
+    pub file: PathBuf,
+    // Zero if the error is not related to a specific line.
+    pub line: usize,
+    pub message: String,
+}
+
+pub struct ObjGroup {
+    pub name: String,
+    pub mesh: TriangleMesh,
+}
+
+pub struct ObjModel {
+    pub groups: Vec<ObjGroup>,
+}
+
+// Material parameters as read from a .mtl file.
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Color,
+    emission: Color,
+    specular_exponent: f32,
+    index_of_refraction: f32,
+    dissolve: f32,
+    illumination: u32,
+    // Roughness, metallic, sheen, clearcoat and clearcoat roughness of the
+    // PBR extension, if the file uses it.
+    pbr: Option<[f32; 5]>,
+}
+
+// Vertices of one group/material combination, deduplicated by their
+// position/uv/normal index triple.
+struct MeshBuilder {
+    group: String,
+    material: Arc<dyn Material>,
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    positions: Vec<Vec3>,
+    normals: Vec<Option<Vec3>>,
+    uvs: Vec<Option<(f32, f32)>>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line > 0 {
+            write!(f, "{}:{}: {}", self.file.display(), self.line, self.message)
+        } else {
+            write!(f, "{}: {}", self.file.display(), self.message)
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+impl Default for MtlMaterial {
+    fn default() -> MtlMaterial {
+        MtlMaterial {
+            diffuse: Color {
+                r: 0.8,
+                g: 0.8,
+                b: 0.8,
+            },
+            specular: Color::black(),
+            emission: Color::black(),
+            specular_exponent: 0.0,
+            index_of_refraction: 1.5,
+            dissolve: 1.0,
+            illumination: 2,
+            pbr: None,
+        }
+    }
+}
+
+fn max_component(c: &Color) -> f32 {
+    c.r.max(c.g).max(c.b)
+}
+
+impl MtlMaterial {
+    fn to_material(&self) -> Arc<dyn Material> {
+        let transparent = self.dissolve < 1.0 || matches!(self.illumination, 4 | 6 | 7);
+        let metallic = self.illumination == 3
+            || (max_component(&self.diffuse) == 0.0 && max_component(&self.specular) > 0.0);
+
+        if let Some([roughness, metallic, sheen, clearcoat, clearcoat_roughness]) = self.pbr {
+            let emission = max_component(&self.emission) > 0.0;
+            Arc::new(material::Principled {
+                base_color: texture::solid(self.diffuse),
+                metallic: texture::constant(metallic),
+                roughness: texture::constant(roughness),
+                specular: texture::constant(0.5),
+                sheen: texture::constant(sheen),
+                sheen_tint: texture::constant(0.5),
+                clearcoat: texture::constant(clearcoat),
+                clearcoat_roughness: texture::constant(clearcoat_roughness),
+                transmission: texture::constant(1.0 - self.dissolve),
+                index_of_refraction: self.index_of_refraction,
+                emission: emission.then(|| texture::solid(self.emission)),
+                emission_strength: 1.0,
+            })
+        } else if max_component(&self.emission) > 0.0 {
+            Arc::new(material::DiffuseLight {
+                emit: self.emission,
+            })
+        } else if transparent {
+            Arc::new(material::Dielectric {
+                index_of_refraction: self.index_of_refraction,
+                roughness: texture::constant(0.0),
+                absorption: Color::black(),
+                dispersion: None,
+            })
+        } else if metallic {
+            // Maps the Phong exponent onto a roughness in [0, 1], see
+            // Walter et al. 2007, "Microfacet Models for Refraction".
+            let roughness = (2.0 / (self.specular_exponent + 2.0)).sqrt();
+            Arc::new(material::Metal {
+                albedo: texture::solid(self.specular),
+                roughness: texture::constant(roughness),
+            })
+        } else {
+            Arc::new(material::Lambertian {
+                albedo: texture::solid(self.diffuse),
+            })
+        }
+    }
+}
+
+impl MeshBuilder {
+    fn new(group: String, material: Arc<dyn Material>) -> MeshBuilder {
+        MeshBuilder {
+            group,
+            material,
+            vertices: HashMap::new(),
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn vertex(&mut self, key: (usize, Option<usize>, Option<usize>), data: &ObjData) -> usize {
+        if let Some(&index) = self.vertices.get(&key) {
+            return index;
+        }
+        let index = self.positions.len();
+        self.positions.push(data.positions[key.0]);
+        self.uvs.push(key.1.map(|i| data.uvs[i]));
+        self.normals.push(key.2.map(|i| data.normals[i]));
+        self.vertices.insert(key, index);
+        index
+    }
+
+    // Normals and uvs are only kept if every vertex has them.
+    fn build(self) -> Result<ObjGroup, String> {
+        let normals = self.normals.iter().copied().collect::<Option<Vec<_>>>();
+        let uvs = self.uvs.iter().copied().collect::<Option<Vec<_>>>();
+        let mesh = TriangleMesh::new(
+            self.positions,
+            normals.unwrap_or_default(),
+            uvs.unwrap_or_default(),
+            self.indices,
+            self.material,
+        )?;
+        Ok(ObjGroup {
+            name: self.group,
+            mesh,
+        })
+    }
+}
+
+// Vertex attributes shared by all groups of the file.
+struct ObjData {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f32, f32)>,
+}
+
+fn parse_floats<const N: usize>(args: &[&str]) -> Result<[f32; N], String> {
+    if args.len() < N {
+        return Err(format!("expected {} numbers, found {}", N, args.len()));
+    }
+    let mut result = [0.0; N];
+    for (r, a) in result.iter_mut().zip(args) {
+        *r = a
+            .parse::<f32>()
+            .map_err(|_| format!("invalid number `{}`", a))?;
+    }
+    Ok(result)
+}
+
+fn parse_color(args: &[&str]) -> Result<Color, String> {
+    // A single value sets all three channels.
+    if args.len() == 1 {
+        let [v] = parse_floats::<1>(args)?;
+        return Ok(Color { r: v, g: v, b: v });
+    }
+    let [r, g, b] = parse_floats::<3>(args)?;
+    Ok(Color { r, g, b })
+}
+
+// Resolves a 1-based, possibly negative (relative) OBJ index.
+fn resolve_index(text: &str, count: usize, kind: &str) -> Result<usize, String> {
+    let index = text
+        .parse::<i64>()
+        .map_err(|_| format!("invalid {} index `{}`", kind, text))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!(
+            "{} index {} out of range ({} defined)",
+            kind, index, count
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_face_vertex(
+    text: &str,
+    data: &ObjData,
+) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = text.split('/');
+    let position = resolve_index(parts.next().unwrap_or(""), data.positions.len(), "vertex")?;
+    let uv = match parts.next() {
+        None | Some("") => None,
+        Some(t) => Some(resolve_index(t, data.uvs.len(), "texture coordinate")?),
+    };
+    let normal = match parts.next() {
+        None | Some("") => None,
+        Some(t) => Some(resolve_index(t, data.normals.len(), "normal")?),
+    };
+    Ok((position, uv, normal))
+}
+
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, Arc<dyn Material>>, ObjError> {
+    let source = std::fs::read_to_string(path).map_err(|e| ObjError {
+        file: path.to_path_buf(),
+        line: 0,
+        message: e.to_string(),
+    })?;
+
+    let mut parsed: Vec<(String, MtlMaterial)> = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let error = |message: String| ObjError {
+            file: path.to_path_buf(),
+            line: line_index + 1,
+            message,
+        };
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            None => continue,
+            Some(k) if k.starts_with('#') => continue,
+            Some(k) => k,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            let name = args.join(" ");
+            if name.is_empty() {
+                return Err(error("missing material name".to_string()));
+            }
+            parsed.push((name, MtlMaterial::default()));
+            continue;
+        }
+
+        let current = match parsed.last_mut() {
+            Some((_, m)) => m,
+            None => return Err(error(format!("`{}` before any `newmtl`", keyword))),
+        };
+        match keyword {
+            "Kd" => current.diffuse = parse_color(&args).map_err(error)?,
+            "Ks" => current.specular = parse_color(&args).map_err(error)?,
+            "Ke" => current.emission = parse_color(&args).map_err(error)?,
+            "Ns" => current.specular_exponent = parse_floats::<1>(&args).map_err(error)?[0],
+            "Ni" => current.index_of_refraction = parse_floats::<1>(&args).map_err(error)?[0],
+            "d" => current.dissolve = parse_floats::<1>(&args).map_err(error)?[0],
+            "Tr" => current.dissolve = 1.0 - parse_floats::<1>(&args).map_err(error)?[0],
+            "Pr" | "Pm" | "Ps" | "Pc" | "Pcr" => {
+                let value = parse_floats::<1>(&args).map_err(error)?[0];
+                let pbr = current.pbr.get_or_insert([0.5, 0.0, 0.0, 0.0, 0.03]);
+                let index = ["Pr", "Pm", "Ps", "Pc", "Pcr"]
+                    .iter()
+                    .position(|k| *k == keyword)
+                    .unwrap();
+                pbr[index] = value;
+            }
+            "illum" => {
+                current.illumination = args
+                    .first()
+                    .and_then(|a| a.parse().ok())
+                    .ok_or_else(|| error("invalid illumination model".to_string()))?
+            }
+            // Texture maps and other parameters have no equivalent yet.
+            _ => {}
+        }
+    }
+
+    Ok(parsed
+        .into_iter()
+        .map(|(name, m)| (name, m.to_material()))
+        .collect())
+}
+
+impl ObjModel {
+    // Loads an OBJ file and the material libraries it references. Faces
+    // without a material use `default_material`.
+    pub fn load(path: &Path, default_material: Arc<dyn Material>) -> Result<ObjModel, ObjError> {
+        let source = std::fs::read_to_string(path).map_err(|e| ObjError {
+            file: path.to_path_buf(),
+            line: 0,
+            message: e.to_string(),
+        })?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut data = ObjData {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+        };
+        let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+        let mut builders: Vec<MeshBuilder> = Vec::new();
+        let mut builder_lookup: HashMap<(String, String), usize> = HashMap::new();
+        let mut group = "default".to_string();
+        let mut material_name = String::new();
+
+        for (line_index, line) in source.lines().enumerate() {
+            let error = |message: String| ObjError {
+                file: path.to_path_buf(),
+                line: line_index + 1,
+                message,
+            };
+            let mut tokens = line.split_whitespace();
+            let keyword = match tokens.next() {
+                None => continue,
+                Some(k) if k.starts_with('#') => continue,
+                Some(k) => k,
+            };
+            let args: Vec<&str> = tokens.collect();
+
+            match keyword {
+                "v" => {
+                    let [x, y, z] = parse_floats::<3>(&args).map_err(error)?;
+                    data.positions.push(Vec3 { x, y, z });
+                }
+                "vn" => {
+                    let [x, y, z] = parse_floats::<3>(&args).map_err(error)?;
+                    data.normals.push(Vec3 { x, y, z }.normalized());
+                }
+                "vt" => {
+                    // The optional third coordinate is ignored.
+                    let [u, v] = parse_floats::<2>(&args).map_err(error)?;
+                    data.uvs.push((u, v));
+                }
+                "g" | "o" => {
+                    group = if args.is_empty() {
+                        "default".to_string()
+                    } else {
+                        args.join(" ")
+                    };
+                }
+                "usemtl" => {
+                    material_name = args.join(" ");
+                    if !materials.contains_key(&material_name) {
+                        return Err(error(format!("unknown material `{}`", material_name)));
+                    }
+                }
+                "mtllib" => {
+                    for library in &args {
+                        let library_path = directory.join(library);
+                        materials.extend(load_mtl(&library_path)?);
+                    }
+                }
+                "f" => {
+                    if args.len() < 3 {
+                        return Err(error(format!(
+                            "face needs at least 3 vertices, found {}",
+                            args.len()
+                        )));
+                    }
+                    let key = (group.clone(), material_name.clone());
+                    let index = *builder_lookup.entry(key).or_insert_with(|| {
+                        let material = materials
+                            .get(&material_name)
+                            .cloned()
+                            .unwrap_or_else(|| default_material.clone());
+                        builders.push(MeshBuilder::new(group.clone(), material));
+                        builders.len() - 1
+                    });
+                    let builder = &mut builders[index];
+
+                    let mut face = Vec::with_capacity(args.len());
+                    for arg in &args {
+                        let key = parse_face_vertex(arg, &data).map_err(error)?;
+                        face.push(builder.vertex(key, &data));
+                    }
+                    for i in 1..face.len() - 1 {
+                        builder.indices.push([face[0], face[i], face[i + 1]]);
+                    }
+                }
+                // Smoothing groups, lines, points and free-form geometry.
+                _ => {}
+            }
+        }
+
+        let mut groups = Vec::new();
+        for builder in builders {
+            groups.push(builder.build().map_err(|message| ObjError {
+                file: path.to_path_buf(),
+                line: 0,
+                message,
+            })?);
+        }
+        Ok(ObjModel { groups })
+    }
+
+    // A single hierarchy over the triangles of all groups.
+    pub fn into_bvh(self) -> Bvh<Triangle> {
+        let mut triangles = Vec::new();
+        for group in self.groups {
+            triangles.extend(TriangleMesh::triangles(&Arc::new(group.mesh)));
+        }
+        Bvh::new(triangles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intersection::HitRecord;
+    use crate::intersection::Intersectable;
+    use crate::math::Ray;
+
+    // Writes the files to a fresh directory and loads the first as a model.
+    fn load(name: &str, files: &[(&str, &str)]) -> Result<ObjModel, ObjError> {
+        let directory =
+            std::env::temp_dir().join(format!("rust_tracer_test_{}_{}", std::process::id(), name));
+        std::fs::create_dir_all(&directory).unwrap();
+        for (file, contents) in files {
+            std::fs::write(directory.join(file), contents).unwrap();
+        }
+        let default_material: Arc<dyn Material> = Arc::new(material::Lambertian {
+            albedo: texture::solid(Color::black()),
+        });
+        let result = ObjModel::load(&directory.join(files[0].0), default_material);
+        std::fs::remove_dir_all(&directory).unwrap();
+        result
+    }
+
+    fn error(name: &str, files: &[(&str, &str)]) -> (usize, String) {
+        let error = load(name, files).err().unwrap();
+        (error.line, error.message)
+    }
+
+    fn hits(bvh: &Bvh<Triangle>, x: f32, y: f32) -> bool {
+        let ray = Ray {
+            origin: Vec3 { x, y, z: 1.0 },
+            direction: Vec3 {
+                x: 0.0,
+                y: 0.0,
+                z: -1.0,
+            },
+            time: 0.0,
+            wavelength: None,
+        };
+        let mut hit = HitRecord::new();
+        bvh.intersect(&ray, 0.001, f32::INFINITY, &mut hit)
+    }
+
+    const QUAD: &str = "\
+        # A unit quad\n\
+        v 0 0 0\n\
+        v 1 0 0\n\
+        v 1 1 0\n\
+        v 0 1 0\n\
+        vt 0 0\n\
+        vn 0 0 2\n";
+
+    #[test]
+    fn groups_and_materials() {
+        let obj = format!(
+            "mtllib quad.mtl\n{}\
+             g first\n\
+             f 1/1/1 2/1/1 3/1/1 4/1/1\n\
+             g second\n\
+             usemtl lamp\n\
+             f -4 -3 -2\n\
+             usemtl shiny\n\
+             f 1//1 3//1 4//1\n",
+            QUAD
+        );
+        let mtl = "\
+            newmtl lamp\n\
+            Ke 4 4 4\n\
+            newmtl shiny\n\
+            illum 3\n\
+            Ks 0.9\n\
+            Ns 100\n";
+        let model = load("groups", &[("quad.obj", &obj), ("quad.mtl", mtl)]).unwrap();
+        let names: Vec<&str> = model.groups.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["first", "second", "second"]);
+        // The quad is split into two triangles.
+        let bvh = model.into_bvh();
+        assert!(hits(&bvh, 0.2, 0.8));
+        assert!(hits(&bvh, 0.8, 0.2));
+        assert!(!hits(&bvh, 1.2, 0.5));
+    }
+
+    #[test]
+    fn materials() {
+        let directory =
+            std::env::temp_dir().join(format!("rust_tracer_test_{}_mtl", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("materials.mtl");
+        std::fs::write(
+            &path,
+            "newmtl matte\n\
+             Kd 0.5 0.5 0.5\n\
+             \n\
+             newmtl light\n\
+             Ke 1 0.5 0\n\
+             # comment\n\
+             newmtl glass\n\
+             d 0.1\n\
+             Ni 1.33\n",
+        )
+        .unwrap();
+        let materials = load_mtl(&path);
+        std::fs::remove_dir_all(&directory).unwrap();
+        let materials = materials.unwrap();
+        assert_eq!(materials.len(), 3);
+        assert!(materials["light"].is_light());
+        assert!(!materials["matte"].is_light());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("index", &[("a.obj", &format!("{}f 1 2 5\n", QUAD))]),
+            (8, "vertex index 5 out of range (4 defined)".to_string())
+        );
+        assert_eq!(
+            error("zero", &[("a.obj", &format!("{}f 0 1 2\n", QUAD))]),
+            (8, "vertex index 0 out of range (4 defined)".to_string())
+        );
+        assert_eq!(
+            error("uv", &[("a.obj", &format!("{}f 1/2 2/1 3/1\n", QUAD))]),
+            (
+                8,
+                "texture coordinate index 2 out of range (1 defined)".to_string()
+            )
+        );
+        assert_eq!(
+            error("short_face", &[("a.obj", &format!("{}f 1 2\n", QUAD))]),
+            (8, "face needs at least 3 vertices, found 2".to_string())
+        );
+        assert_eq!(
+            error("number", &[("a.obj", "v 1 2 x\n")]),
+            (1, "invalid number `x`".to_string())
+        );
+        assert_eq!(
+            error("count", &[("a.obj", "v 1 2\n")]),
+            (1, "expected 3 numbers, found 2".to_string())
+        );
+        assert_eq!(
+            error("material", &[("a.obj", "usemtl missing\n")]),
+            (1, "unknown material `missing`".to_string())
+        );
+        assert_eq!(
+            error(
+                "mtl",
+                &[("a.obj", "mtllib a.mtl\n"), ("a.mtl", "\nKd 1 1 1\n")]
+            ),
+            (2, "`Kd` before any `newmtl`".to_string())
+        );
+        let missing = load("missing_mtl", &[("a.obj", "mtllib b.mtl\n")])
+            .err()
+            .unwrap();
+        assert_eq!(missing.line, 0);
+        assert!(missing.file.ends_with("b.mtl"));
+    }
+}
@@ -0,0 +1,184 @@
+// Portable float map (.pfm) images: a short text header followed by raw 32 bit
+// floats, stored bottom row first.
+
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Write;
+
+use crate::image::Image;
+use crate::math::Color;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+// Reads a whitespace terminated header token.
+fn read_token<R: Read>(reader: &mut R) -> std::io::Result<String> {
+    let mut token = String::new();
+    let mut byte = [0u8; 1];
+    loop {
+        reader.read_exact(&mut byte)?;
+        if byte[0].is_ascii_whitespace() {
+            if token.is_empty() {
+                continue;
+            }
+            return Ok(token);
+        }
+        token.push(byte[0] as char);
+    }
+}
+
+pub fn read(filename: &str) -> std::io::Result<Image> {
+    let data = std::fs::read(filename)?;
+    // Reading advances the slice past the header.
+    let mut reader = data.as_slice();
+
+    let channels = match read_token(&mut reader)?.as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid("not a PFM file")),
+    };
+    let width: usize = read_token(&mut reader)?
+        .parse()
+        .map_err(|_| invalid("invalid width"))?;
+    let height: usize = read_token(&mut reader)?
+        .parse()
+        .map_err(|_| invalid("invalid height"))?;
+    Image::check_size(width, height)?;
+    // The sign of the scale gives the byte order, its magnitude is unused.
+    let scale: f32 = read_token(&mut reader)?
+        .parse()
+        .map_err(|_| invalid("invalid scale"))?;
+    let little_endian = scale < 0.0;
+
+    // Check the size against the file before allocating anything.
+    let bytes = (width * height)
+        .checked_mul(channels * 4)
+        .and_then(|size| reader.get(..size))
+        .ok_or_else(|| invalid("not enough image data"))?;
+    let values: Vec<f32> = bytes
+        .chunks_exact(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if little_endian {
+                f32::from_le_bytes(b)
+            } else {
+                f32::from_be_bytes(b)
+            }
+        })
+        .collect();
+
+    let mut pixels = vec![Color::black(); width * height];
+    for y in 0..height {
+        let row = height - 1 - y;
+        for x in 0..width {
+            let i = (y * width + x) * channels;
+            pixels[row * width + x] = if channels == 3 {
+                Color {
+                    r: values[i],
+                    g: values[i + 1],
+                    b: values[i + 2],
+                }
+            } else {
+                Color {
+                    r: values[i],
+                    g: values[i],
+                    b: values[i],
+                }
+            };
+        }
+    }
+
+    Ok(Image::from_data(width, height, pixels))
+}
+
+// Writes little endian RGB floats.
+pub fn write(image: &Image, filename: &str) -> std::io::Result<()> {
+    let mut file = BufWriter::new(std::fs::File::create(filename)?);
+    write!(file, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+    for y in (0..image.height()).rev() {
+        for x in 0..image.width() {
+            let color = image.get_pixel(x, y);
+            for value in &[color.r, color.g, color.b] {
+                file.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads `data` as if it came from a PFM file.
+    fn read_bytes(name: &str, data: &[u8]) -> std::io::Result<Image> {
+        let path = std::env::temp_dir().join(format!(
+            "rust_tracer_test_{}_{}.pfm",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, data).unwrap();
+        let result = read(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    #[test]
+    fn reads_grayscale_big_endian() {
+        let mut data = b"Pf\n2 1\n1.0\n".to_vec();
+        data.extend_from_slice(&0.5f32.to_be_bytes());
+        data.extend_from_slice(&2.0f32.to_be_bytes());
+        let image = read_bytes("gray", &data).unwrap();
+        assert_eq!((image.width(), image.height()), (2, 1));
+        assert_eq!(image.get_pixel(0, 0).g, 0.5);
+        assert_eq!(image.get_pixel(1, 0).b, 2.0);
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        let error = read_bytes("empty", b"PF\n0 4\n-1.0\n").err().unwrap();
+        assert_eq!(error.to_string(), "empty image");
+        let error = read_bytes("huge", b"PF\n100000 100000\n-1.0\n")
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "image too large");
+        let error = read_bytes("short", b"PF\n1000 1000\n-1.0\n\0\0\0\0")
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "not enough image data");
+    }
+
+    #[test]
+    fn round_trip() {
+        let (width, height) = (13, 7);
+        let data = (0..width * height)
+            .map(|i| Color {
+                r: i as f32 * 0.1,
+                g: -(i as f32),
+                b: 1e-30 * i as f32,
+            })
+            .collect();
+        let image = Image::from_data(width, height, data);
+        let path = std::env::temp_dir().join(format!(
+            "rust_tracer_test_{}_round_trip.pfm",
+            std::process::id()
+        ));
+        let filename = path.to_str().unwrap();
+        write(&image, filename).unwrap();
+        let result = read(filename);
+        std::fs::remove_file(&path).unwrap();
+        let read_back = result.unwrap();
+
+        assert_eq!(read_back.width(), image.width());
+        assert_eq!(read_back.height(), image.height());
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let (a, e) = (read_back.get_pixel(x, y), image.get_pixel(x, y));
+                assert_eq!((a.r, a.g, a.b), (e.r, e.g, e.b));
+            }
+        }
+    }
+}
@@ -0,0 +1,395 @@
+// PNG images. Written as RGB with 8 or 16 bits per channel; reading supports
+// all non-interlaced color types and bit depths.
+//
+// See: https://www.w3.org/TR/png/
+
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Write;
+
+use crate::image::Image;
+use crate::math::Color;
+use crate::zlib;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for data in chunks {
+        for &byte in data.iter() {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+    !crc
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc32(&[kind, data]).to_be_bytes())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Applies all five filter types to a row and keeps the one with the smallest
+// sum of absolute differences, the heuristic recommended by the standard.
+fn filter_row(row: &[u8], previous: &[u8], bytes_per_pixel: usize, out: &mut Vec<u8>) {
+    let mut best: Option<(u64, u8, Vec<u8>)> = None;
+    for filter in 0..5u8 {
+        let filtered: Vec<u8> = (0..row.len())
+            .map(|i| {
+                let a = if i >= bytes_per_pixel {
+                    row[i - bytes_per_pixel]
+                } else {
+                    0
+                };
+                let b = previous[i];
+                let c = if i >= bytes_per_pixel {
+                    previous[i - bytes_per_pixel]
+                } else {
+                    0
+                };
+                let predictor = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c),
+                };
+                row[i].wrapping_sub(predictor)
+            })
+            .collect();
+        let cost = filtered
+            .iter()
+            .map(|&v| (v as i8).unsigned_abs() as u64)
+            .sum();
+        if best.as_ref().is_none_or(|(c, _, _)| cost < *c) {
+            best = Some((cost, filter, filtered));
+        }
+    }
+    let (_, filter, filtered) = best.unwrap();
+    out.push(filter);
+    out.extend(filtered);
+}
+
+// Writes the image as is; encoding to sRGB or gamma has to happen before.
+pub fn write(image: &Image, filename: &str, bit_depth: u8) -> std::io::Result<()> {
+    assert!(bit_depth == 8 || bit_depth == 16);
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let bytes_per_pixel = 3 * bit_depth as usize / 8;
+
+    let mut raw = Vec::with_capacity(height * (1 + width * bytes_per_pixel));
+    let mut previous = vec![0u8; width * bytes_per_pixel];
+    let mut row = Vec::with_capacity(width * bytes_per_pixel);
+    for y in 0..height as u32 {
+        row.clear();
+        for x in 0..width as u32 {
+            let color = image.get_pixel(x, y);
+            if bit_depth == 8 {
+                let (r, g, b) = color.to_u8();
+                row.extend_from_slice(&[r, g, b]);
+            } else {
+                let (r, g, b) = color.to_u16();
+                row.extend_from_slice(&r.to_be_bytes());
+                row.extend_from_slice(&g.to_be_bytes());
+                row.extend_from_slice(&b.to_be_bytes());
+            }
+        }
+        filter_row(&row, &previous, bytes_per_pixel, &mut raw);
+        std::mem::swap(&mut row, &mut previous);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth, truecolor, deflate, adaptive filtering, no interlacing.
+    header.extend_from_slice(&[bit_depth, 2, 0, 0, 0]);
+
+    let mut file = BufWriter::new(std::fs::File::create(filename)?);
+    file.write_all(&SIGNATURE)?;
+    write_chunk(&mut file, b"IHDR", &header)?;
+    write_chunk(&mut file, b"IDAT", &zlib::compress(&raw))?;
+    write_chunk(&mut file, b"IEND", &[])?;
+    file.flush()
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn unfilter_row(filter: u8, row: &mut [u8], previous: &[u8], bytes_per_pixel: usize) -> bool {
+    for i in 0..row.len() {
+        let a = if i >= bytes_per_pixel {
+            row[i - bytes_per_pixel]
+        } else {
+            0
+        };
+        let b = previous[i];
+        let c = if i >= bytes_per_pixel {
+            previous[i - bytes_per_pixel]
+        } else {
+            0
+        };
+        let predictor = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => return false,
+        };
+        row[i] = row[i].wrapping_add(predictor);
+    }
+    true
+}
+
+// Reads the image with values scaled to [0, 1] as stored in the file, that is
+// usually sRGB encoded. Alpha is ignored.
+pub fn read(filename: &str) -> std::io::Result<Image> {
+    let data = std::fs::read(filename)?;
+    if !data.starts_with(&SIGNATURE) {
+        return Err(invalid("not a PNG file"));
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+    let mut position = SIGNATURE.len();
+    while position + 12 <= data.len() {
+        let length = u32::from_be_bytes([
+            data[position],
+            data[position + 1],
+            data[position + 2],
+            data[position + 3],
+        ]) as usize;
+        let kind = &data[position + 4..position + 8];
+        let body = data
+            .get(position + 8..position + 8 + length)
+            .ok_or_else(|| invalid("truncated chunk"))?;
+        match kind {
+            b"IHDR" if length == 13 => header = Some(body.to_vec()),
+            b"PLTE" => palette = body.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        position += 12 + length;
+    }
+
+    let header = header.ok_or_else(|| invalid("missing IHDR chunk"))?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    Image::check_size(width, height)?;
+    let (bit_depth, color_type, interlace) = (header[8] as usize, header[9], header[12]);
+    if interlace != 0 {
+        return Err(invalid("interlaced PNG files are not supported"));
+    }
+    let channels = match color_type {
+        0 => 1,
+        2 => 3,
+        3 => 1,
+        4 => 2,
+        6 => 4,
+        _ => return Err(invalid("unknown color type")),
+    };
+    if ![1, 2, 4, 8, 16].contains(&bit_depth) {
+        return Err(invalid("unsupported bit depth"));
+    }
+    if color_type == 3 && palette.is_empty() {
+        return Err(invalid("missing palette"));
+    }
+
+    let raw = zlib::decompress(&compressed).map_err(|e| invalid(&e))?;
+    let bits_per_pixel = channels * bit_depth;
+    let stride = (width * bits_per_pixel).div_ceil(8);
+    let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+    if (stride + 1)
+        .checked_mul(height)
+        .is_none_or(|length| raw.len() < length)
+    {
+        return Err(invalid("not enough image data"));
+    }
+
+    let max = ((1u32 << bit_depth) - 1) as f32;
+    // The sample at index `i` of a row, for any bit depth.
+    let sample = |row: &[u8], i: usize| -> u32 {
+        match bit_depth {
+            16 => u16::from_be_bytes([row[2 * i], row[2 * i + 1]]) as u32,
+            8 => row[i] as u32,
+            _ => {
+                let bit = i * bit_depth;
+                let shift = 8 - bit_depth - bit % 8;
+                (row[bit / 8] as u32 >> shift) & ((1 << bit_depth) - 1)
+            }
+        }
+    };
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut previous = vec![0u8; stride];
+    for y in 0..height {
+        let offset = y * (stride + 1);
+        let mut row = raw[offset + 1..offset + 1 + stride].to_vec();
+        if !unfilter_row(raw[offset], &mut row, &previous, bytes_per_pixel) {
+            return Err(invalid("unknown filter type"));
+        }
+        for x in 0..width {
+            let color = match color_type {
+                3 => {
+                    let index = sample(&row, x) as usize;
+                    let entry = palette
+                        .get(3 * index..3 * index + 3)
+                        .ok_or_else(|| invalid("palette index out of range"))?;
+                    Color {
+                        r: entry[0] as f32 / 255.0,
+                        g: entry[1] as f32 / 255.0,
+                        b: entry[2] as f32 / 255.0,
+                    }
+                }
+                0 | 4 => {
+                    let gray = sample(&row, x * channels) as f32 / max;
+                    Color {
+                        r: gray,
+                        g: gray,
+                        b: gray,
+                    }
+                }
+                _ => Color {
+                    r: sample(&row, x * channels) as f32 / max,
+                    g: sample(&row, x * channels + 1) as f32 / max,
+                    b: sample(&row, x * channels + 2) as f32 / max,
+                },
+            };
+            pixels.push(color);
+        }
+        previous = row;
+    }
+
+    Ok(Image::from_data(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Color;
+
+    // Values that are exact at the bit depth, varied enough to exercise all
+    // row filters.
+    fn test_image(levels: f32) -> Image {
+        let (width, height) = (37, 23);
+        let data = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                Color {
+                    r: ((x * 7.0 + y * 3.0) % (levels + 1.0)) / levels,
+                    g: ((x * y * 13.0) % (levels + 1.0)) / levels,
+                    b: if x > y {
+                        1.0
+                    } else {
+                        (x / 36.0 * levels).round() / levels
+                    },
+                }
+            })
+            .collect();
+        Image::from_data(width, height, data)
+    }
+
+    fn round_trip(bit_depth: u8, levels: f32) {
+        let image = test_image(levels);
+        let path = std::env::temp_dir().join(format!(
+            "rust_tracer_test_{}_{}.png",
+            std::process::id(),
+            bit_depth
+        ));
+        let filename = path.to_str().unwrap();
+        write(&image, filename, bit_depth).unwrap();
+        let result = read(filename);
+        std::fs::remove_file(&path).unwrap();
+        let read_back = result.unwrap();
+
+        assert_eq!(read_back.width(), image.width());
+        assert_eq!(read_back.height(), image.height());
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                let expected = image.get_pixel(x, y);
+                let actual = read_back.get_pixel(x, y);
+                if bit_depth == 8 {
+                    assert_eq!(actual.to_u8(), expected.to_u8());
+                } else {
+                    assert_eq!(actual.to_u16(), expected.to_u16());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_8_bit() {
+        round_trip(8, 255.0);
+    }
+
+    #[test]
+    fn round_trip_16_bit() {
+        round_trip(16, 65535.0);
+    }
+
+    // Reads `data` as if it came from a PNG file.
+    fn read_bytes(name: &str, data: &[u8]) -> std::io::Result<Image> {
+        let path = std::env::temp_dir().join(format!(
+            "rust_tracer_test_{}_{}.png",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, data).unwrap();
+        let result = read(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn header_only(width: u32, height: u32) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        let mut data = SIGNATURE.to_vec();
+        write_chunk(&mut data, b"IHDR", &header).unwrap();
+        write_chunk(&mut data, b"IEND", &[]).unwrap();
+        data
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(read_bytes("invalid", b"P6\n1 1\n255\n\0\0\0").is_err());
+    }
+
+    #[test]
+    fn rejects_empty_images() {
+        let error = read_bytes("empty", &header_only(0, 16)).err().unwrap();
+        assert_eq!(error.to_string(), "empty image");
+    }
+
+    #[test]
+    fn rejects_huge_images() {
+        let error = read_bytes("huge", &header_only(1 << 31, 1 << 31))
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "image too large");
+    }
+}
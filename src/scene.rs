@@ -0,0 +1,1475 @@
+// Loads scene descriptions from TOML files.
+//
+// A scene file has an [image] and a [render] section, a [camera], an optional
+// [background], any number of named [materials.<name>] and an [[objects]]
+// entry per object. Objects reference materials by name or define them
+// inline:
+//
+//   [materials.glass]
+//   type = "dielectric"
+//   index_of_refraction = 1.5
+//
+//   [[objects]]
+//   type = "sphere"
+//   center = [0.0, 0.0, -1.0]
+//   radius = 0.5
+//   material = "glass"
+//
+// Supported object types are "sphere" (moving from `center` at frame time 0 to
+// `end_center` at time 1 if given), "triangle" (three `vertices`), "box"
+// (corners `min` and `max`), "mesh" (`positions`, `indices` and optionally
+// per-vertex `normals`/`uvs`) and "obj" (a Wavefront `file`, optionally
+// restricted to some `groups`; materials come from the file unless `material`
+// is given).
+//
+// A "medium" object fills a closed `boundary` object with smoke of some
+// `density`, `albedo` and `anisotropy` (the Henyey-Greenstein asymmetry,
+// isotropic if left out). An optional [fog] section with the same keys fills
+// the space around the camera and all objects. Media with a `density_grid`
+// (a Mitsuba .vol file scaled by `density`) fill the bounds of the grid
+// instead and glow like fire if they also have a `temperature_grid`, in
+// Kelvin after multiplying by `temperature_scale`; `emission` sets the
+// brightness of the hottest voxel.
+//
+// Objects defined under [prototypes.<name>] are only built once and placed
+// with "instance" objects naming the `prototype`. Any object can have a
+// `transform` table with `scale`, `rotate` (degrees) and `translate`.
+// Spheres, triangles, meshes and models with a "diffuse_light" material or a
+// "principled" one with an `emission` become lights, unless they are
+// transformed, instanced or moving.
+//
+// Material parameters like `albedo` or `roughness` take a color, a number, the
+// name of a texture from [textures.<name>] or an inline texture table. Texture
+// types are "solid" (`color`), "checker" (`even` and `odd` textures, `scale`,
+// `space` = "uv" or "world"), "image" (a `file`, `wrap`/`wrap_u`/`wrap_v` =
+// "repeat", "clamp" or "mirror", `color_space` = "srgb" or "linear") and
+// "perlin", "turbulence" and "marble" noise (`scale`, `octaves`, `color`,
+// `seed`). Any material can be filtered by a measured `spectrum`, which is
+// only fully resolved in spectral mode.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::background;
+use crate::background::Background;
+use crate::bvh::Bvh;
+use crate::camera::Camera;
+use crate::camera::CubeMapCamera;
+use crate::camera::EquirectangularCamera;
+use crate::camera::FisheyeCamera;
+use crate::camera::FisheyeMapping;
+use crate::camera::OrthographicCamera;
+use crate::camera::PerspectiveCamera;
+use crate::camera::Shutter;
+use crate::grid::Grid;
+use crate::image::Encoding;
+use crate::image::Image;
+use crate::image::ImageFormat;
+use crate::image::Wrap;
+use crate::intersection::HitRecord;
+use crate::intersection::Intersectable;
+use crate::intersection::Sphere;
+use crate::light::LightList;
+use crate::material;
+use crate::material::Material;
+use crate::math::Color;
+use crate::math::Mat4;
+use crate::math::Ray;
+use crate::math::Vec3;
+use crate::mesh::Triangle;
+use crate::mesh::TriangleMesh;
+use crate::obj::ObjError;
+use crate::obj::ObjModel;
+use crate::spectrum;
+use crate::spectrum::Dispersion;
+use crate::spectrum::Spectrum;
+use crate::texture;
+use crate::texture::Checker;
+use crate::texture::CheckerSpace;
+use crate::texture::ImageTexture;
+use crate::texture::NoiseKind;
+use crate::texture::NoiseTexture;
+use crate::texture::Perlin;
+use crate::texture::Texture;
+use crate::toml;
+use crate::toml::Item;
+use crate::toml::Position;
+use crate::toml::Table;
+use crate::toml::Value;
+use crate::tonemap;
+use crate::tonemap::ToneMapping;
+use crate::transform::Transformed;
+use crate::volume::BlackbodyEmission;
+use crate::volume::ConstantMedium;
+use crate::volume::GridMedium;
+use crate::volume::PhaseFunction;
+
+pub struct RenderSettings {
+    pub samples_per_pixel: u32,
+    pub max_recursion_depth: u32,
+    pub image_encoding: Encoding,
+    pub render_threads: u32,
+    // Exposure adjustment in stops, applied before tone mapping.
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+    // Trace paths at a few wavelengths each instead of in RGB.
+    pub spectral: bool,
+}
+
+pub struct CameraDescription {
+    pub position: Vec3,
+    pub target: Vec3,
+    pub up: Vec3,
+    pub projection: Projection,
+    // Frame times at which the shutter opens and closes.
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+}
+
+pub enum Projection {
+    Perspective {
+        fovy: f32,
+        // Lens diameter, zero for a pinhole camera.
+        aperture: f32,
+        focus: Focus,
+    },
+    Orthographic {
+        view_height: f32,
+    },
+    Fisheye {
+        fov: f32,
+        mapping: FisheyeMapping,
+    },
+    Equirectangular,
+    CubeMap,
+}
+
+// Where a camera with an aperture focuses.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Focus {
+    Target,
+    Distance(f32),
+    // On whatever the center ray hits, or the target if it hits nothing.
+    Auto,
+}
+
+pub struct Scene {
+    pub image_width: usize,
+    pub image_height: usize,
+    pub render_settings: RenderSettings,
+    pub camera: CameraDescription,
+    pub world: Bvh<Box<dyn Intersectable>>,
+    // The emissive surfaces of the world, for direct light sampling.
+    pub lights: LightList,
+    pub background: Box<dyn Background>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(toml::Error),
+    // A model referenced at the given position failed to load.
+    Obj(Position, ObjError),
+}
+
+// A table from the scene file that keeps track of which keys were read, so
+// that misspelled or unsupported keys can be reported.
+struct Section<'a> {
+    name: String,
+    table: &'a Table,
+    position: Position,
+    used: RefCell<Vec<String>>,
+}
+
+// Named textures, materials and prototypes. Definitions can only refer to
+// ones defined before them.
+#[derive(Default)]
+struct Library {
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    prototypes: HashMap<String, Arc<dyn Intersectable>>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            samples_per_pixel: 50,
+            max_recursion_depth: 12,
+            image_encoding: Encoding::Srgb,
+            render_threads: 16,
+            exposure: 0.0,
+            tone_mapping: ToneMapping::Clamp,
+            spectral: false,
+        }
+    }
+}
+
+impl CameraDescription {
+    pub fn build<I: Intersectable>(&self, aspect_ratio: f32, world: &I) -> Box<dyn Camera> {
+        Box::new(Shutter::new(
+            self.build_projection(aspect_ratio, world),
+            self.shutter_open,
+            self.shutter_close,
+        ))
+    }
+
+    fn build_projection<I: Intersectable>(&self, aspect_ratio: f32, world: &I) -> Box<dyn Camera> {
+        let (position, target, up) = (self.position, self.target, self.up);
+        match self.projection {
+            Projection::Perspective {
+                fovy,
+                aperture,
+                focus,
+            } => {
+                let camera = PerspectiveCamera::new(position, target, up, fovy, aspect_ratio);
+                if aperture <= 0.0 {
+                    return Box::new(camera);
+                }
+                Box::new(camera.with_lens(aperture, self.focus_distance(focus, world)))
+            }
+            Projection::Orthographic { view_height } => Box::new(OrthographicCamera::new(
+                position,
+                target,
+                up,
+                view_height,
+                aspect_ratio,
+            )),
+            Projection::Fisheye { fov, mapping } => Box::new(FisheyeCamera::new(
+                position,
+                target,
+                up,
+                fov,
+                mapping,
+                aspect_ratio,
+            )),
+            Projection::Equirectangular => {
+                Box::new(EquirectangularCamera::new(position, target, up))
+            }
+            Projection::CubeMap => Box::new(CubeMapCamera::new(position, target, up)),
+        }
+    }
+
+    fn focus_distance<I: Intersectable>(&self, focus: Focus, world: &I) -> f32 {
+        let view = self.target - self.position;
+        match focus {
+            Focus::Target => view.mag(),
+            Focus::Distance(distance) => distance,
+            Focus::Auto => {
+                let ray = Ray {
+                    origin: self.position,
+                    direction: view.normalized(),
+                    time: self.shutter_open,
+                    wavelength: None,
+                };
+                let mut hit_record = HitRecord::new();
+                if world.intersect_surface(&ray, 0.0001, f32::INFINITY, &mut hit_record) {
+                    hit_record.t
+                } else {
+                    view.mag()
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "{}", e),
+            SceneError::Parse(e) => write!(f, "{}", e),
+            SceneError::Obj(position, e) => write!(f, "{}: {}", position, e),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+    fn from(e: std::io::Error) -> SceneError {
+        SceneError::Io(e)
+    }
+}
+
+impl From<toml::Error> for SceneError {
+    fn from(e: toml::Error) -> SceneError {
+        SceneError::Parse(e)
+    }
+}
+
+fn error<T>(position: Position, message: String) -> Result<T, SceneError> {
+    Err(SceneError::Parse(toml::Error::new(position, message)))
+}
+
+fn as_float(item: &Item) -> Result<f32, SceneError> {
+    match item.value {
+        Value::Float(f) => Ok(f as f32),
+        Value::Integer(i) => Ok(i as f32),
+        ref other => error(
+            item.position,
+            format!("expected a number, found {}", other.type_name()),
+        ),
+    }
+}
+
+fn as_array(item: &Item) -> Result<&Vec<Item>, SceneError> {
+    match &item.value {
+        Value::Array(values) => Ok(values),
+        other => error(
+            item.position,
+            format!("expected an array, found {}", other.type_name()),
+        ),
+    }
+}
+
+fn as_index(item: &Item) -> Result<usize, SceneError> {
+    match item.value {
+        Value::Integer(i) if i >= 0 => Ok(i as usize),
+        Value::Integer(_) => error(item.position, "expected a non-negative index".to_string()),
+        ref other => error(
+            item.position,
+            format!("expected an integer, found {}", other.type_name()),
+        ),
+    }
+}
+
+fn as_vec3(item: &Item) -> Result<Vec3, SceneError> {
+    let [x, y, z] = as_floats::<3>(item)?;
+    Ok(Vec3 { x, y, z })
+}
+
+fn as_floats<const N: usize>(item: &Item) -> Result<[f32; N], SceneError> {
+    match &item.value {
+        Value::Array(values) if values.len() == N => {
+            let mut result = [0.0; N];
+            for (r, v) in result.iter_mut().zip(values) {
+                *r = as_float(v)?;
+            }
+            Ok(result)
+        }
+        Value::Array(values) => error(
+            item.position,
+            format!("expected {} numbers, found {}", N, values.len()),
+        ),
+        other => error(
+            item.position,
+            format!(
+                "expected an array of {} numbers, found {}",
+                N,
+                other.type_name()
+            ),
+        ),
+    }
+}
+
+impl<'a> Section<'a> {
+    fn new(name: String, item: &'a Item) -> Result<Section<'a>, SceneError> {
+        match &item.value {
+            Value::Table(table) => Ok(Section {
+                name,
+                table,
+                position: item.position,
+                used: RefCell::new(Vec::new()),
+            }),
+            other => error(
+                item.position,
+                format!(
+                    "expected `{}` to be a table, found {}",
+                    name,
+                    other.type_name()
+                ),
+            ),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&'a Item> {
+        self.used.borrow_mut().push(key.to_string());
+        self.table.get(key)
+    }
+
+    fn require(&self, key: &str) -> Result<&'a Item, SceneError> {
+        match self.get(key) {
+            Some(item) => Ok(item),
+            None => error(
+                self.position,
+                format!("missing key `{}` in {}", key, self.name),
+            ),
+        }
+    }
+
+    fn float(&self, key: &str) -> Result<f32, SceneError> {
+        as_float(self.require(key)?)
+    }
+
+    fn float_or(&self, key: &str, default: f32) -> Result<f32, SceneError> {
+        self.get(key).map_or(Ok(default), as_float)
+    }
+
+    fn positive_integer_or(&self, key: &str, default: u32) -> Result<u32, SceneError> {
+        match self.get(key) {
+            None => Ok(default),
+            Some(Item {
+                value: Value::Integer(i),
+                position,
+            }) => {
+                if *i > 0 && *i <= u32::MAX as i64 {
+                    Ok(*i as u32)
+                } else {
+                    error(*position, format!("`{}` must be a positive integer", key))
+                }
+            }
+            Some(item) => error(
+                item.position,
+                format!("expected an integer, found {}", item.value.type_name()),
+            ),
+        }
+    }
+
+    fn boolean_or(&self, key: &str, default: bool) -> Result<bool, SceneError> {
+        match self.get(key) {
+            None => Ok(default),
+            Some(Item {
+                value: Value::Boolean(b),
+                ..
+            }) => Ok(*b),
+            Some(item) => error(
+                item.position,
+                format!("expected a boolean, found {}", item.value.type_name()),
+            ),
+        }
+    }
+
+    fn vec3(&self, key: &str) -> Result<Vec3, SceneError> {
+        as_vec3(self.require(key)?)
+    }
+
+    fn vec3_or(&self, key: &str, default: Vec3) -> Result<Vec3, SceneError> {
+        match self.get(key) {
+            None => Ok(default),
+            Some(_) => self.vec3(key),
+        }
+    }
+
+    // An optional array whose elements are converted with `f`. Missing arrays
+    // are treated as empty.
+    fn list<T>(
+        &self,
+        key: &str,
+        f: impl Fn(&Item) -> Result<T, SceneError>,
+    ) -> Result<Vec<T>, SceneError> {
+        match self.get(key) {
+            None => Ok(Vec::new()),
+            Some(item) => as_array(item)?.iter().map(f).collect(),
+        }
+    }
+
+    fn color(&self, key: &str) -> Result<Color, SceneError> {
+        let [r, g, b] = as_floats::<3>(self.require(key)?)?;
+        Ok(Color { r, g, b })
+    }
+
+    fn color_or(&self, key: &str, default: Color) -> Result<Color, SceneError> {
+        match self.get(key) {
+            None => Ok(default),
+            Some(_) => self.color(key),
+        }
+    }
+
+    fn string(&self, key: &str) -> Result<(&'a str, Position), SceneError> {
+        let item = self.require(key)?;
+        match &item.value {
+            Value::String(s) => Ok((s.as_str(), item.position)),
+            other => error(
+                item.position,
+                format!("expected a string, found {}", other.type_name()),
+            ),
+        }
+    }
+
+    // Fails on the first key that was never read.
+    fn finish(self) -> Result<(), SceneError> {
+        let used = self.used.borrow();
+        for (key, item) in self.table.iter() {
+            if !used.contains(key) {
+                return error(
+                    item.position,
+                    format!("unknown key `{}` in {}", key, self.name),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_material(
+    section: Section,
+    library: &Library,
+    directory: &Path,
+) -> Result<Arc<dyn Material>, SceneError> {
+    let (kind, position) = section.string("type")?;
+    let texture = |key: &str| parse_texture_parameter(section.require(key)?, library, directory);
+    let roughness = || match section.get("roughness") {
+        Some(_) => texture("roughness"),
+        None => Ok(texture::constant(0.0)),
+    };
+    let material: Arc<dyn Material> = match kind {
+        "constant" => Arc::new(material::Constant {
+            color: section.color("color")?,
+        }),
+        "lambertian" => Arc::new(material::Lambertian {
+            albedo: texture("albedo")?,
+        }),
+        "metal" => Arc::new(material::Metal {
+            albedo: texture("albedo")?,
+            roughness: roughness()?,
+        }),
+        "conductor" => {
+            let (eta, k) = match section.get("preset") {
+                Some(_) => {
+                    let (name, position) = section.string("preset")?;
+                    match material::CONDUCTORS.iter().find(|c| c.0 == name) {
+                        Some(&(_, eta, k)) => (eta, k),
+                        None => return error(position, format!("unknown conductor `{}`", name)),
+                    }
+                }
+                None => (section.color("eta")?, section.color("k")?),
+            };
+            Arc::new(material::Conductor {
+                eta,
+                k,
+                roughness: roughness()?,
+            })
+        }
+        "dielectric" => {
+            let dispersion = parse_dispersion(&section)?;
+            let index_of_refraction = match dispersion {
+                Some(dispersion) => section.float_or(
+                    "index_of_refraction",
+                    dispersion.index_of_refraction(spectrum::LAMBDA_D),
+                )?,
+                None => section.float("index_of_refraction")?,
+            };
+            Arc::new(material::Dielectric {
+                index_of_refraction,
+                roughness: roughness()?,
+                absorption: material::Dielectric::absorption(
+                    &section.color_or("attenuation_color", Color::white())?,
+                    section.float_or("density", 1.0)?,
+                ),
+                dispersion,
+            })
+        }
+        "principled" => {
+            let parameter = |key: &str, default: f32| match section.get(key) {
+                Some(_) => texture(key),
+                None => Ok(texture::constant(default)),
+            };
+            let emission = match section.get("emission") {
+                Some(_) => Some(texture("emission")?),
+                None => None,
+            };
+            Arc::new(material::Principled {
+                base_color: match section.get("base_color") {
+                    Some(_) => texture("base_color")?,
+                    None => texture::constant(0.8),
+                },
+                metallic: parameter("metallic", 0.0)?,
+                roughness: parameter("roughness", 0.5)?,
+                specular: parameter("specular", 0.5)?,
+                sheen: parameter("sheen", 0.0)?,
+                sheen_tint: parameter("sheen_tint", 0.5)?,
+                clearcoat: parameter("clearcoat", 0.0)?,
+                clearcoat_roughness: parameter("clearcoat_roughness", 0.03)?,
+                transmission: parameter("transmission", 0.0)?,
+                index_of_refraction: section.float_or("index_of_refraction", 1.5)?,
+                emission,
+                emission_strength: section.float_or("emission_strength", 1.0)?,
+            })
+        }
+        "diffuse_light" => {
+            // A spectrum alone is enough to describe the light.
+            let emit = match section.get("spectrum") {
+                Some(_) => section.color_or("emit", Color::white())?,
+                None => section.color("emit")?,
+            };
+            Arc::new(material::DiffuseLight {
+                emit: emit * section.float_or("intensity", 1.0)?,
+            })
+        }
+        _ => return error(position, format!("unknown material type `{}`", kind)),
+    };
+    let material: Arc<dyn Material> = match section.get("spectrum") {
+        Some(item) => Arc::new(material::SpectralTint {
+            material,
+            spectrum: Arc::new(parse_spectrum(item, directory)?),
+        }),
+        None => material,
+    };
+    section.finish()?;
+    Ok(material)
+}
+
+// A measured spectrum given by `wavelengths` in nanometers and `values`, read
+// from a `file` with a wavelength and a value per line, or the emission of a
+// black body at `temperature` Kelvin.
+fn parse_spectrum(item: &Item, directory: &Path) -> Result<Spectrum, SceneError> {
+    let section = Section::new("spectrum".to_string(), item)?;
+    let spectrum = if section.get("temperature").is_some() {
+        let temperature = section.float("temperature")?;
+        if temperature <= 0.0 {
+            return error(item.position, "`temperature` must be positive".to_string());
+        }
+        Spectrum::blackbody(temperature)
+    } else {
+        let (wavelengths, values) = if section.get("file").is_some() {
+            let (file, position) = section.string("file")?;
+            read_spectrum(file, position, directory)?
+        } else {
+            (
+                section.list("wavelengths", as_float)?,
+                section.list("values", as_float)?,
+            )
+        };
+        Spectrum::new(wavelengths, values).or_else(|e| error(item.position, e))?
+    };
+    section.finish()?;
+    Ok(spectrum)
+}
+
+// Two columns of numbers separated by whitespace or commas. Lines starting
+// with `#` are comments.
+fn read_spectrum(
+    file: &str,
+    position: Position,
+    directory: &Path,
+) -> Result<(Vec<f32>, Vec<f32>), SceneError> {
+    let read_error = |message: String| {
+        SceneError::Parse(toml::Error::new(
+            position,
+            format!("cannot read `{}`: {}", file, message),
+        ))
+    };
+    let source =
+        std::fs::read_to_string(directory.join(file)).map_err(|e| read_error(e.to_string()))?;
+    let (mut wavelengths, mut values) = (Vec::new(), Vec::new());
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let columns: Vec<f32> = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|column| !column.is_empty())
+            .map(|column| column.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| read_error(format!("invalid number on line {}", number + 1)))?;
+        if columns.len() != 2 {
+            return Err(read_error(format!(
+                "expected two columns on line {}",
+                number + 1
+            )));
+        }
+        wavelengths.push(columns[0]);
+        values.push(columns[1]);
+    }
+    Ok((wavelengths, values))
+}
+
+// The wavelength dependent index of refraction of a dielectric: a glass
+// `preset`, `cauchy` = [a, b] or `sellmeier_b` and `sellmeier_c`.
+fn parse_dispersion(section: &Section) -> Result<Option<Dispersion>, SceneError> {
+    if section.get("preset").is_some() {
+        let (name, position) = section.string("preset")?;
+        return match spectrum::GLASSES.iter().find(|g| g.0 == name) {
+            Some(&(_, dispersion)) => Ok(Some(dispersion)),
+            None => error(position, format!("unknown glass `{}`", name)),
+        };
+    }
+    if let Some(item) = section.get("cauchy") {
+        let [a, b] = as_floats::<2>(item)?;
+        return Ok(Some(Dispersion::Cauchy { a, b }));
+    }
+    match (section.get("sellmeier_b"), section.get("sellmeier_c")) {
+        (Some(b), Some(c)) => Ok(Some(Dispersion::Sellmeier {
+            b: as_floats::<3>(b)?,
+            c: as_floats::<3>(c)?,
+        })),
+        (Some(item), None) | (None, Some(item)) => error(
+            item.position,
+            "`sellmeier_b` and `sellmeier_c` go together".to_string(),
+        ),
+        (None, None) => Ok(None),
+    }
+}
+
+// A material parameter: a color, a number, the name of a texture or an inline
+// texture table.
+fn parse_texture_parameter(
+    item: &Item,
+    library: &Library,
+    directory: &Path,
+) -> Result<Arc<dyn Texture>, SceneError> {
+    match &item.value {
+        Value::Array(_) => {
+            let [r, g, b] = as_floats::<3>(item)?;
+            Ok(texture::solid(Color { r, g, b }))
+        }
+        Value::Float(_) | Value::Integer(_) => Ok(texture::constant(as_float(item)?)),
+        Value::String(name) => match library.textures.get(name) {
+            Some(t) => Ok(t.clone()),
+            None => error(item.position, format!("unknown texture `{}`", name)),
+        },
+        Value::Table(_) => parse_texture(
+            Section::new("texture".to_string(), item)?,
+            library,
+            directory,
+        ),
+        other => error(
+            item.position,
+            format!(
+                "expected a color, number or texture, found {}",
+                other.type_name()
+            ),
+        ),
+    }
+}
+
+fn parse_wrap(section: &Section, key: &str, default: Wrap) -> Result<Wrap, SceneError> {
+    if section.get(key).is_none() {
+        return Ok(default);
+    }
+    match section.string(key)? {
+        ("repeat", _) => Ok(Wrap::Repeat),
+        ("clamp", _) => Ok(Wrap::Clamp),
+        ("mirror", _) => Ok(Wrap::Mirror),
+        (name, position) => error(position, format!("unknown wrap mode `{}`", name)),
+    }
+}
+
+// Reads an image from a file named relative to the scene. 8 and 16 bit
+// formats are decoded from sRGB unless `linear` is set.
+fn read_image(
+    file: &str,
+    position: Position,
+    directory: &Path,
+    linear: bool,
+) -> Result<Image, SceneError> {
+    let path = directory.join(file).to_string_lossy().into_owned();
+    let mut image = Image::read(&path).map_err(|e| {
+        SceneError::Parse(toml::Error::new(
+            position,
+            format!("cannot read `{}`: {}", file, e),
+        ))
+    })?;
+    let stored_linear = ImageFormat::from_path(&path).is_none_or(|f| f.is_linear());
+    if !stored_linear && !linear {
+        image.decode(Encoding::Srgb);
+    }
+    Ok(image)
+}
+
+// Reads a voxel grid from a file named relative to the scene.
+fn read_grid(file: &str, position: Position, directory: &Path) -> Result<Grid, SceneError> {
+    Grid::read(&directory.join(file)).map_err(|e| {
+        SceneError::Parse(toml::Error::new(
+            position,
+            format!("cannot read `{}`: {}", file, e),
+        ))
+    })
+}
+
+fn parse_texture(
+    section: Section,
+    library: &Library,
+    directory: &Path,
+) -> Result<Arc<dyn Texture>, SceneError> {
+    let (kind, position) = section.string("type")?;
+    let parameter = |key: &str| parse_texture_parameter(section.require(key)?, library, directory);
+    let texture: Arc<dyn Texture> = match kind {
+        "solid" => texture::solid(section.color("color")?),
+        "checker" => {
+            let space = match section.get("space") {
+                None => CheckerSpace::Uv,
+                Some(_) => match section.string("space")? {
+                    ("uv", _) => CheckerSpace::Uv,
+                    ("world", _) => CheckerSpace::World,
+                    (name, position) => {
+                        return error(position, format!("unknown checker space `{}`", name))
+                    }
+                },
+            };
+            Arc::new(Checker {
+                even: parameter("even")?,
+                odd: parameter("odd")?,
+                scale: section.float_or("scale", 10.0)?,
+                space,
+            })
+        }
+        "image" => {
+            let (file, file_position) = section.string("file")?;
+            let linear = match section.get("color_space") {
+                None => false,
+                Some(_) => match section.string("color_space")? {
+                    ("srgb", _) => false,
+                    ("linear", _) => true,
+                    (name, position) => {
+                        return error(position, format!("unknown color space `{}`", name))
+                    }
+                },
+            };
+            let wrap = parse_wrap(&section, "wrap", Wrap::Repeat)?;
+            Arc::new(ImageTexture {
+                image: read_image(file, file_position, directory, linear)?,
+                wrap_u: parse_wrap(&section, "wrap_u", wrap)?,
+                wrap_v: parse_wrap(&section, "wrap_v", wrap)?,
+            })
+        }
+        "perlin" | "turbulence" | "marble" => Arc::new(NoiseTexture {
+            noise: Perlin::new(section.positive_integer_or("seed", 1)? as u64),
+            kind: match kind {
+                "perlin" => NoiseKind::Perlin,
+                "turbulence" => NoiseKind::Turbulence,
+                _ => NoiseKind::Marble,
+            },
+            color: section.color_or("color", Color::white())?,
+            scale: section.float_or("scale", 1.0)?,
+            octaves: section.positive_integer_or("octaves", 7)?,
+        }),
+        _ => return error(position, format!("unknown texture type `{}`", kind)),
+    };
+    section.finish()?;
+    Ok(texture)
+}
+
+fn parse_background(section: Section, directory: &Path) -> Result<Box<dyn Background>, SceneError> {
+    let (kind, position) = section.string("type")?;
+    let background: Box<dyn Background> = match kind {
+        "solid" => Box::new(background::Solid {
+            color: section.color("color")?,
+        }),
+        "gradient" => {
+            let default = background::Gradient::default();
+            Box::new(background::Gradient {
+                bottom: section.color_or("bottom", default.bottom)?,
+                top: section.color_or("top", default.top)?,
+            })
+        }
+        "environment" => {
+            let (file, file_position) = section.string("file")?;
+            Box::new(background::EnvironmentMap::new(
+                read_image(file, file_position, directory, false)?,
+                section.float_or("rotation", 0.0)?.to_radians(),
+                section.float_or("intensity", 1.0)?,
+            ))
+        }
+        _ => return error(position, format!("unknown background type `{}`", kind)),
+    };
+    section.finish()?;
+    Ok(background)
+}
+
+fn parse_projection(section: &Section) -> Result<Projection, SceneError> {
+    let (kind, position) = match section.get("projection") {
+        Some(_) => section.string("projection")?,
+        None => ("perspective", section.position),
+    };
+    let projection = match kind {
+        "perspective" => {
+            let aperture = section.float_or("aperture", 0.0)?;
+            if aperture < 0.0 {
+                let position = section.require("aperture")?.position;
+                return error(position, "`aperture` must not be negative".to_string());
+            }
+            let mut focus = Focus::Target;
+            if let Some(item) = section.get("focus_distance") {
+                match as_float(item)? {
+                    distance if distance > 0.0 => focus = Focus::Distance(distance),
+                    _ => {
+                        return error(
+                            item.position,
+                            "`focus_distance` must be positive".to_string(),
+                        )
+                    }
+                }
+            }
+            if section.boolean_or("autofocus", false)? {
+                if let Focus::Distance(_) = focus {
+                    let position = section.require("autofocus")?.position;
+                    return error(
+                        position,
+                        "`autofocus` and `focus_distance` are mutually exclusive".to_string(),
+                    );
+                }
+                focus = Focus::Auto;
+            }
+            let fovy = section.float_or("fovy", 60.0)?;
+            if !(fovy > 0.0 && fovy < 180.0) {
+                let position = section.require("fovy")?.position;
+                return error(
+                    position,
+                    "`fovy` must be between 0 and 180 degrees".to_string(),
+                );
+            }
+            Projection::Perspective {
+                fovy,
+                aperture,
+                focus,
+            }
+        }
+        "orthographic" => {
+            let view_height = section.float_or("view_height", 2.0)?;
+            if view_height <= 0.0 {
+                let position = section.require("view_height")?.position;
+                return error(position, "`view_height` must be positive".to_string());
+            }
+            Projection::Orthographic { view_height }
+        }
+        "fisheye" => {
+            let mapping = match section.get("mapping") {
+                None => FisheyeMapping::Equidistant,
+                Some(_) => match section.string("mapping")? {
+                    ("equidistant", _) => FisheyeMapping::Equidistant,
+                    ("equisolid", _) => FisheyeMapping::Equisolid,
+                    (name, position) => {
+                        return error(position, format!("unknown fisheye mapping `{}`", name))
+                    }
+                },
+            };
+            let fov = section.float_or("fov", 180.0)?;
+            if !(fov > 0.0 && fov <= 360.0) {
+                let position = section.require("fov")?.position;
+                return error(
+                    position,
+                    "`fov` must be greater than 0 and at most 360 degrees".to_string(),
+                );
+            }
+            Projection::Fisheye { fov, mapping }
+        }
+        "equirectangular" => Projection::Equirectangular,
+        "cube_map" => Projection::CubeMap,
+        _ => return error(position, format!("unknown projection `{}`", kind)),
+    };
+    Ok(projection)
+}
+
+// Parses a single object, which may be a whole model or mesh.
+// Objects without a `material` get `default_material`, if there is one.
+fn parse_object(
+    object: &Section,
+    library: &Library,
+    directory: &Path,
+    default_material: Option<&Arc<dyn Material>>,
+) -> Result<Box<dyn Intersectable>, SceneError> {
+    let (kind, position) = object.string("type")?;
+    let material = || match (object.get("material"), default_material) {
+        (None, Some(material)) => Ok(material.clone()),
+        _ => parse_object_material(object.require("material")?, library, directory),
+    };
+    let shape: Box<dyn Intersectable> = match kind {
+        "sphere" => {
+            let center = object.vec3("center")?;
+            let radius = object.float("radius")?;
+            let material = material()?;
+            let sphere = match object.get("end_center") {
+                Some(item) => Sphere::moving(center, as_vec3(item)?, radius, material),
+                None => Sphere::new(center, radius, material),
+            };
+            Box::new(sphere)
+        }
+        "triangle" => {
+            let vertices_item = object.require("vertices")?;
+            let vertices = object.list("vertices", as_vec3)?;
+            if vertices.len() != 3 {
+                return error(
+                    vertices_item.position,
+                    format!("expected 3 vertices, found {}", vertices.len()),
+                );
+            }
+            let material = material()?;
+            Box::new(Triangle::new(
+                vertices[0],
+                vertices[1],
+                vertices[2],
+                material,
+            ))
+        }
+        "obj" => {
+            let (file, file_position) = object.string("file")?;
+            // An explicit material replaces the ones from the file.
+            let material = match object.get("material") {
+                Some(item) => Some(parse_object_material(item, library, directory)?),
+                None => None,
+            };
+            let default_material = material.clone().unwrap_or_else(|| {
+                Arc::new(material::Lambertian {
+                    albedo: texture::solid(Color {
+                        r: 0.8,
+                        g: 0.8,
+                        b: 0.8,
+                    }),
+                })
+            });
+            let groups_item = object.get("groups");
+            let groups = object.list("groups", |item| match &item.value {
+                Value::String(s) => Ok(s.clone()),
+                other => error(
+                    item.position,
+                    format!("expected a group name, found {}", other.type_name()),
+                ),
+            })?;
+
+            let mut model = ObjModel::load(&directory.join(file), default_material)
+                .map_err(|e| SceneError::Obj(file_position, e))?;
+            for name in &groups {
+                if !model.groups.iter().any(|g| &g.name == name) {
+                    return error(
+                        groups_item.unwrap().position,
+                        format!("`{}` has no group `{}`", file, name),
+                    );
+                }
+            }
+            if !groups.is_empty() {
+                model.groups.retain(|g| groups.contains(&g.name));
+            }
+            if let Some(material) = material {
+                for group in &mut model.groups {
+                    group.mesh.set_material(material.clone());
+                }
+            }
+            Box::new(model.into_bvh())
+        }
+        "mesh" => {
+            let positions = object.list("positions", as_vec3)?;
+            let normals = object.list("normals", as_vec3)?;
+            let uvs = object.list("uvs", |item| as_floats::<2>(item).map(|[u, v]| (u, v)))?;
+            let indices = object.list("indices", |item| {
+                let values = as_array(item)?;
+                if values.len() != 3 {
+                    return error(
+                        item.position,
+                        format!("expected 3 indices, found {}", values.len()),
+                    );
+                }
+                Ok([
+                    as_index(&values[0])?,
+                    as_index(&values[1])?,
+                    as_index(&values[2])?,
+                ])
+            })?;
+            let mesh = TriangleMesh::new(positions, normals, uvs, indices, material()?)
+                .map_err(|e| SceneError::Parse(toml::Error::new(object.position, e)))?;
+            Box::new(mesh.into_bvh())
+        }
+        "box" => {
+            let mesh =
+                TriangleMesh::cuboid(&object.vec3("min")?, &object.vec3("max")?, material()?);
+            Box::new(mesh.into_bvh())
+        }
+        "medium" if object.get("density_grid").is_some() => {
+            let (density, albedo, phase) = parse_medium(object)?;
+            let (file, file_position) = object.string("density_grid")?;
+            let grid = read_grid(file, file_position, directory)?;
+            let emission = match object.get("temperature_grid") {
+                Some(_) => {
+                    let (file, file_position) = object.string("temperature_grid")?;
+                    Some(BlackbodyEmission::new(
+                        read_grid(file, file_position, directory)?,
+                        object.float_or("temperature_scale", 1.0)?,
+                        object.float_or("emission", 1.0)?,
+                    ))
+                }
+                None => None,
+            };
+            Box::new(GridMedium::new(grid, density, albedo, phase, emission))
+        }
+        "medium" => {
+            // The boundary only delimits the medium, its surface is invisible.
+            let invisible: Arc<dyn Material> = Arc::new(material::Constant {
+                color: Color::black(),
+            });
+            let section = Section::new("boundary".to_string(), object.require("boundary")?)?;
+            let boundary = parse_object(&section, library, directory, Some(&invisible))?;
+            section.finish()?;
+            let (density, albedo, phase) = parse_medium(object)?;
+            Box::new(ConstantMedium::new(boundary, density, albedo, phase))
+        }
+        "instance" => {
+            let (name, name_position) = object.string("prototype")?;
+            match library.prototypes.get(name) {
+                Some(prototype) => Box::new(prototype.clone()),
+                None => return error(name_position, format!("unknown prototype `{}`", name)),
+            }
+        }
+        _ => return error(position, format!("unknown object type `{}`", kind)),
+    };
+
+    match object.get("transform") {
+        Some(item) => {
+            let transform = parse_transform(Section::new("transform".to_string(), item)?)?;
+            let transformed = Transformed::new(shape, transform)
+                .map_err(|e| SceneError::Parse(toml::Error::new(item.position, e)))?;
+            Ok(Box::new(transformed))
+        }
+        None => Ok(shape),
+    }
+}
+
+// The `density`, `albedo` and phase function of a medium, isotropic unless
+// an `anisotropy` is given for Henyey-Greenstein.
+fn parse_medium(section: &Section) -> Result<(f32, Color, PhaseFunction), SceneError> {
+    let density = section.float("density")?;
+    if density <= 0.0 {
+        let position = section.require("density")?.position;
+        return error(position, "`density` must be positive".to_string());
+    }
+    let albedo = section.color_or("albedo", Color::white())?;
+    let phase = match section.get("anisotropy") {
+        Some(item) => {
+            let g = as_float(item)?;
+            if g <= -1.0 || g >= 1.0 {
+                return error(
+                    item.position,
+                    "`anisotropy` must be between -1 and 1".to_string(),
+                );
+            }
+            PhaseFunction::HenyeyGreenstein(g)
+        }
+        None => PhaseFunction::Isotropic,
+    };
+    Ok((density, albedo, phase))
+}
+
+// Scale, then rotate (in degrees, around x, y and z in that order), then
+// translate.
+fn parse_transform(section: Section) -> Result<Mat4, SceneError> {
+    let one = Vec3 {
+        x: 1.0,
+        y: 1.0,
+        z: 1.0,
+    };
+    let scale = match section.get("scale") {
+        Some(Item {
+            value: Value::Array(_),
+            ..
+        }) => section.vec3("scale")?,
+        Some(_) => one * section.float("scale")?,
+        None => one,
+    };
+    let rotate = section.vec3_or("rotate", Vec3::zero())?;
+    let translate = section.vec3_or("translate", Vec3::zero())?;
+    section.finish()?;
+
+    let axis = |x, y, z| Vec3 { x, y, z };
+    Ok(Mat4::translation(&translate)
+        * Mat4::rotation(&axis(0.0, 0.0, 1.0), rotate.z.to_radians())
+        * Mat4::rotation(&axis(0.0, 1.0, 0.0), rotate.y.to_radians())
+        * Mat4::rotation(&axis(1.0, 0.0, 0.0), rotate.x.to_radians())
+        * Mat4::scaling(&scale))
+}
+
+fn parse_object_material(
+    item: &Item,
+    library: &Library,
+    directory: &Path,
+) -> Result<Arc<dyn Material>, SceneError> {
+    match &item.value {
+        Value::String(name) => match library.materials.get(name) {
+            Some(m) => Ok(m.clone()),
+            None => error(item.position, format!("unknown material `{}`", name)),
+        },
+        Value::Table(_) => parse_material(
+            Section::new("material".to_string(), item)?,
+            library,
+            directory,
+        ),
+        other => error(
+            item.position,
+            format!(
+                "expected a material name or table, found {}",
+                other.type_name()
+            ),
+        ),
+    }
+}
+
+impl Scene {
+    pub fn load(path: &str) -> Result<Scene, SceneError> {
+        let source = std::fs::read_to_string(path)?;
+        let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        Scene::parse(&source, directory)
+    }
+
+    // Relative file names in the scene are resolved against `directory`.
+    pub fn parse(source: &str, directory: &Path) -> Result<Scene, SceneError> {
+        let root = toml::parse(source)?;
+        let root_item = Item {
+            value: Value::Table(root),
+            position: Position::start(),
+        };
+        let document = Section::new("scene".to_string(), &root_item)?;
+
+        // Image
+        let mut image_width = 1024;
+        let mut image_height = 1024;
+        if let Some(item) = document.get("image") {
+            let image = Section::new("image".to_string(), item)?;
+            image_width = image.positive_integer_or("width", image_width as u32)? as usize;
+            image_height = image.positive_integer_or("height", image_height as u32)? as usize;
+            image.finish()?;
+        }
+
+        // Render settings
+        let mut render_settings = RenderSettings::default();
+        if let Some(item) = document.get("render") {
+            let render = Section::new("render".to_string(), item)?;
+            render_settings.samples_per_pixel = render
+                .positive_integer_or("samples_per_pixel", render_settings.samples_per_pixel)?;
+            render_settings.max_recursion_depth = render
+                .positive_integer_or("max_recursion_depth", render_settings.max_recursion_depth)?;
+            if let Some(item) = render.get("image_gamma") {
+                render_settings.image_encoding = match &item.value {
+                    Value::String(s) if s == "srgb" => Encoding::Srgb,
+                    Value::String(s) => {
+                        return error(item.position, format!("unknown encoding `{}`", s))
+                    }
+                    _ => match as_float(item)? {
+                        gamma if gamma > 0.0 => Encoding::Gamma(gamma),
+                        _ => {
+                            return error(
+                                item.position,
+                                "`image_gamma` must be positive".to_string(),
+                            )
+                        }
+                    },
+                };
+            }
+            render_settings.render_threads =
+                render.positive_integer_or("render_threads", render_settings.render_threads)?;
+            render_settings.exposure = render.float_or("exposure", render_settings.exposure)?;
+            if render.get("tone_mapping").is_some() {
+                let (name, position) = render.string("tone_mapping")?;
+                render_settings.tone_mapping = match ToneMapping::from_name(name) {
+                    Some(ToneMapping::ExtendedReinhard { .. }) => {
+                        let white_point =
+                            render.float_or("white_point", tonemap::DEFAULT_WHITE_POINT)?;
+                        if white_point <= 0.0 {
+                            return error(position, "`white_point` must be positive".to_string());
+                        }
+                        ToneMapping::ExtendedReinhard { white_point }
+                    }
+                    Some(tone_mapping) => tone_mapping,
+                    None => {
+                        return error(
+                            position,
+                            format!("unknown tone mapping operator `{}`", name),
+                        )
+                    }
+                };
+            }
+            render_settings.spectral = render.boolean_or("spectral", render_settings.spectral)?;
+            render.finish()?;
+        }
+
+        // Camera
+        let camera_section = Section::new("camera".to_string(), document.require("camera")?)?;
+        let camera = CameraDescription {
+            position: camera_section.vec3("position")?,
+            target: camera_section.vec3("target")?,
+            up: camera_section.vec3_or("up", Vec3::up())?,
+            projection: parse_projection(&camera_section)?,
+            shutter_open: camera_section.float_or("shutter_open", 0.0)?,
+            shutter_close: camera_section.float_or("shutter_close", 1.0)?,
+        };
+        if camera.shutter_close < camera.shutter_open {
+            // Only one of them may be given, the other being the default.
+            let position = camera_section
+                .get("shutter_close")
+                .or_else(|| camera_section.get("shutter_open"))
+                .map_or(camera_section.position, |item| item.position);
+            return error(
+                position,
+                "`shutter_close` must not be before `shutter_open`".to_string(),
+            );
+        }
+        camera_section.finish()?;
+
+        // Background
+        let background = match document.get("background") {
+            Some(item) => {
+                parse_background(Section::new("background".to_string(), item)?, directory)?
+            }
+            None => Box::new(background::Gradient::default()),
+        };
+
+        let mut library = Library::default();
+
+        // Textures
+        if let Some(item) = document.get("textures") {
+            let section = Section::new("textures".to_string(), item)?;
+            for (name, item) in section.table.iter() {
+                let texture = parse_texture(
+                    Section::new(format!("texture `{}`", name), item)?,
+                    &library,
+                    directory,
+                )?;
+                library.textures.insert(name.clone(), texture);
+            }
+        }
+
+        // Materials
+        if let Some(item) = document.get("materials") {
+            let section = Section::new("materials".to_string(), item)?;
+            for (name, item) in section.table.iter() {
+                let material = parse_material(
+                    Section::new(format!("material `{}`", name), item)?,
+                    &library,
+                    directory,
+                )?;
+                library.materials.insert(name.clone(), material);
+            }
+        }
+
+        // Prototypes are objects that are only visible through instances.
+        if let Some(item) = document.get("prototypes") {
+            let section = Section::new("prototypes".to_string(), item)?;
+            for (name, item) in section.table.iter() {
+                let object = Section::new(format!("prototype `{}`", name), item)?;
+                let prototype = parse_object(&object, &library, directory, None)?;
+                object.finish()?;
+                library
+                    .prototypes
+                    .insert(name.clone(), Arc::from(prototype));
+            }
+        }
+
+        // Objects
+        let mut world: Vec<Box<dyn Intersectable>> = Vec::new();
+        if let Some(item) = document.get("objects") {
+            let objects = match &item.value {
+                Value::Array(objects) => objects,
+                other => {
+                    return error(
+                        item.position,
+                        format!("expected an array of objects, found {}", other.type_name()),
+                    )
+                }
+            };
+            for item in objects {
+                let object = Section::new("object".to_string(), item)?;
+                world.push(parse_object(&object, &library, directory, None)?);
+                object.finish()?;
+            }
+        }
+
+        // Fog fills a box around the camera and all objects.
+        if let Some(item) = document.get("fog") {
+            let section = Section::new("fog".to_string(), item)?;
+            let (density, albedo, phase) = parse_medium(&section)?;
+            section.finish()?;
+            let mut bounds = world.iter().fold(Aabb::empty(), |b, object| {
+                Aabb::union(&b, &object.bounding_box())
+            });
+            bounds.grow(&camera.position);
+            let margin = bounds.extent() * 0.01
+                + Vec3 {
+                    x: 1.0,
+                    y: 1.0,
+                    z: 1.0,
+                };
+            let invisible: Arc<dyn Material> = Arc::new(material::Constant {
+                color: Color::black(),
+            });
+            let boundary =
+                TriangleMesh::cuboid(&(bounds.min - margin), &(bounds.max + margin), invisible);
+            world.push(Box::new(ConstantMedium::new(
+                boundary.into_bvh(),
+                density,
+                albedo,
+                phase,
+            )));
+        }
+
+        document.finish()?;
+
+        let world = Bvh::new(world);
+        let mut lights = Vec::new();
+        world.lights(&mut lights);
+
+        Ok(Scene {
+            image_width,
+            image_height,
+            render_settings,
+            camera,
+            world,
+            lights: LightList::new(lights),
+            background,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn autofocus_ignores_media() {
+        let scene = Scene::parse(
+            "[fog]\n\
+             density = 0.5\n\
+             [camera]\n\
+             position = [0.0, 0.0, 0.0]\n\
+             target = [0.0, 0.0, -1.0]\n\
+             [materials.white]\n\
+             type = \"lambertian\"\n\
+             albedo = [0.5, 0.5, 0.5]\n\
+             [[objects]]\n\
+             type = \"medium\"\n\
+             density = 2.0\n\
+             boundary = { type = \"sphere\", center = [0.0, 0.0, -3.0], radius = 1.0 }\n\
+             [[objects]]\n\
+             type = \"sphere\"\n\
+             center = [0.0, 0.0, -10.0]\n\
+             radius = 1.0\n\
+             material = \"white\"\n",
+            Path::new(""),
+        )
+        .unwrap();
+        for _ in 0..100 {
+            let distance = scene.camera.focus_distance(Focus::Auto, &scene.world);
+            assert!((distance - 9.0).abs() < 1e-4, "focused at {}", distance);
+        }
+    }
+
+    // The error position of a scene with the given camera section.
+    fn camera_error(camera: &str) -> (usize, usize) {
+        let source = format!(
+            "[camera]\nposition = [0, 0, 0]\ntarget = [0, 0, -1]\n{}\n",
+            camera
+        );
+        match Scene::parse(&source, Path::new("")) {
+            Err(SceneError::Parse(e)) => (e.position.line, e.position.column),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("`{}` was accepted", camera),
+        }
+    }
+
+    #[test]
+    fn perspective_field_of_view() {
+        assert_eq!(camera_error("fovy = 0.0"), (4, 8));
+        assert_eq!(camera_error("fovy = -30.0"), (4, 8));
+        assert_eq!(camera_error("fovy = 180.0"), (4, 8));
+    }
+
+    #[test]
+    fn other_projections() {
+        let orthographic = "projection = \"orthographic\"\nview_height";
+        assert_eq!(camera_error(&format!("{} = 0.0", orthographic)), (5, 15));
+        assert_eq!(camera_error(&format!("{} = -2.0", orthographic)), (5, 15));
+        let fisheye = "projection = \"fisheye\"\nfov";
+        assert_eq!(camera_error(&format!("{} = 0.0", fisheye)), (5, 7));
+        assert_eq!(camera_error(&format!("{} = 361.0", fisheye)), (5, 7));
+    }
+}
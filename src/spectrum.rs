@@ -0,0 +1,388 @@
+// Wavelengths of visible light and their conversion to RGB. Paths through
+// dispersive materials carry a single wavelength in nanometers, sampled
+// uniformly over the visible range.
+//
+// In spectral mode every path carries radiance at four wavelengths instead of
+// RGB, see Wilkie et al., "Hero Wavelength Spectral Sampling" (EGSR 2014).
+// Colors from materials, lights and the background are upsampled to spectra
+// at those wavelengths, and the result is projected back to RGB through the
+// CIE color matching functions.
+
+use std::ops;
+use std::sync::OnceLock;
+
+use rand::Rng;
+
+use crate::math::Color;
+
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 780.0;
+
+// The Fraunhofer d line, where indices of refraction are usually quoted.
+pub const LAMBDA_D: f32 = 587.6;
+
+pub fn sample_wavelength() -> f32 {
+    rand::thread_rng().gen_range(LAMBDA_MIN..LAMBDA_MAX)
+}
+
+// CIE 1931 2° color matching functions, using the multi-lobe fit of Wyman,
+// Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ Color
+// Matching Functions" (JCGT 2013).
+pub fn xyz_matching(lambda: f32) -> (f32, f32, f32) {
+    let g = |mu: f32, sigma_below: f32, sigma_above: f32| {
+        let sigma = if lambda < mu {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    let x =
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+// Linear sRGB (D65 white point) from CIE XYZ.
+pub fn xyz_to_rgb(x: f32, y: f32, z: f32) -> Color {
+    Color {
+        r: 3.240_454 * x - 1.537_139 * y - 0.498_531 * z,
+        g: -0.969_266 * x + 1.876_011 * y + 0.041_556 * z,
+        b: 0.055_643 * x - 0.204_026 * y + 1.057_225 * z,
+    }
+}
+
+// The color a path carrying `lambda` contributes, scaled so that averaging
+// over uniformly sampled wavelengths gives white, i.e. a constant spectrum is
+// white. Wavelengths outside the sRGB gamut have negative components.
+pub fn wavelength_to_rgb(lambda: f32) -> Color {
+    static SCALE: OnceLock<Color> = OnceLock::new();
+    let scale = SCALE.get_or_init(|| {
+        let steps = 400;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f32;
+        let mut sum = Color::black();
+        for i in 0..steps {
+            let (x, y, z) = xyz_matching(LAMBDA_MIN + (i as f32 + 0.5) * step);
+            sum += xyz_to_rgb(x, y, z);
+        }
+        let mean = sum / steps as f32;
+        Color {
+            r: 1.0 / mean.r,
+            g: 1.0 / mean.g,
+            b: 1.0 / mean.b,
+        }
+    });
+    let (x, y, z) = xyz_matching(lambda);
+    xyz_to_rgb(x, y, z) * *scale
+}
+
+// Index of refraction as a function of wavelength.
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+    // n = a + b / λ², with λ in micrometers.
+    Cauchy { a: f32, b: f32 },
+    // n² = 1 + Σ b_i λ² / (λ² - c_i), with λ in micrometers.
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+// Sellmeier coefficients of common optical materials.
+pub const GLASSES: [(&str, Dispersion); 4] = [
+    (
+        "bk7",
+        Dispersion::Sellmeier {
+            b: [1.039_612, 0.231_792_34, 1.010_469_5],
+            c: [0.006_000_699, 0.020_017_914, 103.560_65],
+        },
+    ),
+    (
+        "fused_silica",
+        Dispersion::Sellmeier {
+            b: [0.696_166_3, 0.407_942_6, 0.897_479_4],
+            c: [0.004_679_148, 0.013_512_063, 97.934],
+        },
+    ),
+    (
+        "sf11",
+        Dispersion::Sellmeier {
+            b: [1.737_597, 0.313_747_35, 1.898_781],
+            c: [0.013_188_707, 0.062_306_814, 155.236_3],
+        },
+    ),
+    (
+        "diamond",
+        Dispersion::Sellmeier {
+            b: [0.3306, 4.3356, 0.0],
+            c: [0.030_625, 0.011_236, 0.0],
+        },
+    ),
+];
+
+impl Dispersion {
+    pub fn index_of_refraction(&self, lambda: f32) -> f32 {
+        let micrometers = lambda / 1000.0;
+        let l2 = micrometers * micrometers;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>();
+                n2.sqrt()
+            }
+        }
+    }
+}
+
+// Number of wavelengths carried by a path in spectral mode.
+pub const WAVELENGTHS: usize = 4;
+
+// Radiance or throughput along a path: at the path's wavelengths in spectral
+// mode, otherwise red, green and blue in the first three entries.
+#[derive(Clone, Copy)]
+pub struct SampledSpectrum {
+    pub values: [f32; WAVELENGTHS],
+}
+
+// The wavelengths a path is traced at.
+#[derive(Clone, Copy)]
+pub enum PathWavelengths {
+    Rgb,
+    // The hero wavelength first, the others evenly spaced after it.
+    Spectral([f32; WAVELENGTHS]),
+}
+
+// A spectrum given by samples, linearly interpolated in between and constant
+// beyond the first and last one.
+pub struct Spectrum {
+    wavelengths: Vec<f32>,
+    values: Vec<f32>,
+    // The color of the spectrum for RGB mode.
+    rgb: Color,
+}
+
+impl SampledSpectrum {
+    pub fn zero() -> SampledSpectrum {
+        SampledSpectrum {
+            values: [0.0; WAVELENGTHS],
+        }
+    }
+
+    pub fn one() -> SampledSpectrum {
+        SampledSpectrum {
+            values: [1.0; WAVELENGTHS],
+        }
+    }
+}
+
+impl PathWavelengths {
+    pub fn sample(spectral: bool) -> PathWavelengths {
+        if !spectral {
+            return PathWavelengths::Rgb;
+        }
+        let hero = sample_wavelength();
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / WAVELENGTHS as f32;
+        let mut lambdas = [hero; WAVELENGTHS];
+        for (i, lambda) in lambdas.iter_mut().enumerate().skip(1) {
+            *lambda += i as f32 * step;
+            if *lambda >= LAMBDA_MAX {
+                *lambda -= LAMBDA_MAX - LAMBDA_MIN;
+            }
+        }
+        PathWavelengths::Spectral(lambdas)
+    }
+
+    // Upsamples a color, see `upsample`.
+    pub fn color(&self, color: &Color) -> SampledSpectrum {
+        match self {
+            PathWavelengths::Rgb => SampledSpectrum {
+                values: [color.r, color.g, color.b, 0.0],
+            },
+            PathWavelengths::Spectral(lambdas) => SampledSpectrum {
+                values: lambdas.map(|lambda| upsample(color, lambda)),
+            },
+        }
+    }
+
+    pub fn spectrum(&self, spectrum: &Spectrum) -> SampledSpectrum {
+        match self {
+            PathWavelengths::Rgb => self.color(&spectrum.rgb),
+            PathWavelengths::Spectral(lambdas) => SampledSpectrum {
+                values: lambdas.map(|lambda| spectrum.value(lambda)),
+            },
+        }
+    }
+
+    // Restricts the path to a single wavelength, for materials that send each
+    // wavelength a different way. The others are dropped and the remaining
+    // one stands in for them.
+    pub fn single(&self, throughput: &mut SampledSpectrum) -> f32 {
+        match self {
+            PathWavelengths::Rgb => {
+                let lambda = sample_wavelength();
+                *throughput *= self.color(&wavelength_to_rgb(lambda));
+                lambda
+            }
+            PathWavelengths::Spectral(lambdas) => {
+                let hero = throughput.values[0] * WAVELENGTHS as f32;
+                *throughput = SampledSpectrum::zero();
+                throughput.values[0] = hero;
+                lambdas[0]
+            }
+        }
+    }
+
+    pub fn rgb(&self, radiance: &SampledSpectrum) -> Color {
+        match self {
+            PathWavelengths::Rgb => Color {
+                r: radiance.values[0],
+                g: radiance.values[1],
+                b: radiance.values[2],
+            },
+            PathWavelengths::Spectral(lambdas) => {
+                let mut color = Color::black();
+                for (lambda, value) in lambdas.iter().zip(radiance.values) {
+                    color += wavelength_to_rgb(*lambda) * value;
+                }
+                color / WAVELENGTHS as f32
+            }
+        }
+    }
+}
+
+// Upsamples a color to a spectrum made of three smooth bands, blue below
+// 488nm, green up to 588nm and red above. Projected back to RGB these give the
+// original color within a few percent, and white exactly.
+fn upsample(color: &Color, lambda: f32) -> f32 {
+    let step = |edge: f32| 1.0 / (1.0 + (-(lambda - edge) / 8.0).exp());
+    let (above_blue, above_green) = (step(488.0), step(588.0));
+    color.b * (1.0 - above_blue) + color.g * (above_blue - above_green) + color.r * above_green
+}
+
+impl Spectrum {
+    // Wavelengths have to be increasing.
+    pub fn new(wavelengths: Vec<f32>, values: Vec<f32>) -> Result<Spectrum, String> {
+        if wavelengths.is_empty() || wavelengths.len() != values.len() {
+            return Err("a spectrum needs as many values as wavelengths".to_string());
+        }
+        if wavelengths.windows(2).any(|w| w[0] >= w[1]) {
+            return Err("the wavelengths of a spectrum must be increasing".to_string());
+        }
+        let mut spectrum = Spectrum {
+            wavelengths,
+            values,
+            rgb: Color::black(),
+        };
+        spectrum.rgb = spectrum.integrate_rgb();
+        Ok(spectrum)
+    }
+
+    // Emission of a black body at `temperature` Kelvin, scaled to a luminance
+    // of one. Black if it is too cold to emit visible light.
+    pub fn blackbody(temperature: f32) -> Spectrum {
+        let mut spectrum = Spectrum::planck(temperature);
+        let luminance = spectrum.rgb.luminance();
+        if !luminance.is_normal() {
+            return spectrum;
+        }
+        for value in spectrum.values.iter_mut() {
+            *value /= luminance;
+        }
+        spectrum.rgb = spectrum.rgb / luminance;
+        spectrum
+    }
+
+    // Planck's law in arbitrary but fixed units, so that black bodies of
+    // different temperatures can be compared.
+    pub fn planck(temperature: f32) -> Spectrum {
+        // Lambda in meters; constant factors are left out.
+        let c2 = 1.438_777e-2;
+        let wavelengths: Vec<f32> = (0..=80).map(|i| LAMBDA_MIN + 5.0 * i as f32).collect();
+        let values = wavelengths
+            .iter()
+            .map(|&lambda| {
+                let meters = lambda as f64 * 1e-9;
+                (1.0 / (meters.powi(5) * ((c2 / (meters * temperature as f64)).exp() - 1.0))) as f32
+            })
+            .collect();
+        Spectrum::new(wavelengths, values).unwrap()
+    }
+
+    // The color of the spectrum in RGB mode.
+    pub fn rgb(&self) -> Color {
+        self.rgb
+    }
+
+    pub fn value(&self, lambda: f32) -> f32 {
+        let i = self.wavelengths.partition_point(|&w| w < lambda);
+        if i == 0 {
+            return self.values[0];
+        }
+        if i == self.wavelengths.len() {
+            return self.values[i - 1];
+        }
+        let (w0, w1) = (self.wavelengths[i - 1], self.wavelengths[i]);
+        let t = (lambda - w0) / (w1 - w0);
+        self.values[i - 1] * (1.0 - t) + self.values[i] * t
+    }
+
+    fn integrate_rgb(&self) -> Color {
+        let steps = 400;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f32;
+        let mut sum = Color::black();
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + (i as f32 + 0.5) * step;
+            sum += wavelength_to_rgb(lambda) * self.value(lambda);
+        }
+        // Narrow spectra can lie outside the sRGB gamut.
+        (sum / steps as f32).map(|c| c.max(0.0))
+    }
+}
+
+impl ops::Add<SampledSpectrum> for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn add(self, _rhs: SampledSpectrum) -> SampledSpectrum {
+        let mut values = self.values;
+        for (v, r) in values.iter_mut().zip(_rhs.values) {
+            *v += r;
+        }
+        SampledSpectrum { values }
+    }
+}
+
+impl ops::AddAssign<SampledSpectrum> for SampledSpectrum {
+    fn add_assign(&mut self, _rhs: SampledSpectrum) {
+        *self = *self + _rhs;
+    }
+}
+
+impl ops::Mul<SampledSpectrum> for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(self, _rhs: SampledSpectrum) -> SampledSpectrum {
+        let mut values = self.values;
+        for (v, r) in values.iter_mut().zip(_rhs.values) {
+            *v *= r;
+        }
+        SampledSpectrum { values }
+    }
+}
+
+impl ops::MulAssign<SampledSpectrum> for SampledSpectrum {
+    fn mul_assign(&mut self, _rhs: SampledSpectrum) {
+        *self = *self * _rhs;
+    }
+}
+
+impl ops::Mul<f32> for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(self, _rhs: f32) -> SampledSpectrum {
+        SampledSpectrum {
+            values: self.values.map(|v| v * _rhs),
+        }
+    }
+}
+
+impl ops::MulAssign<f32> for SampledSpectrum {
+    fn mul_assign(&mut self, _rhs: f32) {
+        *self = *self * _rhs;
+    }
+}
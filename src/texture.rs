@@ -0,0 +1,223 @@
+// Textures provide spatially varying material parameters.
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+use crate::image::Image;
+use crate::image::Wrap;
+use crate::math::Color;
+use crate::math::Vec3;
+
+// Evaluated at a surface point with its (u, v) coordinates and normal, all in
+// world space. Scalar parameters like roughness use the luminance.
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f32, v: f32, point: &Vec3, normal: &Vec3) -> Color;
+}
+
+pub struct SolidColor {
+    pub color: Color,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckerSpace {
+    // Squares on the surface parameterization.
+    Uv,
+    // Cubes filling world space, so the pattern doesn't depend on the
+    // parameterization.
+    World,
+}
+
+// Alternates between two textures, `scale` squares per unit.
+pub struct Checker {
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
+    pub scale: f32,
+    pub space: CheckerSpace,
+}
+
+// An image mapped onto the (u, v) coordinates, with (0, 0) at the bottom left
+// corner. The image should hold linear values.
+pub struct ImageTexture {
+    pub image: Image,
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseKind {
+    Perlin,
+    // Several octaves of noise.
+    Turbulence,
+    // Veins from a sine wave along z perturbed by turbulence.
+    Marble,
+}
+
+// Solid noise scaling `color`, with features about 1/scale units apart.
+pub struct NoiseTexture {
+    pub noise: Perlin,
+    pub kind: NoiseKind,
+    pub color: Color,
+    pub scale: f32,
+    pub octaves: u32,
+}
+
+// Ken Perlin's gradient noise.
+// See: https://raytracing.github.io/books/RayTracingTheNextWeek.html#perlinnoise
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    permutation_x: Vec<usize>,
+    permutation_y: Vec<usize>,
+    permutation_z: Vec<usize>,
+}
+
+const POINT_COUNT: usize = 256;
+
+pub fn solid(color: Color) -> Arc<dyn Texture> {
+    Arc::new(SolidColor { color })
+}
+
+// A constant scalar parameter.
+pub fn constant(value: f32) -> Arc<dyn Texture> {
+    solid(Color {
+        r: value,
+        g: value,
+        b: value,
+    })
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f32, _v: f32, _point: &Vec3, _normal: &Vec3) -> Color {
+        self.color
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f32, v: f32, point: &Vec3, normal: &Vec3) -> Color {
+        let cells = match self.space {
+            CheckerSpace::Uv => (u * self.scale).floor() + (v * self.scale).floor(),
+            CheckerSpace::World => {
+                (point.x * self.scale).floor()
+                    + (point.y * self.scale).floor()
+                    + (point.z * self.scale).floor()
+            }
+        };
+        if (cells as i64).rem_euclid(2) == 0 {
+            self.even.value(u, v, point, normal)
+        } else {
+            self.odd.value(u, v, point, normal)
+        }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _point: &Vec3, _normal: &Vec3) -> Color {
+        self.image
+            .sample_bilinear(u, 1.0 - v, self.wrap_u, self.wrap_v)
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f32, _v: f32, point: &Vec3, _normal: &Vec3) -> Color {
+        let p = *point * self.scale;
+        let t = match self.kind {
+            // Noise is in [-1, 1].
+            NoiseKind::Perlin => 0.5 * (1.0 + self.noise.noise(&p)),
+            NoiseKind::Turbulence => self.noise.turbulence(&p, self.octaves),
+            NoiseKind::Marble => {
+                0.5 * (1.0 + (p.z + 10.0 * self.noise.turbulence(&p, self.octaves)).sin())
+            }
+        };
+        self.color * t
+    }
+}
+
+impl Perlin {
+    // The same seed always gives the same noise, so that renders are
+    // reproducible.
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| {
+                Vec3 {
+                    x: rng.gen_range(-1.0..1.0),
+                    y: rng.gen_range(-1.0..1.0),
+                    z: rng.gen_range(-1.0..1.0),
+                }
+                .normalized()
+            })
+            .collect();
+        let mut permutation = || {
+            let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+            for i in (1..POINT_COUNT).rev() {
+                p.swap(i, rng.gen_range(0..=i));
+            }
+            p
+        };
+        Perlin {
+            permutation_x: permutation(),
+            permutation_y: permutation(),
+            permutation_z: permutation(),
+            gradients,
+        }
+    }
+
+    pub fn noise(&self, p: &Vec3) -> f32 {
+        let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (u, v, w) = (p.x - fx, p.y - fy, p.z - fz);
+        let (i, j, k) = (fx as i64, fy as i64, fz as i64);
+
+        // Hermite smoothing avoids grid artifacts.
+        let (uu, vv, ww) = (
+            u * u * (3.0 - 2.0 * u),
+            v * v * (3.0 - 2.0 * v),
+            w * w * (3.0 - 2.0 * w),
+        );
+
+        let mut accum = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let index = self.permutation_x[((i + di) & 255) as usize]
+                        ^ self.permutation_y[((j + dj) & 255) as usize]
+                        ^ self.permutation_z[((k + dk) & 255) as usize];
+                    let (a, b, c) = (di as f32, dj as f32, dk as f32);
+                    let weight = Vec3 {
+                        x: u - a,
+                        y: v - b,
+                        z: w - c,
+                    };
+                    accum += (a * uu + (1.0 - a) * (1.0 - uu))
+                        * (b * vv + (1.0 - b) * (1.0 - vv))
+                        * (c * ww + (1.0 - c) * (1.0 - ww))
+                        * Vec3::dot(&self.gradients[index], &weight);
+                }
+            }
+        }
+        accum
+    }
+
+    // Sum of octaves with doubling frequency and halving amplitude.
+    pub fn turbulence(&self, p: &Vec3, octaves: u32) -> f32 {
+        let mut accum = 0.0;
+        let mut point = *p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            accum += weight * self.noise(&point);
+            weight *= 0.5;
+            point *= 2.0;
+        }
+        accum.abs()
+    }
+}
+
+// Spherical (u, v) coordinates of a point on the unit sphere: u goes around
+// the y axis starting at -x, v from the bottom (0) to the top (1).
+pub fn sphere_uv(p: &Vec3) -> (f32, f32) {
+    let theta = (-p.y).clamp(-1.0, 1.0).acos();
+    let phi = (-p.z).atan2(p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
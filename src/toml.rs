@@ -0,0 +1,754 @@
+// A small parser for the subset of TOML used by the scene files.
+//
+// Supported are comments, [tables], [[arrays of tables]], dotted headers and
+// keys, bare and quoted keys, basic and literal strings, integers, floats,
+// booleans, arrays and inline tables. Every value remembers where in the file
+// it was defined, so errors found later on can still point at the culprit.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Item>),
+    Table(Table),
+}
+
+// A value together with the position it was defined at.
+#[derive(Debug, Clone)]
+pub struct Item {
+    pub value: Value,
+    pub position: Position,
+}
+
+// Key/value pairs in the order they appear in the file.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    entries: Vec<(String, Item)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub position: Position,
+    pub message: String,
+}
+
+struct Parser {
+    chars: Vec<char>,
+    index: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Position {
+    pub fn start() -> Position {
+        Position { line: 1, column: 1 }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+impl Error {
+    pub fn new(position: Position, message: String) -> Error {
+        Error { position, message }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+}
+
+impl Table {
+    pub fn new() -> Table {
+        Table {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Item> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Item> {
+        self.entries
+            .iter_mut()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Item)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    fn insert(&mut self, key: String, item: Item) {
+        self.entries.push((key, item));
+    }
+}
+
+pub fn parse(source: &str) -> Result<Table, Error> {
+    let mut parser = Parser {
+        chars: source.chars().collect(),
+        index: 0,
+        line: 1,
+        column: 1,
+    };
+    parser.parse_document()
+}
+
+// The table a [header] or key below it refers to: either the table itself or,
+// for arrays of tables, the most recently added element.
+fn as_table_mut(item: &mut Item) -> Option<&mut Table> {
+    match &mut item.value {
+        Value::Table(t) => Some(t),
+        Value::Array(a) => match a.last_mut() {
+            Some(Item {
+                value: Value::Table(t),
+                ..
+            }) => Some(t),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn table_at<'a>(root: &'a mut Table, path: &[String]) -> &'a mut Table {
+    let mut table = root;
+    for key in path {
+        let item = table.get_mut(key).expect("Table path must exist.");
+        table = as_table_mut(item).expect("Table path must lead through tables.");
+    }
+    table
+}
+
+impl Parser {
+    fn position(&self) -> Position {
+        Position {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, Error> {
+        Err(Error::new(self.position(), message))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.index).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.index += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.advance();
+                Ok(())
+            }
+            Some(c) => self.error(format!("expected `{}`, found {}", expected, describe(c))),
+            None => self.error(format!("expected `{}`, found end of file", expected)),
+        }
+    }
+
+    // Skips spaces and tabs on the current line.
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') = self.peek() {
+            self.advance();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while let Some(c) = self.peek() {
+                if c == '\n' {
+                    break;
+                }
+                self.advance();
+            }
+        }
+    }
+
+    // Skips whitespace, newlines and comments, as allowed inside arrays.
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_whitespace();
+            self.skip_comment();
+            match self.peek() {
+                Some('\n') | Some('\r') => {
+                    self.advance();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn expect_line_end(&mut self) -> Result<(), Error> {
+        self.skip_whitespace();
+        self.skip_comment();
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.advance();
+                Ok(())
+            }
+            Some('\r') => {
+                self.advance();
+                self.expect('\n')
+            }
+            Some(c) => self.error(format!("expected end of line, found {}", describe(c))),
+        }
+    }
+
+    fn parse_document(&mut self) -> Result<Table, Error> {
+        let mut root = Table::new();
+        let mut current: Vec<String> = Vec::new();
+        let mut defined_tables: Vec<Vec<String>> = Vec::new();
+
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                None => break,
+                Some('[') => {
+                    current = self.parse_header(&mut root, &mut defined_tables)?;
+                }
+                Some(_) => {
+                    let table = table_at(&mut root, &current);
+                    self.parse_key_value(table)?;
+                }
+            }
+            self.expect_line_end()?;
+        }
+        Ok(root)
+    }
+
+    fn parse_header(
+        &mut self,
+        root: &mut Table,
+        defined_tables: &mut Vec<Vec<String>>,
+    ) -> Result<Vec<String>, Error> {
+        let position = self.position();
+        self.expect('[')?;
+        let is_array = self.peek() == Some('[');
+        if is_array {
+            self.advance();
+        }
+        self.skip_whitespace();
+        let path = self.parse_dotted_key()?;
+        self.skip_whitespace();
+        self.expect(']')?;
+        if is_array {
+            self.expect(']')?;
+        }
+
+        // Create or walk through all parent tables.
+        let (last, parents) = path.split_last().expect("Keys are never empty.");
+        let mut table = root;
+        for key in parents {
+            if !table.contains_key(key) {
+                table.insert(
+                    key.clone(),
+                    Item {
+                        value: Value::Table(Table::new()),
+                        position,
+                    },
+                );
+            }
+            let item = table.get_mut(key).unwrap();
+            let type_name = item.value.type_name();
+            table = match as_table_mut(item) {
+                Some(t) => t,
+                None => {
+                    return Err(Error::new(
+                        position,
+                        format!("`{}` is a {}, not a table", key, type_name),
+                    ))
+                }
+            };
+        }
+
+        let new_table = Item {
+            value: Value::Table(Table::new()),
+            position,
+        };
+        if is_array {
+            match table.get_mut(last) {
+                None => table.insert(
+                    last.clone(),
+                    Item {
+                        value: Value::Array(vec![new_table]),
+                        position,
+                    },
+                ),
+                Some(Item {
+                    value: Value::Array(a),
+                    ..
+                }) if a.iter().all(|i| matches!(i.value, Value::Table(_))) => a.push(new_table),
+                Some(_) => {
+                    return Err(Error::new(
+                        position,
+                        format!("`{}` is already defined and not an array of tables", last),
+                    ))
+                }
+            }
+        } else {
+            if defined_tables.contains(&path) {
+                return Err(Error::new(
+                    position,
+                    format!("table `{}` is defined twice", path.join(".")),
+                ));
+            }
+            match table.get(last) {
+                None => table.insert(last.clone(), new_table),
+                Some(Item {
+                    value: Value::Table(_),
+                    ..
+                }) => {}
+                Some(_) => {
+                    return Err(Error::new(
+                        position,
+                        format!("`{}` is already defined and not a table", last),
+                    ))
+                }
+            }
+            defined_tables.push(path.clone());
+        }
+        Ok(path)
+    }
+
+    fn parse_key_value(&mut self, table: &mut Table) -> Result<(), Error> {
+        let position = self.position();
+        let path = self.parse_dotted_key()?;
+        self.skip_whitespace();
+        self.expect('=')?;
+        self.skip_whitespace();
+        let item = self.parse_value()?;
+
+        let (last, parents) = path.split_last().expect("Keys are never empty.");
+        let mut table = table;
+        for key in parents {
+            if !table.contains_key(key) {
+                table.insert(
+                    key.clone(),
+                    Item {
+                        value: Value::Table(Table::new()),
+                        position,
+                    },
+                );
+            }
+            table = match &mut table.get_mut(key).unwrap().value {
+                Value::Table(t) => t,
+                other => {
+                    return Err(Error::new(
+                        position,
+                        format!("`{}` is a {}, not a table", key, other.type_name()),
+                    ))
+                }
+            };
+        }
+        if table.contains_key(last) {
+            return Err(Error::new(position, format!("duplicate key `{}`", last)));
+        }
+        table.insert(last.clone(), item);
+        Ok(())
+    }
+
+    fn parse_dotted_key(&mut self) -> Result<Vec<String>, Error> {
+        let mut keys = vec![self.parse_key()?];
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('.') {
+                break;
+            }
+            self.advance();
+            self.skip_whitespace();
+            keys.push(self.parse_key()?);
+        }
+        Ok(keys)
+    }
+
+    fn parse_key(&mut self) -> Result<String, Error> {
+        match self.peek() {
+            Some('"') => self.parse_basic_string(),
+            Some('\'') => self.parse_literal_string(),
+            Some(c) if is_bare_key_char(c) => {
+                let mut key = String::new();
+                while let Some(c) = self.peek() {
+                    if !is_bare_key_char(c) {
+                        break;
+                    }
+                    key.push(c);
+                    self.advance();
+                }
+                Ok(key)
+            }
+            Some(c) => self.error(format!("expected a key, found {}", describe(c))),
+            None => self.error("expected a key, found end of file".to_string()),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Item, Error> {
+        let position = self.position();
+        let value = match self.peek() {
+            Some('"') => Value::String(self.parse_basic_string()?),
+            Some('\'') => Value::String(self.parse_literal_string()?),
+            Some('[') => Value::Array(self.parse_array()?),
+            Some('{') => Value::Table(self.parse_inline_table()?),
+            Some('t') | Some('f') => Value::Boolean(self.parse_boolean()?),
+            Some(c) if c.is_ascii_digit() || c == '+' || c == '-' || c == '.' => {
+                self.parse_number()?
+            }
+            Some(c) => return self.error(format!("expected a value, found {}", describe(c))),
+            None => return self.error("expected a value, found end of file".to_string()),
+        };
+        Ok(Item { value, position })
+    }
+
+    fn parse_basic_string(&mut self) -> Result<String, Error> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            let position = self.position();
+            match self.advance() {
+                None | Some('\n') => {
+                    return Err(Error::new(position, "unterminated string".to_string()))
+                }
+                Some('"') => return Ok(s),
+                Some('\\') => match self.advance() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('u') => {
+                        let mut code = 0;
+                        for _ in 0..4 {
+                            let digit = self.advance().and_then(|c| c.to_digit(16));
+                            match digit {
+                                Some(d) => code = code * 16 + d,
+                                None => {
+                                    return Err(Error::new(
+                                        position,
+                                        "invalid unicode escape".to_string(),
+                                    ))
+                                }
+                            }
+                        }
+                        match std::char::from_u32(code) {
+                            Some(c) => s.push(c),
+                            None => {
+                                return Err(Error::new(
+                                    position,
+                                    "invalid unicode escape".to_string(),
+                                ))
+                            }
+                        }
+                    }
+                    _ => return Err(Error::new(position, "invalid escape sequence".to_string())),
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn parse_literal_string(&mut self) -> Result<String, Error> {
+        self.expect('\'')?;
+        let mut s = String::new();
+        loop {
+            let position = self.position();
+            match self.advance() {
+                None | Some('\n') => {
+                    return Err(Error::new(position, "unterminated string".to_string()))
+                }
+                Some('\'') => return Ok(s),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn parse_boolean(&mut self) -> Result<bool, Error> {
+        let position = self.position();
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_alphabetic() {
+                break;
+            }
+            word.push(c);
+            self.advance();
+        }
+        match word.as_str() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(Error::new(position, format!("unknown value `{}`", word))),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Value, Error> {
+        let position = self.position();
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.' || c == '_') {
+                break;
+            }
+            if c != '_' {
+                text.push(c);
+            }
+            self.advance();
+        }
+
+        let is_float = text.contains('.') || text.contains('e') || text.contains('E');
+        let value = if is_float {
+            text.parse::<f64>().ok().map(Value::Float)
+        } else {
+            text.parse::<i64>().ok().map(Value::Integer)
+        };
+        value.ok_or_else(|| Error::new(position, format!("invalid number `{}`", text)))
+    }
+
+    fn parse_array(&mut self) -> Result<Vec<Item>, Error> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(']') {
+                self.advance();
+                return Ok(items);
+            }
+            items.push(self.parse_value()?);
+            self.skip_blank();
+            match self.peek() {
+                Some(',') => {
+                    self.advance();
+                }
+                Some(']') => {
+                    self.advance();
+                    return Ok(items);
+                }
+                Some(c) => {
+                    return self.error(format!("expected `,` or `]`, found {}", describe(c)))
+                }
+                None => return self.error("unterminated array".to_string()),
+            }
+        }
+    }
+
+    fn parse_inline_table(&mut self) -> Result<Table, Error> {
+        self.expect('{')?;
+        let mut table = Table::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.advance();
+            return Ok(table);
+        }
+        loop {
+            self.skip_whitespace();
+            self.parse_key_value(&mut table)?;
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => {
+                    self.advance();
+                }
+                Some('}') => {
+                    self.advance();
+                    return Ok(table);
+                }
+                Some(c) => {
+                    return self.error(format!("expected `,` or `}}`, found {}", describe(c)))
+                }
+                None => return self.error("unterminated inline table".to_string()),
+            }
+        }
+    }
+}
+
+fn is_bare_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+fn describe(c: char) -> String {
+    match c {
+        '\n' | '\r' => "end of line".to_string(),
+        _ => format!("`{}`", c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table<'a>(table: &'a Table, key: &str) -> &'a Table {
+        match &table.get(key).unwrap().value {
+            Value::Table(t) => t,
+            other => panic!("`{}` is a {}", key, other.type_name()),
+        }
+    }
+
+    fn array<'a>(table: &'a Table, key: &str) -> &'a [Item] {
+        match &table.get(key).unwrap().value {
+            Value::Array(a) => a,
+            other => panic!("`{}` is a {}", key, other.type_name()),
+        }
+    }
+
+    fn integer(table: &Table, key: &str) -> i64 {
+        match table.get(key).unwrap().value {
+            Value::Integer(i) => i,
+            ref other => panic!("`{}` is a {}", key, other.type_name()),
+        }
+    }
+
+    fn error_at(source: &str) -> (usize, usize) {
+        let error = parse(source).unwrap_err();
+        (error.position.line, error.position.column)
+    }
+
+    #[test]
+    fn values() {
+        let root = parse(
+            "s = \"a\\tb\\u00e9\" # comment\n\
+             l = 'C:\\path'\n\
+             i = -1_000\n\
+             f = 2.5e-1\n\
+             b = true\n\
+             a = [1, 2,\n  3, # more\n]\n\
+             t = { x = 1, y.z = 2 }\n",
+        )
+        .unwrap();
+        assert!(matches!(&root.get("s").unwrap().value, Value::String(s) if s == "a\tbé"));
+        assert!(matches!(&root.get("l").unwrap().value, Value::String(s) if s == "C:\\path"));
+        assert_eq!(integer(&root, "i"), -1000);
+        assert!(matches!(root.get("f").unwrap().value, Value::Float(f) if f == 0.25));
+        assert!(matches!(root.get("b").unwrap().value, Value::Boolean(true)));
+        assert_eq!(array(&root, "a").len(), 3);
+        let t = table(&root, "t");
+        assert_eq!(integer(t, "x"), 1);
+        assert_eq!(integer(table(t, "y"), "z"), 2);
+    }
+
+    #[test]
+    fn nested_tables() {
+        let root = parse(
+            "[camera]\n\
+             fov = 40\n\
+             [camera.lens]\n\
+             aperture = 2\n\
+             [render.output]\n\
+             width = 640\n\
+             [render]\n\
+             samples = 16\n",
+        )
+        .unwrap();
+        let camera = table(&root, "camera");
+        assert_eq!(integer(camera, "fov"), 40);
+        assert_eq!(integer(table(camera, "lens"), "aperture"), 2);
+        let render = table(&root, "render");
+        assert_eq!(integer(render, "samples"), 16);
+        assert_eq!(integer(table(render, "output"), "width"), 640);
+        // Keys keep the order of the file.
+        let keys: Vec<&String> = root.iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, ["camera", "render"]);
+    }
+
+    #[test]
+    fn arrays_of_tables() {
+        let root = parse(
+            "[[objects]]\n\
+             id = 1\n\
+             [objects.material]\n\
+             kind = 'metal'\n\
+             [[objects]]\n\
+             id = 2\n\
+             [[objects.children]]\n\
+             id = 3\n",
+        )
+        .unwrap();
+        let objects = array(&root, "objects");
+        assert_eq!(objects.len(), 2);
+        let first = match &objects[0].value {
+            Value::Table(t) => t,
+            _ => panic!("not a table"),
+        };
+        assert_eq!(integer(first, "id"), 1);
+        assert!(table(first, "material").contains_key("kind"));
+        let second = match &objects[1].value {
+            Value::Table(t) => t,
+            _ => panic!("not a table"),
+        };
+        assert_eq!(integer(second, "id"), 2);
+        assert!(!second.contains_key("material"));
+        assert_eq!(array(second, "children").len(), 1);
+        assert_eq!(objects[1].position, Position { line: 5, column: 1 });
+    }
+
+    #[test]
+    fn positions() {
+        let root = parse("\n[a]\n  x =   [1,\n 2]\n").unwrap();
+        let a = table(&root, "a");
+        assert_eq!(
+            root.get("a").unwrap().position,
+            Position { line: 2, column: 1 }
+        );
+        let x = array(a, "x");
+        assert_eq!(
+            a.get("x").unwrap().position,
+            Position { line: 3, column: 9 }
+        );
+        assert_eq!(x[1].position, Position { line: 4, column: 2 });
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error_at("a = 1\nb = \n"), (2, 5));
+        assert_eq!(error_at("a = 1\na = 2\n"), (2, 1));
+        assert_eq!(error_at("a = \"open\n"), (1, 10));
+        assert_eq!(error_at("a = [1 2]\n"), (1, 8));
+        assert_eq!(error_at("[t]\n[t]\n"), (2, 1));
+        assert_eq!(error_at("a = 1\n[a.b]\n"), (2, 1));
+        assert_eq!(error_at("x = 1 y = 2\n"), (1, 7));
+        assert_eq!(error_at("n = 1.2.3\n"), (1, 5));
+        assert_eq!(error_at("b = maybe\n"), (1, 5));
+        assert_eq!(error_at("[[o]]\n[o]\n"), (2, 1));
+    }
+}
@@ -0,0 +1,215 @@
+// Tone mapping operators, compressing the unbounded radiance of a render into
+// the [0, 1] range of display formats. All operators take and return linear
+// values; encoding to sRGB or gamma happens afterwards.
+
+use crate::math::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapping {
+    // Values above 1 are simply clipped.
+    Clamp,
+    Reinhard,
+    // Reinhard with a luminance that maps to white instead of approaching it.
+    ExtendedReinhard { white_point: f32 },
+    Aces,
+    Hable,
+    Agx,
+}
+
+pub const DEFAULT_WHITE_POINT: f32 = 4.0;
+
+impl ToneMapping {
+    pub fn from_name(name: &str) -> Option<ToneMapping> {
+        match name {
+            "none" | "clamp" => Some(ToneMapping::Clamp),
+            "reinhard" => Some(ToneMapping::Reinhard),
+            "reinhard_extended" => Some(ToneMapping::ExtendedReinhard {
+                white_point: DEFAULT_WHITE_POINT,
+            }),
+            "aces" => Some(ToneMapping::Aces),
+            "hable" | "uncharted2" => Some(ToneMapping::Hable),
+            "agx" => Some(ToneMapping::Agx),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, color: Color) -> Color {
+        match self {
+            ToneMapping::Clamp => color,
+            ToneMapping::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapping::ExtendedReinhard { white_point } => scale_luminance(color, |l| {
+                l * (1.0 + l / (white_point * white_point)) / (1.0 + l)
+            }),
+            ToneMapping::Aces => aces(color),
+            ToneMapping::Hable => hable(color),
+            ToneMapping::Agx => agx(color),
+        }
+    }
+}
+
+// Maps the luminance and scales the color to match, which keeps hues intact.
+fn scale_luminance<F: Fn(f32) -> f32>(color: Color, f: F) -> Color {
+    let l = color.luminance();
+    if l <= 0.0 {
+        return Color::black();
+    }
+    color * (f(l) / l)
+}
+
+fn transform(m: &[[f32; 3]; 3], c: Color) -> Color {
+    Color {
+        r: m[0][0] * c.r + m[0][1] * c.g + m[0][2] * c.b,
+        g: m[1][0] * c.r + m[1][1] * c.g + m[1][2] * c.b,
+        b: m[2][0] * c.r + m[2][1] * c.g + m[2][2] * c.b,
+    }
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms.
+fn aces(color: Color) -> Color {
+    const INPUT: [[f32; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[f32; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let c = transform(&INPUT, color).map(|v| {
+        let a = v * (v + 0.0245786) - 9.0537e-05;
+        let b = v * (0.983729 * v + 0.432951) + 0.238081;
+        a / b
+    });
+    transform(&OUTPUT, c).map(|v| v.clamp(0.0, 1.0))
+}
+
+// John Hable's filmic curve from Uncharted 2.
+fn hable(color: Color) -> Color {
+    fn curve(x: f32) -> f32 {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+    }
+    const EXPOSURE_BIAS: f32 = 2.0;
+    const WHITE_POINT: f32 = 11.2;
+    let white_scale = 1.0 / curve(WHITE_POINT);
+    color.map(|v| curve(EXPOSURE_BIAS * v.max(0.0)) * white_scale)
+}
+
+// An approximation of Troy Sobotka's AgX: an inset into a wider gamut, a log2
+// encoding and a sigmoid fitted with a polynomial, as popularized by three.js
+// and Blender.
+fn agx(color: Color) -> Color {
+    const INSET: [[f32; 3]; 3] = [
+        [0.8424791, 0.0784336, 0.07922375],
+        [0.04232824, 0.8784686, 0.07916613],
+        [0.04237565, 0.0784336, 0.879143],
+    ];
+    const OUTSET: [[f32; 3]; 3] = [
+        [1.196879, -0.09802088, -0.09902974],
+        [-0.05289685, 1.151903, -0.09896118],
+        [-0.05297164, -0.09804345, 1.151074],
+    ];
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let c = transform(&INSET, color).map(|v| {
+        let x = (v.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+    // The curve produces display values for a 2.2 power function, convert
+    // back to linear so that the usual encoding applies.
+    transform(&OUTSET, c).map(|v| v.max(0.0).powf(2.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapping; 6] = [
+        ToneMapping::Clamp,
+        ToneMapping::Reinhard,
+        ToneMapping::ExtendedReinhard {
+            white_point: DEFAULT_WHITE_POINT,
+        },
+        ToneMapping::Aces,
+        ToneMapping::Hable,
+        ToneMapping::Agx,
+    ];
+
+    fn gray(v: f32) -> Color {
+        Color { r: v, g: v, b: v }
+    }
+
+    #[test]
+    fn names() {
+        let names = [
+            "none",
+            "reinhard",
+            "reinhard_extended",
+            "aces",
+            "hable",
+            "agx",
+        ];
+        for (name, operator) in names.iter().zip(OPERATORS.iter()) {
+            assert_eq!(ToneMapping::from_name(name), Some(*operator));
+        }
+        assert_eq!(ToneMapping::from_name("filmic"), None);
+    }
+
+    #[test]
+    fn black_stays_black() {
+        for operator in OPERATORS.iter() {
+            let c = operator.apply(Color::black());
+            assert!(c.r.abs() < 1e-4 && c.g.abs() < 1e-4 && c.b.abs() < 1e-4);
+        }
+    }
+
+    // Brighter input never gets darker.
+    #[test]
+    fn monotonic() {
+        for operator in OPERATORS.iter() {
+            let mut previous = -1.0;
+            for i in 0..2000 {
+                let v = operator.apply(gray(i as f32 * 0.05)).g;
+                assert!(v >= previous, "{:?} decreases at {}", operator, i);
+                previous = v;
+            }
+        }
+    }
+
+    // Operators without a white point stay displayable however bright the
+    // input is.
+    #[test]
+    fn bounded() {
+        for operator in [ToneMapping::Reinhard, ToneMapping::Aces, ToneMapping::Agx].iter() {
+            for i in 0..30 {
+                let v = operator.apply(gray(2.0f32.powi(i))).g;
+                assert!(v <= 1.0 + 1e-4, "{:?} exceeds 1 at 2^{}", operator, i);
+            }
+        }
+    }
+
+    #[test]
+    fn white_points() {
+        let reinhard = ToneMapping::ExtendedReinhard { white_point: 3.0 };
+        assert!((reinhard.apply(gray(3.0)).g - 1.0).abs() < 1e-6);
+        assert!((ToneMapping::Reinhard.apply(gray(1.0)).g - 0.5).abs() < 1e-6);
+        // Hable's curve reaches white at 11.2 after the exposure bias of 2.
+        assert!((ToneMapping::Hable.apply(gray(5.6)).g - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn reinhard_keeps_hues() {
+        let color = Color {
+            r: 4.0,
+            g: 2.0,
+            b: 1.0,
+        };
+        let c = ToneMapping::Reinhard.apply(color);
+        assert!((c.r / c.g - 2.0).abs() < 1e-5 && (c.g / c.b - 2.0).abs() < 1e-5);
+    }
+}
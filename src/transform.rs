@@ -0,0 +1,127 @@
+// Instancing: any object placed in the scene with an affine transform. The
+// object itself is stored in its own (object) space, so a shared Arc<Bvh<_>>
+// can be instanced many times at the cost of two matrices per instance.
+
+use crate::aabb::Aabb;
+use crate::intersection::HitRecord;
+use crate::intersection::Intersectable;
+use crate::math::Mat4;
+use crate::math::Ray;
+use crate::math::Vec3;
+
+pub struct Transformed<I: Intersectable> {
+    object: I,
+    to_world: Mat4,
+    to_object: Mat4,
+    // Transforms normals to world space: the inverse transpose of `to_world`.
+    normal_to_world: Mat4,
+    // World space bounds, computed once since instances are usually
+    // intersected through a Bvh.
+    bounds: Aabb,
+}
+
+impl<I: Intersectable> Transformed<I> {
+    pub fn new(object: I, to_world: Mat4) -> Result<Transformed<I>, String> {
+        let to_object = to_world
+            .inverse()
+            .ok_or_else(|| "transform is not invertible".to_string())?;
+
+        let object_bounds = object.bounding_box();
+        let mut bounds = Aabb::empty();
+        if !object_bounds.is_empty() {
+            for i in 0..8 {
+                let corner = Vec3 {
+                    x: if i & 1 == 0 {
+                        object_bounds.min.x
+                    } else {
+                        object_bounds.max.x
+                    },
+                    y: if i & 2 == 0 {
+                        object_bounds.min.y
+                    } else {
+                        object_bounds.max.y
+                    },
+                    z: if i & 4 == 0 {
+                        object_bounds.min.z
+                    } else {
+                        object_bounds.max.z
+                    },
+                };
+                bounds.grow(&to_world.transform_point(&corner));
+            }
+        }
+
+        Ok(Transformed {
+            object,
+            to_world,
+            normal_to_world: to_object.transpose(),
+            to_object,
+            bounds,
+        })
+    }
+
+    // The direction isn't renormalized, so t is the same in both spaces.
+    fn object_ray(&self, ray: &Ray) -> Ray {
+        Ray {
+            origin: self.to_object.transform_point(&ray.origin),
+            direction: self.to_object.transform_vector(&ray.direction),
+            time: ray.time,
+            wavelength: ray.wavelength,
+        }
+    }
+
+    fn hit_to_world(&self, hit: &mut HitRecord) {
+        // Lights inside transformed objects aren't registered.
+        hit.light = None;
+        // Normals transform with the inverse transpose. The face orientation
+        // carries over since dot products with directions are preserved.
+        hit.point = self.to_world.transform_point(&hit.point);
+        hit.normal = self
+            .normal_to_world
+            .transform_vector(&hit.normal)
+            .normalized();
+    }
+}
+
+impl<I: Intersectable> Intersectable for Transformed<I> {
+    fn intersect<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord<'a>) -> bool {
+        let found = self
+            .object
+            .intersect(&self.object_ray(ray), t_min, t_max, hit);
+        if found {
+            self.hit_to_world(hit);
+        }
+        found
+    }
+
+    fn intersect_surface<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> bool {
+        let found = self
+            .object
+            .intersect_surface(&self.object_ray(ray), t_min, t_max, hit);
+        if found {
+            self.hit_to_world(hit);
+        }
+        found
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+
+    fn transmittance<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> f32 {
+        self.object
+            .transmittance(&self.object_ray(ray), t_min, t_max, hit)
+    }
+}
@@ -0,0 +1,413 @@
+// Participating media. A medium fills the inside of a closed boundary object
+// with particles that scatter light. Rays inside it travel a random distance
+// before they hit a particle, which then acts like a surface with a phase
+// function as its material. Shadow rays pass through media instead, scaled
+// by their transmittance.
+//
+// Media of varying density are sampled by delta tracking and attenuate
+// shadow rays by ratio tracking, see Novák et al., "Monte Carlo Methods for
+// Volumetric Light Transport Simulation" (Eurographics 2018).
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::aabb::Aabb;
+use crate::grid::Grid;
+use crate::intersection::HitRecord;
+use crate::intersection::Intersectable;
+use crate::material::BsdfSample;
+use crate::material::Lobe;
+use crate::material::Material;
+use crate::math::Color;
+use crate::math::Ray;
+use crate::math::Vec3;
+use crate::spectrum::Spectrum;
+
+// Distribution of scattered directions relative to the direction of travel.
+#[derive(Clone, Copy)]
+pub enum PhaseFunction {
+    Isotropic,
+    // Henyey-Greenstein with asymmetry `g` in (-1, 1): positive values
+    // scatter forward, negative ones back.
+    HenyeyGreenstein(f32),
+}
+
+// The "material" of a scattering event. `albedo` is the fraction of the
+// light that is scattered rather than absorbed, and `emission` the light
+// given off in place of the absorbed part.
+#[derive(Clone)]
+pub struct Scattering {
+    pub albedo: Color,
+    pub phase: PhaseFunction,
+    pub emission: Color,
+}
+
+// A medium of constant density inside `boundary`, which may be any closed
+// object. Rays starting inside it are handled, as are boundaries the ray
+// enters several times.
+pub struct ConstantMedium<I: Intersectable> {
+    boundary: I,
+    // Extinction coefficient, the chance of hitting a particle per unit
+    // distance.
+    density: f32,
+    scattering: Arc<dyn Material>,
+}
+
+// A medium whose density follows a voxel grid, which it fills. The highest
+// density bounds the distance between tentative collisions.
+pub struct GridMedium {
+    grid: Grid,
+    // Scales the values of the grid to extinction coefficients.
+    density: f32,
+    majorant: f32,
+    albedo: Color,
+    phase: PhaseFunction,
+    scattering: Arc<dyn Material>,
+    emission: Option<BlackbodyEmission>,
+}
+
+// Fire: a medium glowing like a black body at the temperature of a grid.
+pub struct BlackbodyEmission {
+    temperature: Grid,
+    // Colors at evenly spaced grid values from zero up to the hottest voxel.
+    colors: Vec<Color>,
+    steps_per_unit: f32,
+}
+
+impl PhaseFunction {
+    // Density of scattering into a direction at `cos_theta` to the direction
+    // of travel, per solid angle.
+    pub fn eval(&self, cos_theta: f32) -> f32 {
+        match *self {
+            PhaseFunction::Isotropic => 1.0 / (4.0 * PI),
+            PhaseFunction::HenyeyGreenstein(g) => {
+                let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+                (1.0 - g * g) / (4.0 * PI * denominator * denominator.max(0.0).sqrt())
+            }
+        }
+    }
+
+    // A direction distributed according to `eval` around the unit vector
+    // `forward`.
+    pub fn sample(&self, forward: &Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let u = rng.gen_range(0.0f32..1.0);
+        let cos_theta = match *self {
+            PhaseFunction::HenyeyGreenstein(g) if g.abs() > 1e-3 => {
+                let t = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+                ((1.0 + g * g - t * t) / (2.0 * g)).clamp(-1.0, 1.0)
+            }
+            _ => 1.0 - 2.0 * u,
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen_range(0.0..1.0);
+        let (t, b) = forward.orthonormal_basis();
+        t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + *forward * cos_theta
+    }
+}
+
+impl Scattering {
+    fn cos_theta(ray_in: &Ray, direction: &Vec3) -> f32 {
+        Vec3::dot(&ray_in.direction.normalized(), &direction.normalized())
+    }
+}
+
+impl Material for Scattering {
+    fn sample(&self, ray_in: &Ray, _hit: &HitRecord) -> Option<BsdfSample> {
+        let direction = self.phase.sample(&ray_in.direction.normalized());
+        let pdf = self.phase.eval(Scattering::cos_theta(ray_in, &direction));
+        Some(BsdfSample {
+            direction,
+            value: self.albedo * pdf,
+            pdf,
+            lobe: Lobe::VOLUME,
+        })
+    }
+
+    fn eval(&self, ray_in: &Ray, _hit: &HitRecord, direction: &Vec3) -> Color {
+        self.albedo * self.phase.eval(Scattering::cos_theta(ray_in, direction))
+    }
+
+    fn pdf(&self, ray_in: &Ray, _hit: &HitRecord, direction: &Vec3) -> f32 {
+        self.phase.eval(Scattering::cos_theta(ray_in, direction))
+    }
+
+    fn emitted(&self, _ray_in: &Ray, _hit: &HitRecord) -> Color {
+        self.emission
+    }
+}
+
+// Turns `hit` into a scattering event at `t` along the ray.
+fn scatter(ray: &Ray, t: f32, material: Arc<dyn Material>, hit: &mut HitRecord) {
+    hit.t = t;
+    hit.point = ray.at(t);
+    // Scattering doesn't depend on a normal, any unit vector will do.
+    hit.normal = ray.direction.normalized() * -1.0;
+    hit.front_face = true;
+    hit.u = 0.0;
+    hit.v = 0.0;
+    hit.material = material;
+    hit.light = None;
+}
+
+// Distance to the next collision in a medium with extinction coefficient
+// `density`, in units of t for a ray moving at `speed`.
+fn free_path(density: f32, speed: f32) -> f32 {
+    -(1.0 - rand::thread_rng().gen_range(0.0f32..1.0)).ln() / (density * speed)
+}
+
+impl<I: Intersectable> ConstantMedium<I> {
+    pub fn new(boundary: I, density: f32, albedo: Color, phase: PhaseFunction) -> Self {
+        ConstantMedium {
+            boundary,
+            density,
+            scattering: Arc::new(Scattering {
+                albedo,
+                phase,
+                emission: Color::black(),
+            }),
+        }
+    }
+
+    // Calls `visit` with the start and end of each part of the ray between
+    // t_min and t_max inside the boundary, in order, until it returns true.
+    // Returns whether it did.
+    fn walk<F: FnMut(f32, f32) -> bool>(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &HitRecord,
+        mut visit: F,
+    ) -> bool {
+        // Start with the part the ray origin may be in.
+        let mut t = f32::NEG_INFINITY;
+        let mut enter = hit.clone();
+        let mut exit = hit.clone();
+        loop {
+            if !self.boundary.intersect(ray, t, f32::INFINITY, &mut enter)
+                || !self
+                    .boundary
+                    .intersect(ray, enter.t + 0.0001, f32::INFINITY, &mut exit)
+            {
+                return false;
+            }
+            let t0 = enter.t.max(t_min);
+            let t1 = exit.t.min(t_max);
+            if t0 >= t_max {
+                return false;
+            }
+            if t0 < t1 && visit(t0, t1) {
+                return true;
+            }
+            t = exit.t + 0.0001;
+        }
+    }
+}
+
+impl<I: Intersectable> Intersectable for ConstantMedium<I> {
+    fn intersect<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord<'a>) -> bool {
+        // The exponential distribution is memoryless, so the free path
+        // carries over from one part of the ray to the next.
+        let mut distance = free_path(self.density, 1.0);
+        let mut collision = 0.0;
+        let speed = ray.direction.mag();
+        let found = self.walk(ray, t_min, t_max, hit, |t0, t1| {
+            let length = (t1 - t0) * speed;
+            if distance < length {
+                collision = t0 + distance / speed;
+                return true;
+            }
+            distance -= length;
+            false
+        });
+        if found {
+            scatter(ray, collision, self.scattering.clone(), hit);
+        }
+        found
+    }
+
+    // Media have no surface of their own.
+    fn intersect_surface<'a>(
+        &'a self,
+        _ray: &Ray,
+        _t_min: f32,
+        _t_max: f32,
+        _hit: &mut HitRecord<'a>,
+    ) -> bool {
+        false
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+
+    // Beer-Lambert over the parts of the ray inside the boundary.
+    fn transmittance<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        hit: &mut HitRecord<'a>,
+    ) -> f32 {
+        let mut length = 0.0;
+        self.walk(ray, t_min, t_max, hit, |t0, t1| {
+            length += t1 - t0;
+            false
+        });
+        (-self.density * length * ray.direction.mag()).exp()
+    }
+}
+
+impl GridMedium {
+    pub fn new(
+        grid: Grid,
+        density: f32,
+        albedo: Color,
+        phase: PhaseFunction,
+        emission: Option<BlackbodyEmission>,
+    ) -> Self {
+        GridMedium {
+            majorant: grid.max() * density,
+            grid,
+            density,
+            albedo,
+            phase,
+            scattering: Arc::new(Scattering {
+                albedo,
+                phase,
+                emission: Color::black(),
+            }),
+            emission,
+        }
+    }
+
+    // The part of the ray between t_min and t_max inside the grid.
+    fn overlap(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+        let inv_direction = Vec3 {
+            x: 1.0 / ray.direction.x,
+            y: 1.0 / ray.direction.y,
+            z: 1.0 / ray.direction.z,
+        };
+        self.grid
+            .bounds()
+            .overlap(ray, &inv_direction, t_min, t_max)
+    }
+}
+
+impl Intersectable for GridMedium {
+    // Delta tracking: tentative collisions are real ones with the chance
+    // of the density there relative to the majorant.
+    fn intersect<'a>(&'a self, ray: &Ray, t_min: f32, t_max: f32, hit: &mut HitRecord<'a>) -> bool {
+        let (mut t, t1) = match self.overlap(ray, t_min, t_max) {
+            Some(range) => range,
+            None => return false,
+        };
+        let speed = ray.direction.mag();
+        let mut rng = rand::thread_rng();
+        loop {
+            t += free_path(self.majorant, speed);
+            if t >= t1 {
+                return false;
+            }
+            let density = self.density * self.grid.value(&ray.at(t));
+            if rng.gen_range(0.0..self.majorant) < density {
+                break;
+            }
+        }
+
+        let material = match &self.emission {
+            Some(emission) => Arc::new(Scattering {
+                albedo: self.albedo,
+                phase: self.phase,
+                emission: (Color::white() - self.albedo) * emission.color(&ray.at(t)),
+            }),
+            None => self.scattering.clone(),
+        };
+        scatter(ray, t, material, hit);
+        true
+    }
+
+    // Media have no surface of their own.
+    fn intersect_surface<'a>(
+        &'a self,
+        _ray: &Ray,
+        _t_min: f32,
+        _t_max: f32,
+        _hit: &mut HitRecord<'a>,
+    ) -> bool {
+        false
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.grid.bounds()
+    }
+
+    // Ratio tracking: every tentative collision lets through the part of the
+    // light it would otherwise have been a null collision for.
+    fn transmittance<'a>(
+        &'a self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        _hit: &mut HitRecord<'a>,
+    ) -> f32 {
+        let (mut t, t1) = match self.overlap(ray, t_min, t_max) {
+            Some(range) => range,
+            None => return 1.0,
+        };
+        let speed = ray.direction.mag();
+        let mut transmittance = 1.0;
+        loop {
+            t += free_path(self.majorant, speed);
+            if t >= t1 {
+                return transmittance;
+            }
+            let density = self.density * self.grid.value(&ray.at(t));
+            transmittance *= 1.0 - density / self.majorant;
+        }
+    }
+}
+
+impl BlackbodyEmission {
+    // `temperature_scale` converts the values of the grid to Kelvin. The
+    // hottest voxel has a luminance of `intensity`, cooler ones are dimmer
+    // following Planck's law.
+    pub fn new(temperature: Grid, temperature_scale: f32, intensity: f32) -> Self {
+        const STEPS: usize = 256;
+        let hottest = temperature.max();
+        let brightest = Spectrum::planck(hottest * temperature_scale)
+            .rgb()
+            .luminance();
+        let colors = (0..=STEPS)
+            .map(|i| {
+                let kelvin = hottest * temperature_scale * i as f32 / STEPS as f32;
+                if kelvin <= 0.0 || brightest <= 0.0 {
+                    return Color::black();
+                }
+                Spectrum::planck(kelvin).rgb() * (intensity / brightest)
+            })
+            .collect();
+        BlackbodyEmission {
+            temperature,
+            colors,
+            steps_per_unit: if hottest > 0.0 {
+                STEPS as f32 / hottest
+            } else {
+                0.0
+            },
+        }
+    }
+
+    fn color(&self, p: &Vec3) -> Color {
+        let x = (self.temperature.value(p) * self.steps_per_unit).max(0.0);
+        let i = (x as usize).min(self.colors.len() - 2);
+        let t = (x - i as f32).min(1.0);
+        self.colors[i] * (1.0 - t) + self.colors[i + 1] * t
+    }
+}
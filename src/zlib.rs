@@ -0,0 +1,518 @@
+// zlib (RFC 1950) streams with deflate (RFC 1951) compression, as needed for
+// PNG files.
+//
+// The compressor uses LZ77 with hash chains and the fixed Huffman codes. That
+// is not as tight as zlib's dynamic codes, but simple and a lot smaller than
+// storing the data uncompressed. The decompressor handles all block types, so
+// it reads files from any encoder.
+
+const WINDOW_SIZE: usize = 32768;
+const HASH_BITS: usize = 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+// How many earlier positions with the same hash are tried per match.
+const MAX_CHAIN: usize = 64;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// Writes bits least significant first, as deflate expects.
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn write_bits(&mut self, value: u32, bits: u32) {
+        self.buffer |= value << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Huffman codes are defined most significant bit first.
+    fn write_code(&mut self, code: u32, bits: u32) {
+        let reversed = code.reverse_bits() >> (32 - bits);
+        self.write_bits(reversed, bits);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+fn write_literal(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let l = LENGTH_BASE
+        .iter()
+        .rposition(|&b| b as usize <= length)
+        .unwrap();
+    write_literal(writer, 257 + l as u32);
+    writer.write_bits(
+        (length - LENGTH_BASE[l] as usize) as u32,
+        LENGTH_EXTRA[l] as u32,
+    );
+
+    let d = DISTANCE_BASE
+        .iter()
+        .rposition(|&b| b as usize <= distance)
+        .unwrap();
+    writer.write_code(d as u32, 5);
+    writer.write_bits(
+        (distance - DISTANCE_BASE[d] as usize) as u32,
+        DISTANCE_EXTRA[d] as u32,
+    );
+}
+
+fn hash(data: &[u8], i: usize) -> usize {
+    let v = (data[i] as usize) << 16 | (data[i + 1] as usize) << 8 | data[i + 2] as usize;
+    (v.wrapping_mul(2654435761) >> (32 - HASH_BITS)) & ((1 << HASH_BITS) - 1)
+}
+
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // A single final block with fixed Huffman codes.
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let insert = |head: &mut Vec<usize>, prev: &mut Vec<usize>, i: usize| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(data, i);
+            prev[i % WINDOW_SIZE] = head[h];
+            head[h] = i;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+
+        if i + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(data, i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[i..i + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = i - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+                let next = prev[candidate % WINDOW_SIZE];
+                // Entries older than the window have been overwritten.
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            write_match(&mut writer, best_length, best_distance);
+            for j in i..i + best_length {
+                insert(&mut head, &mut prev, j);
+            }
+            i += best_length;
+        } else {
+            write_literal(&mut writer, data[i] as u32);
+            insert(&mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+
+    write_literal(&mut writer, 256);
+    writer.finish()
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // 5552 is the largest block for which b can't overflow before the modulo.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window, default compression level.
+    let mut stream = vec![0x78, 0x9c];
+    stream.extend(deflate(data));
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+// Reads bits least significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u64,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            position: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn read_bits(&mut self, bits: u32) -> Result<u32, String> {
+        while self.count < bits {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or_else(|| "unexpected end of compressed data".to_string())?;
+            self.buffer |= (byte as u64) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+        let value = (self.buffer & ((1u64 << bits) - 1)) as u32;
+        self.buffer >>= bits;
+        self.count -= bits;
+        Ok(value)
+    }
+
+    // Stored blocks start at the next byte boundary.
+    fn align_to_byte(&mut self) {
+        let skip = self.count % 8;
+        self.buffer >>= skip;
+        self.count -= skip;
+    }
+}
+
+// Canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    // Number of codes of each length.
+    counts: [u16; 16],
+    // Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for i in 1..16 {
+            offsets[i] = offsets[i - 1] + counts[i - 1];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..16 {
+            code |= reader.read_bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+    let literal_count = reader.read_bits(5)? as usize + 257;
+    let distance_count = reader.read_bits(5)? as usize + 1;
+    let code_length_count = reader.read_bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in ORDER.iter().take(code_length_count) {
+        code_lengths[i] = reader.read_bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| "repeated code length without a previous one".to_string())?;
+                (previous, 3 + reader.read_bits(2)?)
+            }
+            17 => (0, 3 + reader.read_bits(3)?),
+            _ => (0, 11 + reader.read_bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err("code lengths exceed the declared count".to_string());
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    out: &mut Vec<u8>,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let l = symbol - 257;
+                let length =
+                    LENGTH_BASE[l] as usize + reader.read_bits(LENGTH_EXTRA[l] as u32)? as usize;
+                let d = distances.decode(reader)? as usize;
+                if d >= 30 {
+                    return Err("invalid distance code".to_string());
+                }
+                let distance = DISTANCE_BASE[d] as usize
+                    + reader.read_bits(DISTANCE_EXTRA[d] as u32)? as usize;
+                if distance > out.len() {
+                    return Err("distance reaches before the start of the data".to_string());
+                }
+                // Copied byte by byte, the source may overlap what is written.
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+            _ => return Err("invalid literal/length code".to_string()),
+        }
+    }
+}
+
+pub fn decompress(stream: &[u8]) -> Result<Vec<u8>, String> {
+    if stream.len() < 6 {
+        return Err("zlib stream too short".to_string());
+    }
+    let (cmf, flg) = (stream[0], stream[1]);
+    if cmf & 0x0f != 8 || !((cmf as u16) << 8 | flg as u16).is_multiple_of(31) {
+        return Err("invalid zlib header".to_string());
+    }
+    if flg & 0x20 != 0 {
+        return Err("preset dictionaries are not supported".to_string());
+    }
+
+    let mut reader = BitReader::new(&stream[2..]);
+    let mut out = Vec::new();
+    loop {
+        let last = reader.read_bits(1)? == 1;
+        match reader.read_bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let length = reader.read_bits(16)?;
+                let complement = reader.read_bits(16)?;
+                if length != !complement & 0xffff {
+                    return Err("corrupt stored block length".to_string());
+                }
+                for _ in 0..length {
+                    out.push(reader.read_bits(8)? as u8);
+                }
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut reader, &literals, &distances, &mut out)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &literals, &distances, &mut out)?;
+            }
+            _ => return Err("invalid block type".to_string()),
+        }
+        if last {
+            break;
+        }
+    }
+
+    // The Adler-32 checksum follows at the next byte boundary.
+    reader.align_to_byte();
+    let mut checksum = 0u32;
+    for _ in 0..4 {
+        checksum = checksum << 8 | reader.read_bits(8)?;
+    }
+    if checksum != adler32(&out) {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // A xorshift generator, so incompressible data is the same on every run.
+    fn noise(length: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491u32;
+        (0..length)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let repetitive: Vec<u8> = b"abcabcabd".iter().cycle().take(100_000).copied().collect();
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"hello hello hello".to_vec(),
+            repetitive,
+            all_bytes,
+            noise(70_000),
+        ];
+        for input in inputs.iter() {
+            assert_eq!(&decompress(&compress(input)).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn compresses_repetitive_data() {
+        let data = vec![7u8; 100_000];
+        assert!(compress(&data).len() < 1000);
+    }
+
+    #[test]
+    fn stored_block() {
+        // zlib.compress(b"hello hello hello stored block", 0)
+        let stream = from_hex(
+            "7801011e00e1ff68656c6c6f2068656c6c6f2068656c6c6f2073746f726564\
+             20626c6f636baf9b0b59",
+        );
+        assert_eq!(
+            decompress(&stream).unwrap(),
+            b"hello hello hello stored block"
+        );
+    }
+
+    #[test]
+    fn dynamic_block() {
+        // zlib.compress(text, 9), which picks dynamic Huffman codes.
+        let stream = from_hex(
+            "78da9d8cc90dc3300c045bd93afcce236dd0d2d23222918e0e18ea3e4a4ac83c\
+             67b0fba066e9c4686c88d3a49c01cfa15ac4103c2e7b271a7a22da2cbb6768e5\
+             7bd0c2b99ad4a55fbc1941f3712474c72513eaf5b7f95ea0cb9eb9817ff1011d\
+             7e3cb2",
+        );
+        assert_eq!((stream[2] >> 1) & 3, 2);
+        let mut text = b"Deflate uses dynamic Huffman codes when the symbol \
+                         frequencies are skewed enough to pay for the code \
+                         table: "
+            .to_vec();
+        text.extend_from_slice(&[b'e'; 54]);
+        assert_eq!(decompress(&stream).unwrap(), text);
+    }
+
+    #[test]
+    fn corrupt_checksum() {
+        let mut stream = compress(b"checksum");
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+        assert!(decompress(&stream).is_err());
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let stream = compress(&noise(1000));
+        assert!(decompress(&stream[..stream.len() / 2]).is_err());
+    }
+}